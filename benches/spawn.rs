@@ -0,0 +1,98 @@
+use void_orchestrator::specification::Environment;
+use void_orchestrator::{Entrypoint, Orchestrator, Specification};
+
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use object::{Object, ObjectSection};
+
+/// A trivial dynamically linked binary, so each void mounts its libraries
+const BINARY: &str = "/bin/true";
+
+/**
+ * The interpreter and libraries the binary loads. The interpreter is read
+ * from the ELF and lists the libraries itself, so no architecture's paths
+ * are assumed.
+ */
+fn libraries() -> Vec<PathBuf> {
+    let data = fs::read(BINARY).unwrap();
+    let elf = object::File::parse(data.as_slice()).unwrap();
+    let interp = elf
+        .section_by_name(".interp")
+        .expect("the binary is dynamically linked")
+        .data()
+        .unwrap();
+    let interpreter = PathBuf::from(OsStr::from_bytes(
+        interp.split(|b| *b == 0).next().unwrap_or_default(),
+    ));
+
+    let output = Command::new(&interpreter)
+        .arg("--list")
+        .arg(BINARY)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} cannot list libraries",
+        interpreter
+    );
+
+    // each library is listed as `name => path (address)`
+    let mut libraries: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split(" => ").nth(1))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .map(PathBuf::from)
+        .collect();
+    libraries.push(interpreter);
+    libraries
+}
+
+fn mount(path: &Path) -> Environment {
+    Environment::Filesystem {
+        host_path: path.to_path_buf(),
+        environment_path: path.to_path_buf(),
+        noexec: false,
+        nosuid: false,
+        nodev: false,
+        readonly: false,
+    }
+}
+
+fn specification(libraries: &[PathBuf], procfs: bool) -> Specification {
+    let mut entrypoint = Entrypoint::default();
+    entrypoint
+        .environment
+        .extend(libraries.iter().map(|l| mount(l)));
+    if procfs {
+        entrypoint
+            .environment
+            .insert(Environment::Procfs { masked: true });
+    }
+
+    let mut spec = Specification::default();
+    spec.entrypoints.insert("true".to_string(), entrypoint);
+    spec
+}
+
+/// Spawn a single void running the binary and wait for it to exit
+fn run_void(libraries: &[PathBuf], procfs: bool) {
+    let result = Orchestrator::new(specification(libraries, procfs), Path::new(BINARY))
+        .run()
+        .unwrap();
+    assert_eq!(result.exit_code, exitcode::OK);
+}
+
+pub fn benchmark_spawn(c: &mut Criterion) {
+    let libraries = libraries();
+
+    c.bench_function("spawn", |b| b.iter(|| run_void(&libraries, false)));
+    c.bench_function("spawn+Procfs", |b| b.iter(|| run_void(&libraries, true)));
+}
+
+criterion_group!(benches, benchmark_spawn);
+criterion_main!(benches);
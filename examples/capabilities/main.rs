@@ -0,0 +1,11 @@
+use std::fs;
+
+fn main() {
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    let bounding = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapBnd:"))
+        .unwrap();
+
+    println!("{}", bounding.trim());
+}
@@ -0,0 +1,6 @@
+fn main() {
+    let entrypoint = std::env::args().nth(1).unwrap();
+
+    println!("{} crashing", entrypoint);
+    std::process::exit(3);
+}
@@ -0,0 +1,15 @@
+use std::thread;
+use std::time::Duration;
+
+fn main() {
+    let entrypoint = std::env::args().nth(1).unwrap();
+
+    match entrypoint.as_str() {
+        // holds the shim's stdout open for as long as it runs
+        "cache" => thread::sleep(Duration::from_secs(60)),
+        // exits without writing to its Ready fd
+        "database" => println!("database exiting before it is ready"),
+        "web" => println!("web spawned"),
+        e => panic!("unknown entrypoint: {}", e),
+    }
+}
@@ -0,0 +1,19 @@
+use std::fs;
+
+fn main() {
+    let mut buf = [0_u8; 64];
+    let hostname = nix::unistd::gethostname(&mut buf).unwrap();
+    let root: Vec<String> = fs::read_dir("/")
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+
+    println!(
+        "pid {} uid {} gid {} hostname {} root {}",
+        std::process::id(),
+        nix::unistd::getuid(),
+        nix::unistd::getgid(),
+        hostname.to_string_lossy(),
+        root.len()
+    );
+}
@@ -0,0 +1,9 @@
+use std::process::Command;
+
+fn main() {
+    // true is mounted from the host with noexec, so fails before it is loaded
+    match Command::new("/noexec/true").status() {
+        Ok(status) => println!("exec succeeded: {}", status),
+        Err(e) => println!("exec errno {}", e.raw_os_error().unwrap_or(0)),
+    }
+}
@@ -0,0 +1,15 @@
+/// Far more than the 16MiB memory_max of the specification
+const ALLOCATE: usize = 256 << 20;
+const CHUNK: usize = 1 << 20;
+
+fn main() {
+    println!("allocating {} bytes", ALLOCATE);
+
+    // touch every page, as untouched pages are never charged to the cgroup
+    let chunks: Vec<Vec<u8>> = (0..ALLOCATE / CHUNK).map(|_| vec![1; CHUNK]).collect();
+
+    println!(
+        "allocated {} bytes",
+        chunks.iter().map(Vec::len).sum::<usize>()
+    );
+}
@@ -0,0 +1,6 @@
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let (entrypoint, instance, pid) = (&args[1], &args[2], &args[3]);
+
+    println!("instance {} of {} has pid {}", instance, entrypoint, pid);
+}
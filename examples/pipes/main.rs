@@ -34,6 +34,11 @@ fn pipe_sender(mut tx_pipe: File) {
     let data = b"some more data";
     let bytes_written = tx_pipe.write(&data[..]).unwrap();
     assert!(bytes_written == data.len());
+
+    // a message filling a whole packet is still delivered in one piece
+    let data = b"0123456789abcdef".repeat(256);
+    let bytes_written = tx_pipe.write(&data[..]).unwrap();
+    assert!(bytes_written == data.len());
 }
 
 fn pipe_receiver(rx_data: &str) {
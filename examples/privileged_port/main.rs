@@ -0,0 +1,13 @@
+use std::net::TcpListener;
+use std::os::unix::io::FromRawFd;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let fd = args[1]
+        .parse()
+        .expect("listener should be a file descriptor");
+
+    // SAFETY: the shim passes an open listener at this fd
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    println!("listening on {}", listener.local_addr().unwrap());
+}
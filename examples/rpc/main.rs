@@ -0,0 +1,32 @@
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixDatagram;
+
+// RpcKind::OpenTcpSocket
+const OPEN_TCP_SOCKET: u32 = 0;
+
+fn main() {
+    let mut args = std::env::args();
+
+    let _bin = args.next();
+    let fd: i32 = args.next().unwrap().parse().unwrap();
+
+    // a seqpacket socket keeps message boundaries the same as a datagram socket
+    let rpc = unsafe { UnixDatagram::from_raw_fd(fd) };
+
+    // ask for a port the specification does not permit
+    let mut request = Vec::new();
+    request.extend(OPEN_TCP_SOCKET.to_ne_bytes());
+    request.extend(libc::AF_INET.to_ne_bytes());
+    request.extend(1_u16.to_ne_bytes());
+    request.extend(b"127.0.0.1\0");
+    rpc.send(&request).unwrap();
+
+    // the response is a u32 tag followed by an i32 errno
+    let mut response = [0_u8; 8];
+    let bytes_read = rpc.recv(&mut response).unwrap();
+    assert!(bytes_read == response.len());
+
+    let tag = u32::from_ne_bytes(response[0..4].try_into().unwrap());
+    let errno = i32::from_ne_bytes(response[4..8].try_into().unwrap());
+    println!("rpc result: tag {} errno {}", tag, errno);
+}
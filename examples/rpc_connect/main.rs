@@ -0,0 +1,69 @@
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixDatagram;
+
+// RpcKind::OpenTcpSocket
+const OPEN_TCP_SOCKET: u32 = 0;
+// RpcKind::OpenUnixSocket
+const OPEN_UNIX_SOCKET: u32 = 2;
+// RpcKind::OpenFile
+const OPEN_FILE: u32 = 3;
+
+fn main() {
+    let mut args = std::env::args();
+
+    let _bin = args.next();
+    let fd: i32 = args.next().unwrap().parse().unwrap();
+
+    let mut request = Vec::new();
+    match args.next().unwrap().as_str() {
+        "unix" => {
+            let path = args.next().unwrap();
+
+            request.extend(OPEN_UNIX_SOCKET.to_ne_bytes());
+            request.extend(path.as_bytes());
+        }
+        "file" => {
+            let write: u32 = match args.next().unwrap().as_str() {
+                "read" => 0,
+                "write" => 1,
+                m => panic!("unknown mode: {}", m),
+            };
+            let path = args.next().unwrap();
+
+            request.extend(OPEN_FILE.to_ne_bytes());
+            request.extend(write.to_ne_bytes());
+            request.extend(path.as_bytes());
+        }
+        family => {
+            let family = match family {
+                "inet" => libc::AF_INET,
+                "inet6" => libc::AF_INET6,
+                f => panic!("unknown family: {}", f),
+            };
+            let host = args.next().unwrap();
+            let port: u16 = args.next().unwrap().parse().unwrap();
+
+            request.extend(OPEN_TCP_SOCKET.to_ne_bytes());
+            request.extend(family.to_ne_bytes());
+            request.extend(port.to_ne_bytes());
+            request.extend(host.as_bytes());
+        }
+    }
+    request.push(0);
+
+    // a seqpacket socket keeps message boundaries the same as a datagram socket
+    let rpc = unsafe { UnixDatagram::from_raw_fd(fd) };
+    rpc.send(&request).unwrap();
+
+    // a connected socket or opened file arrives in a message of its own before the response
+    let mut response = [0_u8; 8];
+    let mut bytes_read = rpc.recv(&mut response).unwrap();
+    if bytes_read == 0 {
+        bytes_read = rpc.recv(&mut response).unwrap();
+    }
+    assert!(bytes_read == response.len());
+
+    let tag = u32::from_ne_bytes(response[0..4].try_into().unwrap());
+    let errno = i32::from_ne_bytes(response[4..8].try_into().unwrap());
+    println!("rpc result: tag {} errno {}", tag, errno);
+}
@@ -0,0 +1,7 @@
+fn main() {
+    println!("calling getppid");
+
+    // getppid(2) is left out of the allowlist, so the void is killed here
+    let ppid = unsafe { libc::getppid() };
+    println!("parent is {}", ppid);
+}
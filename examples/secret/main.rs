@@ -0,0 +1,22 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+
+fn main() {
+    let fd: RawFd = std::env::args()
+        .nth(1)
+        .expect("secret required")
+        .parse()
+        .expect("secret should be a file descriptor");
+    let mut secret = unsafe { File::from_raw_fd(fd) };
+
+    let mut contents = String::new();
+    secret.read_to_string(&mut contents).unwrap();
+    print!("read {}", contents);
+
+    // the memfd is sealed, so it cannot be changed from within the void
+    match secret.write_all(b"overwritten") {
+        Ok(()) => println!("write succeeded"),
+        Err(e) => println!("write failed with errno {}", e.raw_os_error().unwrap()),
+    }
+}
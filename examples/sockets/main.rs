@@ -0,0 +1,58 @@
+use std::fs::File;
+use std::io::{IoSlice, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+use nix::unistd::pipe;
+
+fn main() {
+    let mut args = std::env::args();
+
+    let _bin = args.next();
+    let entrypoint = args.next().unwrap();
+
+    match entrypoint.as_str() {
+        "sender" => {
+            let socket: RawFd = args.next().unwrap().parse().unwrap();
+            sender(socket)
+        }
+        "handler" => {
+            let read: RawFd = args.next().unwrap().parse().unwrap();
+            let write: RawFd = args.next().unwrap().parse().unwrap();
+            handler(read, write)
+        }
+        _ => unimplemented!(),
+    }
+}
+
+/**
+ * Send two connections, each with one end of two pipes. A handler can only
+ * finish once the other has written to it, so both must run at once.
+ */
+fn sender(socket: RawFd) {
+    let (first_read, first_write) = pipe().unwrap();
+    let (second_read, second_write) = pipe().unwrap();
+
+    for fds in [[first_read, second_write], [second_read, first_write]] {
+        sendmsg::<()>(
+            socket,
+            &[IoSlice::new(b"connection")],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )
+        .unwrap();
+    }
+}
+
+fn handler(read: RawFd, write: RawFd) {
+    let mut read = unsafe { File::from_raw_fd(read) };
+    let mut write = unsafe { File::from_raw_fd(write) };
+
+    write.write_all(b"x").unwrap();
+
+    let mut buf = [0_u8; 1];
+    read.read_exact(&mut buf).unwrap();
+
+    println!("handled connection on fd {}", read.as_raw_fd());
+}
@@ -0,0 +1,8 @@
+use nix::time::{clock_gettime, ClockId};
+
+fn main() {
+    let monotonic = clock_gettime(ClockId::CLOCK_MONOTONIC).unwrap();
+    let boottime = clock_gettime(ClockId::CLOCK_BOOTTIME).unwrap();
+
+    println!("{} {}", monotonic.tv_sec(), boottime.tv_sec());
+}
@@ -0,0 +1,10 @@
+use std::fs;
+
+fn main() {
+    let entrypoint = std::env::args().nth(1).unwrap();
+
+    let uid_map = fs::read_to_string("/proc/self/uid_map").unwrap();
+    let first: Vec<&str> = uid_map.lines().next().unwrap().split_whitespace().collect();
+
+    println!("{} maps {}", entrypoint, first.join(" "));
+}
@@ -0,0 +1,41 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+const SERVER_ADDR: &str = "10.0.0.2:8000";
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    match args[1].as_str() {
+        "server" => server(),
+        "client" => client(),
+        _ => unimplemented!(),
+    }
+}
+
+// the link appears once both ends exist, so each side retries until it does
+fn retry<T>(f: impl Fn() -> std::io::Result<T>) -> T {
+    for _ in 0..50 {
+        if let Ok(t) = f() {
+            return t;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("link never came up");
+}
+
+fn server() {
+    let listener = retry(|| TcpListener::bind(SERVER_ADDR));
+    let (mut stream, addr) = listener.accept().unwrap();
+
+    let mut msg = String::new();
+    stream.read_to_string(&mut msg).unwrap();
+    println!("received {} from {}", msg, addr.ip());
+}
+
+fn client() {
+    let mut stream = retry(|| TcpStream::connect(SERVER_ADDR));
+    stream.write_all(b"hello").unwrap();
+}
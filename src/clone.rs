@@ -1,12 +1,22 @@
+use log::warn;
+
 use std::fs::File;
 use std::marker::PhantomData;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use libc::{pid_t, syscall, SYS_clone3};
+use libc::{c_ulong, pid_t, syscall, SYS_clone, SYS_clone3};
 use nix::errno::Errno;
+use nix::fcntl::{openat, OFlag};
 pub use nix::sched::CloneFlags;
 use nix::sys::signal::Signal;
-use nix::unistd::Pid;
+use nix::sys::stat::Mode;
+use nix::unistd::{self, pipe2, Pid};
+
+const CLONE_INTO_CGROUP: u64 = 0x200000000;
+
+/// Set once clone3 has failed with ENOSYS, after which clone is used directly
+static CLONE3_MISSING: AtomicBool = AtomicBool::new(false);
 
 pub struct CloneArgs<'a> {
     pub flags: CloneFlags,
@@ -16,7 +26,7 @@ pub struct CloneArgs<'a> {
     pub exit_signal: Option<Signal>,
     pub stack: Option<&'a mut [u8]>,
     pub set_tid: Option<&'a [Pid]>,
-    pub cgroup: Option<&'a File>,
+    pub cgroup: Option<RawFd>,
 }
 
 #[repr(C)]
@@ -59,9 +69,29 @@ impl<'a: 'b, 'b: 'c, 'c> CloneArgs<'a> {
         pidfd: &mut RawFd,
         child_tid: &mut pid_t,
         parent_tid: &mut pid_t,
+        set_tid: &mut Vec<pid_t>,
     ) -> CloneArgsFfi<'c> {
+        // Pid is not guaranteed to share the layout of pid_t, so copy them out
+        if let Some(tids) = self.set_tid {
+            set_tid.extend(tids.iter().map(|t| t.as_raw()));
+        }
+
+        // CLONE_PIDFD is not a nix CloneFlag, so request it with the pidfd
+        let pidfd_flag = if self.pidfd.is_some() {
+            libc::CLONE_PIDFD as u64
+        } else {
+            0
+        };
+
+        // nor is CLONE_INTO_CGROUP, which needs the full 64 bits of clone3
+        let cgroup_flag = if self.cgroup.is_some() {
+            CLONE_INTO_CGROUP
+        } else {
+            0
+        };
+
         CloneArgsFfi {
-            flags: self.flags.bits() as u64,
+            flags: self.flags.bits() as u64 | pidfd_flag | cgroup_flag,
             pidfd: self
                 .pidfd
                 .as_ref()
@@ -88,10 +118,10 @@ impl<'a: 'b, 'b: 'c, 'c> CloneArgs<'a> {
             set_tid: self
                 .set_tid
                 .as_ref()
-                .map(|s| s.as_ptr() as u64)
+                .map(|_| set_tid.as_ptr() as u64)
                 .unwrap_or(0),
-            set_tid_size: self.set_tid.as_ref().map(|s| s.len() as u64).unwrap_or(0),
-            cgroup: self.cgroup.map(|c| c.as_raw_fd() as u64).unwrap_or(0),
+            set_tid_size: set_tid.len() as u64,
+            cgroup: self.cgroup.map(|c| c as u64).unwrap_or(0),
 
             phantom: PhantomData,
         }
@@ -111,11 +141,37 @@ impl<'a: 'b, 'b: 'c, 'c> CloneArgs<'a> {
 }
 
 pub fn clone3(mut args: CloneArgs) -> nix::Result<Pid> {
+    clone3_with(&mut args)
+}
+
+/**
+ * Clone with clone3, falling back to the original clone syscall if clone3
+ * is missing. Kernels before 5.3 lack it, and container runtimes often deny
+ * it with ENOSYS so that callers fall back. The fallback creates the same
+ * namespaces, but cannot request pids.
+ */
+pub fn clone(mut args: CloneArgs) -> nix::Result<Pid> {
+    if !CLONE3_MISSING.load(Ordering::Relaxed) {
+        match clone3_with(&mut args) {
+            Err(Errno::ENOSYS) => {
+                warn!("clone3 is unavailable, falling back to clone");
+                CLONE3_MISSING.store(true, Ordering::Relaxed);
+            }
+            result => return result,
+        }
+    }
+
+    clone_legacy(&mut args)
+}
+
+fn clone3_with(args: &mut CloneArgs) -> nix::Result<Pid> {
     let mut pidfd: RawFd = 0;
     let mut child_tid: pid_t = 0;
     let mut parent_tid: pid_t = 0;
+    let mut set_tid: Vec<pid_t> = Vec::new();
 
-    let args_ffi: CloneArgsFfi = args.process(&mut pidfd, &mut child_tid, &mut parent_tid);
+    let args_ffi: CloneArgsFfi =
+        args.process(&mut pidfd, &mut child_tid, &mut parent_tid, &mut set_tid);
     let result = unsafe { syscall(SYS_clone3, &args_ffi, std::mem::size_of_val(&args_ffi)) };
 
     let out = Errno::result(result).map(|p| Pid::from_raw(p as i32))?;
@@ -127,3 +183,89 @@ pub fn clone3(mut args: CloneArgs) -> nix::Result<Pid> {
 
     Ok(out)
 }
+
+/**
+ * Clone with the original clone syscall, which takes the same namespace
+ * flags in a narrower word. Without CLONE_INTO_CGROUP, the parent moves the
+ * child into its cgroup while the child waits on a pipe, so the child never
+ * runs outside of it. Anything else only clone3 supports fails with ENOSYS.
+ */
+fn clone_legacy(args: &mut CloneArgs) -> nix::Result<Pid> {
+    if args.set_tid.is_some()
+        || args.stack.is_some()
+        || args.child_tid.is_some()
+        || args.parent_tid.is_some()
+    {
+        return Err(Errno::ENOSYS);
+    }
+
+    let moved = match args.cgroup {
+        Some(_) => {
+            let (read, write) = pipe2(OFlag::O_CLOEXEC)?;
+            // SAFETY: valid new fds as pipe2(2) returned successfully
+            Some(unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) })
+        }
+        None => None,
+    };
+
+    let pidfd_flag = if args.pidfd.is_some() {
+        libc::CLONE_PIDFD as c_ulong
+    } else {
+        0
+    };
+    let exit_signal = args.exit_signal.map(|s| s as i32 as c_ulong).unwrap_or(0);
+
+    // the pidfd is written where the parent's tid would be
+    let mut pidfd: RawFd = 0;
+    let pidfd_ptr = match args.pidfd {
+        Some(_) => &mut pidfd as *mut RawFd,
+        None => std::ptr::null_mut(),
+    };
+
+    // SAFETY: without a new stack the child continues on a copy of this one, as with fork
+    let result = unsafe {
+        syscall(
+            SYS_clone,
+            args.flags.bits() as c_ulong | pidfd_flag | exit_signal,
+            std::ptr::null_mut::<u8>(),
+            pidfd_ptr,
+            std::ptr::null_mut::<pid_t>(),
+            0 as c_ulong,
+        )
+    };
+    let child = Errno::result(result).map(|p| Pid::from_raw(p as i32))?;
+
+    if let (Some((read, write)), Some(cgroup)) = (moved, args.cgroup) {
+        if child == Pid::from_raw(0) {
+            drop(write);
+
+            // the parent closes the pipe without writing if it failed to move the child
+            let mut buf = [0_u8; 1];
+            if !matches!(unistd::read(read.as_raw_fd(), &mut buf), Ok(1)) {
+                // SAFETY: exits the child without unwinding into the parent's state
+                unsafe { libc::_exit(exitcode::OSERR) };
+            }
+        } else {
+            drop(read);
+
+            let procs = openat(
+                cgroup,
+                "cgroup.procs",
+                OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            )?;
+            // SAFETY: valid new fd as openat(2) returned successfully
+            let procs = unsafe { File::from_raw_fd(procs) };
+
+            unistd::write(procs.as_raw_fd(), child.to_string().as_bytes())?;
+            unistd::write(write.as_raw_fd(), &[1])?;
+        }
+    }
+
+    // SAFETY: the requested pidfd has been filled by the kernel so is now valid
+    unsafe {
+        args.finalise(pidfd, 0, 0);
+    }
+
+    Ok(child)
+}
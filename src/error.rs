@@ -1,5 +1,9 @@
 use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
 
+use nix::unistd::Pid;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -15,15 +19,188 @@ pub enum Error {
     #[error("json: {0}")]
     Json(#[from] serde_json::Error),
 
+    #[error("elf read: {0}")]
+    ElfRead(#[from] object::Error),
+
+    #[error("seccomp: {0}")]
+    Seccomp(#[from] seccompiler::Error),
+
+    #[error("{0}")]
+    Caps(#[from] caps::errors::CapsError),
+
     #[error("bad pipe specification: a pipe must have exactly one reader and one writer: {0}")]
     BadPipe(String),
 
-    #[error("bad socket specification: a socket must have exactly one reader and one or more writers: {0}")]
+    #[error("bad pipe capacity: {capacity} bytes was rejected for pipe `{name}`, which may be at most {max} bytes: {src}")]
+    BadPipeCapacity {
+        name: String,
+        capacity: usize,
+        max: String,
+        src: nix::Error,
+    },
+
+    #[error("bad socket specification: a socket must have exactly one reader and one or more writers, unless it has an abstract address: {0}")]
     BadFileSocket(String),
 
+    #[error("bad abstract address: must be 1 to 107 bytes without a nul, for a socket triggering an entrypoint: {0}")]
+    BadAbstractAddress(String),
+
+    #[error("bad specification: {}", join(.0))]
+    BadSpecification(Vec<Error>),
+
+    #[error(
+        "unsupported specification version: this shim only understands version {}: {0}",
+        crate::specification::SPEC_VERSION
+    )]
+    UnsupportedSpecVersion(String),
+
     #[error("bad specification type: only .json files are supported")]
     BadSpecType,
 
+    #[error("missing trailing argument: the binary was launched with too few arguments for a TrailingAt that is not optional: {0}")]
+    MissingTrailingArg(usize),
+
+    #[error("binary not found: the binary to launch must exist: {0:?}")]
+    BinaryNotFound(PathBuf),
+
+    #[error("no specification: pass --specification, set $VOID_SPEC or embed one in the binary")]
+    NoSpecification,
+
+    #[error("bad substitution: the binary was not given enough arguments for {0}")]
+    BadSubstitution(String),
+
+    #[error("bad file specification: a file can only be created if it is writable: {0:?}")]
+    BadFile(PathBuf),
+
+    #[error("bad root limits: the size and inode limits of the root must be non-zero: {0}")]
+    BadRootLimits(String),
+
+    #[error("bad workdir: the working directory must be absolute and exist in the void: {0:?}")]
+    BadWorkdir(PathBuf),
+
+    #[error("bad rpc specification: hosts must be an address or hostname, ports non-zero and paths absolute: {0}")]
+    BadRpcSpecification(String),
+
+    #[error("bad entrypoint: no entrypoint with this name exists: {0}")]
+    BadEntrypoint(String),
+
+    #[error("bad restart policy: only startup entrypoints without pipe or socket readers can restart, and only they can limit restarts, with a non-zero backoff: {0}")]
+    BadRestartPolicy(String),
+
+    #[error("bad oneshot: only startup entrypoints without pipe or socket readers or a restart policy can be oneshot: {0}")]
+    BadOneshot(String),
+
+    #[error("oneshot entrypoint failed, aborting startup: {0}")]
+    OneshotFailed(String),
+
+    #[error("bad instances: there must be at least one instance, and multiple only for startup entrypoints that are not oneshot or restarted: {0}")]
+    BadInstances(String),
+
+    #[error("bad dependency: dependencies must be oneshots or startup entrypoints taking a Ready argument, and oneshot or signal triggered entrypoints cannot depend on others: {0}")]
+    BadDependency(String),
+
+    #[error("bad ready argument: only entrypoints spawned at startup can signal they are ready")]
+    BadReadyArgument,
+
+    #[error("dependency cycle: entrypoints cannot depend on each other in a cycle: {0}")]
+    DependencyCycle(String),
+
+    #[error("dependency not ready: the entrypoint exited before it was ready: {0}")]
+    NotReady(String),
+
+    #[error("bad max file descriptors: must be between 1 and 253, the most one socket message can carry: {0}")]
+    BadMaxFileDescriptors(String),
+
+    #[error("truncated file descriptors: a socket message carried more than the entrypoint's max_file_descriptors: {0}")]
+    TruncatedFileDescriptors(String),
+
+    #[error("truncated message: a socket message carried more data than the {} bytes the shim reads at once: {0}", libc::PIPE_BUF)]
+    TruncatedMessage(String),
+
+    #[error("bad interval: the period must be non-zero: {0}")]
+    BadInterval(String),
+
+    #[error("bad namespaces: only the network namespace can be named, a host or domain name cannot be set in a shared uts namespace, and a shared user namespace cannot map ids or join a named network namespace: {0}")]
+    BadNamespaces(String),
+
+    #[error(
+        "bad name template: braces may only form the {{entrypoint}} and {{pid}} placeholders: {0}"
+    )]
+    BadNameTemplate(String),
+
+    #[error("bad signal trigger: the signal must exist and not be reserved by the shim, and the entrypoint must not take pipes or socket readers: {0}")]
+    BadSignal(String),
+
+    #[error("bad literal argument: arguments cannot contain a nul byte: {0:?}")]
+    BadLiteral(String),
+
     #[error("bad trigger argument: this entrypoint is not triggered by something with arguments")]
     BadTriggerArgument,
+
+    #[error("bad fixed fd: must wrap an fd argument, avoid stdio and be unique: {0}")]
+    BadFixedFd(RawFd),
+
+    #[error("bad inherited fd: must be open in the shim, avoid stdio and not be the target of a fixed fd: {0}")]
+    BadInheritFd(RawFd),
+
+    #[error("bad pid: a pid must be positive and only requested by a single instance startup or signal triggered entrypoint: {0}")]
+    BadPid(String),
+
+    #[error("bad limits: memory_max must be non-zero, cpu_max at least 0.01 CPUs and the entrypoint's name free of '/': {0}")]
+    BadLimits(String),
+
+    #[error("bad trigger list: a list of triggers must hold at least one pipe, file socket or interval trigger and nothing else: {0}")]
+    BadTriggerList(String),
+
+    #[error("bad timeout: timeout_ms must be non-zero and is only supported for startup entrypoints: {0}")]
+    BadTimeout(String),
+
+    #[error("bad network: an entrypoint may declare one network, only in a voided network namespace, and a private one needs loopback and a private subnet with room for two hosts: {0}")]
+    BadNetwork(String),
+
+    #[error("bad link: must join two different network namespaces named by entrypoints, addressed from a private IPv4 subnet with room for both ends: {0}")]
+    BadLink(String),
+
+    #[error("link unavailable: creating `{name}` needs CAP_SYS_ADMIN and CAP_NET_ADMIN in the shim's user namespace: {src}")]
+    LinkUnavailable { name: String, src: nix::Error },
+
+    #[error("cgroup unavailable: resource limits need the shim to run in a cgroup v2 at /sys/fs/cgroup delegated to it alone: {0}")]
+    CgroupUnavailable(String),
+
+    #[error("privileged port: binding {0} needs CAP_NET_BIND_SERVICE in the shim")]
+    PrivilegedPort(SocketAddr),
+
+    #[error("shared user namespace: only a shim running as root can share its user namespace with a void")]
+    SharedUserNamespace,
+
+    #[error("secret unavailable: {path:?} could not be read by the shim: {src}")]
+    SecretUnavailable { path: PathBuf, src: io::Error },
+
+    #[error("clone unavailable: requesting a pid needs clone3 from Linux 5.3 or later, allowed by any seccomp filter on the shim, and every void needs permission to create namespaces, which sysctls such as user.max_user_namespaces can deny: {0}")]
+    CloneUnavailable(nix::Error),
+
+    #[error("unavailable pid: {pid} is in use, out of range or needs CAP_SYS_ADMIN in the shim's pid namespace: {src}")]
+    UnavailablePid { pid: Pid, src: nix::Error },
+}
+
+/// A finding that does not stop a specification running, but fails it in
+/// strict mode.
+#[derive(Error, Debug)]
+pub enum Warning {
+    #[error("unused trigger: the trigger data is read but not passed to the entrypoint: {0}")]
+    UnusedTrigger(String),
+
+    #[error("broad mount: mounting the root or a whole top level directory: {0:?}")]
+    BroadMount(PathBuf),
+
+    #[error("missing interpreter: the binary is dynamically linked but {interpreter:?} is not mounted: {entrypoint}")]
+    MissingInterpreter {
+        entrypoint: String,
+        interpreter: PathBuf,
+    },
+}
+
+fn join(errors: &[Error]) -> String {
+    let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    errors.join("; ")
 }
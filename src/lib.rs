@@ -1,143 +1,1010 @@
-use log::{debug, info};
+use log::{debug, error, info, warn};
 
 pub mod clone;
 mod error;
 mod spawner;
-mod specification;
+pub mod specification;
 mod void;
 
-use error::{Error, Result};
-use spawner::Spawner;
-use specification::{Environment, Specification};
+pub use error::{Error, Result, Warning};
+use spawner::{BoundSockets, Spawner};
+pub use specification::{Arg, Entrypoint, Specification};
+use specification::{Environment, PipeOptions, SocketKind, SocketOptions};
+pub use void::CapturedOutput;
+use void::VoidHandle;
 
-use std::collections::HashMap;
-use std::fs::File;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::io;
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::Path;
+use std::time::{Duration, Instant};
 
-use nix::fcntl::OFlag;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{SigSet, SigmaskHow, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
 use nix::sys::socket;
 use nix::sys::wait::{waitid, Id, WaitPidFlag, WaitStatus};
 use nix::unistd;
 
+const SPEC_ENV_VAR: &str = "VOID_SPEC";
+
+const RESTART_BACKOFF_MIN: Duration = Duration::from_millis(100);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest pipe capacity an unprivileged process can set
+const PIPE_MAX_SIZE: &str = "/proc/sys/fs/pipe-max-size";
+
 pub struct RunArgs<'a> {
     pub spec: Option<&'a Path>,
     pub debug: bool,
     pub daemon: bool,
+    pub trace_builder: bool,
+    pub audit: bool,
+    pub strict: bool,
+    pub dry_run: bool,
+    pub exit_policy: ExitPolicy,
+    pub max_restarts: Option<u32>,
+    pub restart_backoff: Option<Duration>,
 
     pub stdout: bool,
     pub stderr: bool,
+    pub mounts: Vec<ExtraMount<'a>>,
 
     pub binary: &'a Path,
     pub binary_args: Vec<&'a str>,
 }
 
-pub fn run(args: &RunArgs) -> Result<i32> {
-    // parse the specification
-    let mut spec: Specification = if let Some(m) = args.spec {
-        if m.extension().map(|e| e == "json") == Some(true) {
-            let f = std::fs::File::open(m)?;
-            Ok(serde_json::from_reader(f)?)
-        } else {
-            Err(Error::BadSpecType)
+/// A bind mount given to every entrypoint on top of those in its specification
+#[derive(Clone, Copy, Debug)]
+pub struct ExtraMount<'a> {
+    pub host_path: &'a Path,
+    pub environment_path: &'a Path,
+    pub writable: bool,
+}
+
+/// How the shim's exit code is chosen when more than one void fails
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExitPolicy {
+    /// The code of the first void to fail
+    FirstFailure,
+    /// The code of the most recent void to fail
+    LastFailure,
+    /// The highest code of any void
+    MaxCode,
+}
+
+impl ExitPolicy {
+    /// The exit code after a void fails with code, given the current one
+    fn combine(self, current: i32, code: i32) -> i32 {
+        match self {
+            ExitPolicy::FirstFailure if current != exitcode::OK => current,
+            ExitPolicy::MaxCode => current.max(code),
+            _ => code,
         }
-    } else {
-        unimplemented!("reading spec from the elf is unimplemented")
-    }?;
+    }
+}
 
-    debug!("specification read: {:?}", &spec);
-    spec.validate()?;
+/// The outcome of running a specification
+pub struct RunResult {
+    /// The code the shim should exit with
+    pub exit_code: i32,
 
-    if args.stdout {
-        debug!("forwarding stdout");
-        for entrypoint in &mut spec.entrypoints.values_mut() {
-            entrypoint.environment.insert(Environment::Stdout);
+    /// Every void spawned, including restarts, with the entrypoint it runs
+    pub spawned: Vec<(unistd::Pid, String)>,
+
+    /// What each void wrote to stdout and stderr by pid, if output was captured
+    pub output: HashMap<unistd::Pid, CapturedOutput>,
+}
+
+impl RunResult {
+    fn new(exit_code: i32) -> RunResult {
+        RunResult {
+            exit_code,
+            spawned: Vec::new(),
+            output: HashMap::new(),
         }
     }
 
+    /// Keep the rest of a void's captured output, if any
+    fn collect_output(&mut self, void: &mut VoidHandle) {
+        match void.take_output() {
+            Ok(Some(output)) => {
+                self.output.insert(void.pid(), output);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("failed to read the output of {}: {}", void, e),
+        }
+    }
+}
+
+pub fn run(args: &RunArgs) -> Result<RunResult> {
+    // parse the specification
+    let spec = read_specification(args)?;
+    debug!("specification read: {:?}", &spec);
+
+    let mut orchestrator = Orchestrator::new(spec, args.binary);
+    orchestrator
+        .binary_args(args.binary_args.iter().copied())
+        .exit_policy(args.exit_policy);
+
+    if args.debug {
+        orchestrator.debug();
+    }
+    if args.daemon {
+        orchestrator.daemon();
+    }
+    if args.trace_builder {
+        orchestrator.trace_builder();
+    }
+    if args.audit {
+        orchestrator.audit();
+    }
+    if args.strict {
+        orchestrator.strict();
+    }
+    if args.dry_run {
+        orchestrator.dry_run();
+    }
+    if let Some(max) = args.max_restarts {
+        orchestrator.max_restarts(max);
+    }
+    if let Some(backoff) = args.restart_backoff {
+        orchestrator.restart_backoff(backoff);
+    }
+    if args.stdout {
+        orchestrator.stdout();
+    }
     if args.stderr {
-        debug!("forwarding stderr");
-        for entrypoint in &mut spec.entrypoints.values_mut() {
-            entrypoint.environment.insert(Environment::Stderr);
+        orchestrator.stderr();
+    }
+    for mount in &args.mounts {
+        orchestrator.mount(*mount);
+    }
+
+    orchestrator.run()
+}
+
+/// Runs a specification built in Rust rather than read from JSON, for
+/// embedding the shim in another program
+pub struct Orchestrator<'a> {
+    spec: Specification,
+    debug: bool,
+    daemon: bool,
+    trace_builder: bool,
+    audit: bool,
+    strict: bool,
+    dry_run: bool,
+    exit_policy: ExitPolicy,
+    max_restarts: Option<u32>,
+    restart_backoff: Duration,
+
+    stdout: bool,
+    stderr: bool,
+    stdout_file: Option<&'a Path>,
+    capture_output: bool,
+    mounts: Vec<ExtraMount<'a>>,
+
+    binary: &'a Path,
+    binary_args: Vec<&'a str>,
+}
+
+impl<'a> Orchestrator<'a> {
+    pub fn new(spec: Specification, binary: &'a Path) -> Orchestrator<'a> {
+        Orchestrator {
+            spec,
+            debug: false,
+            daemon: false,
+            trace_builder: false,
+            audit: false,
+            strict: false,
+            dry_run: false,
+            exit_policy: ExitPolicy::LastFailure,
+            max_restarts: None,
+            restart_backoff: RESTART_BACKOFF_MIN,
+            stdout: false,
+            stderr: false,
+            stdout_file: None,
+            capture_output: false,
+            mounts: Vec::new(),
+            binary,
+            binary_args: Vec::new(),
         }
     }
 
-    // create all the pipes
-    let (pipes, _) = spec.pipes();
-    let pipes = create_pipes(pipes)?;
+    /// Arguments given to the binary, which Trailing arguments pass on
+    pub fn binary_args(&mut self, args: impl IntoIterator<Item = &'a str>) -> &mut Self {
+        self.binary_args.extend(args);
+        self
+    }
 
-    let (sockets, _) = spec.sockets();
-    let sockets = create_sockets(sockets)?;
+    /// Stop each void before it executes its entrypoint, for a debugger to attach
+    pub fn debug(&mut self) -> &mut Self {
+        self.debug = true;
+        self
+    }
 
-    // spawn all processes
-    Spawner {
-        spec: &spec,
-        binary: args.binary,
-        binary_args: &args.binary_args,
-        debug: args.debug,
+    /// Return once every void is spawned rather than waiting on them
+    pub fn daemon(&mut self) -> &mut Self {
+        self.daemon = true;
+        self
+    }
 
-        pipes,
-        sockets,
+    /// Log each void's builder before it is spawned
+    pub fn trace_builder(&mut self) -> &mut Self {
+        self.trace_builder = true;
+        self
     }
-    .spawn()?;
 
-    if args.daemon {
-        return Ok(exitcode::OK);
+    /// Log the argv and mounts each void executes its entrypoint with
+    pub fn audit(&mut self) -> &mut Self {
+        self.audit = true;
+        self
+    }
+
+    /// Fail on specification warnings as well as errors
+    pub fn strict(&mut self) -> &mut Self {
+        self.strict = true;
+        self
     }
 
-    info!("spawned successfully, awaiting children exiting...");
-    let mut exit_code = exitcode::OK;
+    /// Print the plan for the specification without spawning anything
+    pub fn dry_run(&mut self) -> &mut Self {
+        self.dry_run = true;
+        self
+    }
 
-    loop {
-        let status = match waitid(Id::All, WaitPidFlag::WEXITED) {
-            Ok(v) => Ok(v),
-            Err(nix::Error::ECHILD) => {
+    /// Choose the exit code when more than one void fails
+    pub fn exit_policy(&mut self, policy: ExitPolicy) -> &mut Self {
+        self.exit_policy = policy;
+        self
+    }
+
+    /// Give up on an entrypoint after this many restarts in a row, unless it sets its own limit
+    pub fn max_restarts(&mut self, max: u32) -> &mut Self {
+        self.max_restarts = Some(max);
+        self
+    }
+
+    /// Delay the first restart in a row by this much, unless the entrypoint sets its own
+    pub fn restart_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.restart_backoff = backoff;
+        self
+    }
+
+    /// Pass the shim's stdout to every entrypoint
+    pub fn stdout(&mut self) -> &mut Self {
+        self.stdout = true;
+        self
+    }
+
+    /// Pass the shim's stderr to every entrypoint
+    pub fn stderr(&mut self) -> &mut Self {
+        self.stderr = true;
+        self
+    }
+
+    /// Append every entrypoint's stdout to a file in place of the shim's stdout
+    pub fn stdout_file(&mut self, path: &'a Path) -> &mut Self {
+        self.stdout_file = Some(path);
+        self
+    }
+
+    /// Collect the stdout and stderr of every void into the result rather than passing them on
+    pub fn capture_output(&mut self) -> &mut Self {
+        self.capture_output = true;
+        self
+    }
+
+    /// Bind mount a host path into every entrypoint, read-only unless writable
+    pub fn mount(&mut self, mount: ExtraMount<'a>) -> &mut Self {
+        self.mounts.push(mount);
+        self
+    }
+
+    /**
+     * Validate the specification and spawn its entrypoints, then wait on
+     * them unless running as a daemon. The shim receives SIGCHLD, SIGTERM,
+     * SIGINT and its trigger signals on the calling thread, so any other
+     * threads of the program must keep them blocked. The calling thread's
+     * signal mask is restored on return.
+     */
+    pub fn run(&mut self) -> Result<RunResult> {
+        let mut warnings = self.spec.validate()?;
+        warnings.extend(self.spec.validate_binary(self.binary)?);
+        self.spec.validate_trailing(self.binary_args.len())?;
+        self.spec.validate_inherited_fds()?;
+
+        if report_warnings(&warnings, self.strict) {
+            return Ok(RunResult::new(exitcode::DATAERR));
+        }
+
+        if self.stdout {
+            debug!("forwarding stdout");
+            for entrypoint in &mut self.spec.entrypoints.values_mut() {
+                entrypoint.environment.insert(Environment::Stdout);
+            }
+        }
+
+        if self.stderr {
+            debug!("forwarding stderr");
+            for entrypoint in &mut self.spec.entrypoints.values_mut() {
+                entrypoint.environment.insert(Environment::Stderr);
+            }
+        }
+
+        if let Some(path) = self.stdout_file {
+            debug!("appending stdout to {:?}", path);
+            for entrypoint in &mut self.spec.entrypoints.values_mut() {
+                entrypoint.environment.remove(&Environment::Stdout);
+                entrypoint
+                    .environment
+                    .insert(Environment::StdoutFile(path.to_path_buf()));
+            }
+        }
+
+        for mount in &self.mounts {
+            debug!("adding mount {:?}", mount);
+            for entrypoint in self.spec.entrypoints.values_mut() {
+                entrypoint.environment.insert(Environment::Filesystem {
+                    host_path: mount.host_path.into(),
+                    environment_path: mount.environment_path.into(),
+                    noexec: false,
+                    nosuid: false,
+                    nodev: false,
+                    readonly: !mount.writable,
+                });
+            }
+        }
+
+        let spec = &self.spec;
+        if self.dry_run {
+            let exit_code = if print_plan(spec)? {
+                exitcode::OK
+            } else {
+                exitcode::DATAERR
+            };
+            return Ok(RunResult::new(exit_code));
+        }
+
+        // create all the pipes
+        let (pipes, _) = spec.pipes();
+        let pipes = create_pipes(pipes, &spec.pipe_options)?;
+
+        let (sockets, _) = spec.sockets();
+        let sockets = create_sockets(sockets, &spec.socket_options)?;
+
+        let readiness = create_readiness_pipes(spec.readiness())?;
+
+        // block the signals the shim waits on before any void is spawned, so
+        // none are missed. voids reset their signal mask
+        let signal_triggers = spec.signal_triggers();
+        let (mut signals, _mask) = block_signals(signal_triggers.keys())?;
+
+        // the output of voids outliving the shim could never be read
+        if self.capture_output && self.daemon {
+            warn!("output is never captured in daemon mode");
+        }
+
+        // resolved once, as every void of a startup entrypoint mounts it
+        let canonical_binary = self.binary.canonicalize().map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::BinaryNotFound(self.binary.to_path_buf()),
+            _ => e.into(),
+        })?;
+
+        // spawn all processes
+        let mut spawner = Spawner {
+            spec,
+            binary: self.binary,
+            canonical_binary: &canonical_binary,
+            binary_args: &self.binary_args,
+            debug: self.debug,
+            trace_builder: self.trace_builder,
+            audit: self.audit,
+            capture_output: self.capture_output && !self.daemon,
+            cpus: spawner::effective_cpus(),
+
+            pipes,
+            sockets,
+            readiness,
+            network_namespaces: HashMap::new(),
+            cgroups: spawner::limit_entrypoints(spec)?,
+            bound_sockets: BoundSockets::default(),
+            secrets: spawner::read_secrets(spec)?,
+            mount_plans: RefCell::new(HashMap::new()),
+            helpers: RefCell::new(HashSet::new()),
+        };
+        let mut voids = spawner.spawn()?;
+
+        let mut result = RunResult::new(exitcode::OK);
+        result
+            .spawned
+            .extend(voids.iter().map(|(pid, (name, _))| (*pid, name.clone())));
+
+        if self.daemon {
+            // the voids stay in their cgroups once the shim exits
+            spawner.cgroups.persist();
+
+            if !signal_triggers.is_empty() {
+                warn!("signal triggered entrypoints are never spawned in daemon mode");
+            }
+            if spec.entrypoints.values().any(|e| e.timeout().is_some()) {
+                warn!("entrypoint timeouts are never enforced in daemon mode");
+            }
+            return Ok(result);
+        }
+
+        info!("spawned successfully, awaiting children exiting...");
+        let mut restarts: HashMap<String, Backoff> = HashMap::new();
+
+        // entrypoints waiting out their backoff, with when to restart them
+        let mut pending: Vec<(Instant, String)> = Vec::new();
+
+        // when each void of an entrypoint with a timeout is killed
+        let spawned_at = Instant::now();
+        let mut deadlines: HashMap<unistd::Pid, Instant> = voids
+            .iter()
+            .filter_map(|(pid, (name, _))| {
+                let timeout = spec.entrypoints[name].timeout()?;
+                Some((*pid, spawned_at + timeout))
+            })
+            .collect();
+
+        loop {
+            // reap every exited child before waiting on the next signal. only
+            // the shim's own children are waited on, as an embedding program
+            // may have others
+            let children: Vec<unistd::Pid> = voids
+                .keys()
+                .chain(spawner.helpers.borrow().iter())
+                .copied()
+                .collect();
+            for pid in children {
+                let status = match waitid(Id::Pid(pid), WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG)
+                {
+                    Ok(WaitStatus::StillAlive) => continue,
+                    Ok(v) => v,
+                    Err(e) => {
+                        return Err(Error::Nix {
+                            msg: "waitpid",
+                            src: e,
+                        })
+                    }
+                };
+
+                let mut void = voids.remove(&pid);
+                spawner.helpers.borrow_mut().remove(&pid);
+                deadlines.remove(&pid);
+                if let Some((_, void)) = &mut void {
+                    result.collect_output(void);
+                }
+                let entrypoint = void.as_ref().map_or_else(
+                    || "an rpc handler".to_string(),
+                    |(name, _)| format!("entrypoint `{}`", name),
+                );
+
+                let failed = match status {
+                    WaitStatus::Exited(_, code) => {
+                        if code != exitcode::OK {
+                            result.exit_code = self.exit_policy.combine(result.exit_code, code);
+                            warn!("child {} of {} exited with code {}", pid, entrypoint, code);
+                        } else {
+                            debug!("child {} of {} exited with code {}", pid, entrypoint, code);
+                        }
+                        code != exitcode::OK
+                    }
+                    WaitStatus::Signaled(_, sig, _coredump) => {
+                        // as a shell reports a process killed by a signal
+                        let code = 128 + sig as i32;
+                        result.exit_code = self.exit_policy.combine(result.exit_code, code);
+
+                        warn!(
+                            "child {} of {} was terminated with signal {}",
+                            pid, entrypoint, sig
+                        );
+                        true
+                    }
+                    _ => unreachable!(),
+                };
+
+                if let Some((name, _)) = void {
+                    let config = &spec.entrypoints[&name];
+                    if config.restart.should_restart(failed) {
+                        let backoff = restarts.entry(name.clone()).or_insert_with(|| {
+                            Backoff::new(
+                                config
+                                    .restart_backoff_ms
+                                    .map_or(self.restart_backoff, Duration::from_millis),
+                                config.max_restarts.or(self.max_restarts),
+                            )
+                        });
+
+                        let delay = match backoff.next_delay() {
+                            Some(delay) => delay,
+                            None => {
+                                error!(
+                                    "entrypoint `{}` exceeded {} restarts in a row, giving up on it",
+                                    name, backoff.restarts
+                                );
+                                continue;
+                            }
+                        };
+                        info!("restarting entrypoint `{}` in {:?}", name, delay);
+                        pending.push((Instant::now() + delay, name));
+                    }
+                }
+            }
+
+            if voids.is_empty()
+                && spawner.helpers.borrow().is_empty()
+                && signal_triggers.is_empty()
+                && pending.is_empty()
+            {
                 info!("all child processes have exited, exiting...");
-                break;
+                return Ok(result);
             }
-            Err(e) => Err(Error::Nix {
-                msg: "waitpid",
-                src: e,
-            }),
-        }?;
 
-        match status {
-            WaitStatus::Exited(pid, code) => {
-                if code != exitcode::OK {
-                    exit_code = code;
+            // restart the entrypoints whose backoff has passed
+            let now = Instant::now();
+            let (due, waiting) = pending.into_iter().partition(|(at, _)| *at <= now);
+            pending = waiting;
+            for (_, name) in due {
+                match spawner.respawn(&name) {
+                    Ok(void) => {
+                        result.spawned.push((void.pid(), name.clone()));
+                        if let Some(timeout) = spec.entrypoints[&name].timeout() {
+                            deadlines.insert(void.pid(), Instant::now() + timeout);
+                        }
+                        voids.insert(void.pid(), (name, void));
+                    }
+                    Err(e) => error!("failed to restart entrypoint `{}`: {}", name, e),
+                }
+            }
+
+            // kill the voids that outlived their timeout, then wait on a
+            // signal no longer than until the next deadline or restart
+            let now = Instant::now();
+            deadlines.retain(|pid, deadline| {
+                if *deadline > now {
+                    return true;
+                }
+                if let Some((name, void)) = voids.get(pid) {
+                    warn!(
+                        "{} of entrypoint `{}` timed out, sending SIGKILL",
+                        void, name
+                    );
+                    // the void may have exited since, so ignore failures
+                    let _ = void.signal(Signal::SIGKILL);
+                }
+                false
+            });
+
+            // captured output is drained meanwhile, as a void blocks once its pipe fills
+            let timeout = deadlines
+                .values()
+                .chain(pending.iter().map(|(at, _)| at))
+                .min()
+                .map(|deadline| deadline.saturating_duration_since(now));
+            if timeout.is_some() || self.capture_output {
+                let voids = voids.values_mut().map(|(_, void)| void);
+                if !wait_signal(&signals, timeout, voids)? {
+                    continue;
                 }
-                debug!("child {} exited with code {}", pid, code);
             }
-            WaitStatus::Signaled(pid, sig, _coredump) => {
-                debug!("child {} was terminated with signal {}", pid, sig);
+
+            let sig = match signals.read_signal() {
+                Ok(Some(info)) => Signal::try_from(info.ssi_signo as i32),
+                Ok(None) | Err(nix::Error::EINTR) => continue,
+                Err(e) => {
+                    return Err(Error::Nix {
+                        msg: "read_signal",
+                        src: e,
+                    })
+                }
+            };
+
+            match sig {
+                Ok(Signal::SIGCHLD) => {}
+                Ok(Signal::SIGTERM | Signal::SIGINT) => {
+                    let code =
+                        shutdown(voids.values().map(|(_, void)| void), spawner.helpers.take())?;
+                    // a clean shutdown keeps the code of any void that failed earlier
+                    if code != exitcode::OK {
+                        result.exit_code = self.exit_policy.combine(result.exit_code, code);
+                    }
+                    for (_, void) in voids.values_mut() {
+                        result.collect_output(void);
+                    }
+                    return Ok(result);
+                }
+                Ok(sig) => {
+                    for name in signal_triggers.get(&sig).into_iter().flatten() {
+                        info!("received {}, spawning entrypoint `{}`", sig, name);
+
+                        match spawner.respawn(name) {
+                            Ok(void) => {
+                                result.spawned.push((void.pid(), name.to_string()));
+                                voids.insert(void.pid(), (name.to_string(), void));
+                            }
+                            Err(e) => error!("failed to spawn entrypoint `{}`: {}", name, e),
+                        }
+                    }
+                }
+                Err(e) => debug!("received unknown signal: {}", e),
             }
-            _ => unreachable!(),
         }
     }
+}
 
-    Ok(exit_code)
+/**
+ * Log each validation warning, as an error in strict mode. Returns whether
+ * the warnings should stop the specification running.
+ */
+fn report_warnings(warnings: &[Warning], strict: bool) -> bool {
+    for warning in warnings {
+        if strict {
+            error!("{}", warning);
+        } else {
+            warn!("{}", warning);
+        }
+    }
+
+    strict && !warnings.is_empty()
 }
 
-fn create_pipes(names: Vec<&str>) -> Result<HashMap<String, PipePair>> {
+/**
+ * Print every entrypoint in dependency order, with its trigger,
+ * arguments and environment. Returns false if a mount source is missing from
+ * the launching namespace, which would otherwise only fail once spawned.
+ */
+fn print_plan(spec: &Specification) -> Result<bool> {
+    let mut mounts_found = true;
+
+    for name in spec.dependency_order()? {
+        let entrypoint = &spec.entrypoints[name];
+
+        println!("entrypoint `{}`", name);
+        println!("    trigger: {:?}", entrypoint.trigger);
+        println!("    instances: {}", entrypoint.instances);
+        if !entrypoint.depends_on.is_empty() {
+            println!("    depends on: {}", entrypoint.depends_on.join(", "));
+        }
+
+        for arg in &entrypoint.args {
+            println!("    arg: {:?}", arg);
+        }
+
+        // the environment is a set, so sort it for a stable plan
+        let mut environment: Vec<String> = Vec::new();
+        for env in &entrypoint.environment {
+            environment.push(match env {
+                Environment::Filesystem {
+                    host_path,
+                    environment_path,
+                    ..
+                } => {
+                    let missing = !host_path.exists();
+                    if missing {
+                        error!(
+                            "mount source `{}` of entrypoint `{}` does not exist",
+                            host_path.display(),
+                            name
+                        );
+                        mounts_found = false;
+                    }
+
+                    format!(
+                        "mount: {} -> {}{}",
+                        host_path.display(),
+                        environment_path.display(),
+                        if missing { " (missing)" } else { "" }
+                    )
+                }
+                env => format!("environment: {:?}", env),
+            });
+        }
+        environment.sort();
+
+        for line in environment {
+            println!("    {}", line);
+        }
+    }
+
+    Ok(mounts_found)
+}
+
+/// The signal mask of a thread before the shim blocked its signals, restored
+/// once dropped
+struct SavedMask(SigSet);
+
+impl Drop for SavedMask {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.thread_set_mask() {
+            warn!("failed to restore the signal mask: {}", e);
+        }
+    }
+}
+
+/**
+ * Block SIGCHLD, the shutdown signals and every trigger signal, returning a
+ * signalfd to receive them on and the previous mask of the thread. The shim's
+ * main loop waits on this rather than on waitid so a signal can never be lost
+ * between checking and blocking.
+ */
+fn block_signals<'a>(
+    triggers: impl IntoIterator<Item = &'a Signal>,
+) -> Result<(SignalFd, SavedMask)> {
+    let mut mask = SigSet::empty();
+    for sig in [Signal::SIGCHLD, Signal::SIGTERM, Signal::SIGINT] {
+        mask.add(sig);
+    }
+    for sig in triggers {
+        mask.add(*sig);
+    }
+
+    let saved = mask
+        .thread_swap_mask(SigmaskHow::SIG_BLOCK)
+        .map_err(|e| Error::Nix {
+            msg: "pthread_sigmask",
+            src: e,
+        })?;
+    let saved = SavedMask(saved);
+
+    let signals = SignalFd::with_flags(&mask, SfdFlags::SFD_CLOEXEC).map_err(|e| Error::Nix {
+        msg: "signalfd",
+        src: e,
+    })?;
+    Ok((signals, saved))
+}
+
+/**
+ * Wait up to the timeout, or forever if none is given, for a signal to be
+ * ready on the signalfd, returning whether one is. Captured output of the
+ * voids that is ready first is read, which also ends the wait.
+ */
+fn wait_signal<'a>(
+    signals: &SignalFd,
+    timeout: Option<Duration>,
+    voids: impl IntoIterator<Item = &'a mut VoidHandle>,
+) -> Result<bool> {
+    // rounded up, so the deadline has passed once a wait times out
+    let timeout = match timeout {
+        Some(t) => ((t.as_micros() + 999) / 1000)
+            .try_into()
+            .unwrap_or(libc::c_int::MAX),
+        None => -1,
+    };
+
+    let mut voids: Vec<&mut VoidHandle> = voids.into_iter().collect();
+    let mut fds = vec![PollFd::new(signals.as_raw_fd(), PollFlags::POLLIN)];
+    let mut owners = Vec::new();
+    for (i, void) in voids.iter().enumerate() {
+        for fd in void.output_fds() {
+            fds.push(PollFd::new(fd, PollFlags::POLLIN));
+            owners.push(i);
+        }
+    }
+
+    match poll(&mut fds, timeout) {
+        Ok(_) => {}
+        Err(nix::Error::EINTR) => return Ok(false),
+        Err(e) => {
+            return Err(Error::Nix {
+                msg: "poll",
+                src: e,
+            })
+        }
+    }
+
+    // a pipe whose writers have all closed polls as POLLHUP, read as EOF
+    for (fd, i) in fds[1..].iter().zip(owners) {
+        if matches!(fd.revents(), Some(events) if !events.is_empty()) {
+            voids[i].read_output()?;
+        }
+    }
+
+    Ok(matches!(fds[0].revents(), Some(events) if events.contains(PollFlags::POLLIN)))
+}
+
+/**
+ * Send SIGTERM to every void and reap them, falling back to SIGKILL for any
+ * still running after the timeout. A void is the init of its own pid
+ * namespace, so SIGTERM only reaches it if the application handles it;
+ * SIGKILL always tears down the whole namespace. The rpc handlers of the
+ * voids exit with them, and are reaped after.
+ *
+ * Returns OK if every void exited within the timeout.
+ */
+fn shutdown<'a>(
+    voids: impl IntoIterator<Item = &'a VoidHandle>,
+    helpers: impl IntoIterator<Item = unistd::Pid>,
+) -> Result<i32> {
+    let voids: Vec<&VoidHandle> = voids.into_iter().collect();
+
+    info!("shutting down {} voids...", voids.len());
+    for void in &voids {
+        debug!("sending SIGTERM to {}", void);
+        if let Err(e) = void.signal(Signal::SIGTERM) {
+            debug!("failed to signal {}: {}", void, e);
+        }
+    }
+
+    // wait on each void's pidfd in turn, as all share the one deadline
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    let mut killed = false;
+
+    for void in &voids {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if !killed && !void.wait(Some(timeout))? {
+            warn!(
+                "voids did not exit within {:?}, sending SIGKILL",
+                SHUTDOWN_TIMEOUT
+            );
+            for void in &voids {
+                // the void may have exited since, so ignore failures
+                let _ = void.signal(Signal::SIGKILL);
+            }
+            killed = true;
+        }
+    }
+
+    let children: Vec<unistd::Pid> = voids.iter().map(|void| void.pid()).chain(helpers).collect();
+    for pid in children {
+        loop {
+            match waitid(Id::Pid(pid), WaitPidFlag::WEXITED) {
+                Ok(status) => {
+                    debug!("child exited during shutdown: {:?}", status);
+                    break;
+                }
+                Err(nix::Error::EINTR) => {}
+                Err(e) => {
+                    return Err(Error::Nix {
+                        msg: "waitpid",
+                        src: e,
+                    })
+                }
+            }
+        }
+    }
+
+    info!("all child processes have exited, exiting...");
+    Ok(if killed {
+        exitcode::SOFTWARE
+    } else {
+        exitcode::OK
+    })
+}
+
+/**
+ * Find the specification, in order of precedence: an explicit file,
+ * a file named by $VOID_SPEC, then a specification embedded in the binary.
+ * `${N}` placeholders are substituted with the binary's arguments.
+ */
+fn read_specification(args: &RunArgs) -> Result<Specification> {
+    // placeholders in the specification are filled from the binary's argv
+    let binary = args.binary.to_string_lossy();
+    let argv: Vec<&str> = std::iter::once(binary.as_ref())
+        .chain(args.binary_args.iter().copied())
+        .collect();
+
+    if let Some(path) = args.spec {
+        info!("reading specification from `{}`", path.display());
+        return Specification::from_file(path, &argv);
+    }
+
+    if let Some(path) = env::var_os(SPEC_ENV_VAR) {
+        let path = Path::new(&path);
+        info!(
+            "reading specification from ${} `{}`",
+            SPEC_ENV_VAR,
+            path.display()
+        );
+        return Specification::from_file(path, &argv);
+    }
+
+    if let Some(spec) = Specification::from_elf(args.binary, &argv)? {
+        info!(
+            "reading specification embedded in `{}`",
+            args.binary.display()
+        );
+        return Ok(spec);
+    }
+
+    Err(Error::NoSpecification)
+}
+
+fn create_pipes(
+    names: Vec<&str>,
+    options: &HashMap<String, PipeOptions>,
+) -> Result<HashMap<String, PipePair>> {
+    let default = PipeOptions::default();
+
     let mut pipes = HashMap::new();
     for pipe in names {
         info!("creating pipe pair `{}`", pipe);
-        pipes.insert(pipe.to_string(), PipePair::new(pipe)?);
+        let options = options.get(pipe).unwrap_or(&default);
+        pipes.insert(pipe.to_string(), PipePair::new(pipe, options)?);
     }
 
     Ok(pipes)
 }
 
-fn create_sockets(names: Vec<&str>) -> Result<HashMap<String, SocketPair>> {
+fn create_sockets(
+    names: Vec<&str>,
+    options: &HashMap<String, SocketOptions>,
+) -> Result<HashMap<String, SocketPair>> {
     let mut sockets = HashMap::new();
     for socket in names {
         info!("creating socket pair `{}`", socket);
-        sockets.insert(socket.to_string(), SocketPair::new(socket)?);
+        let options = options.get(socket);
+        let kind = options.map(|o| o.kind).unwrap_or_default();
+        let mut pair = SocketPair::new(socket, kind)?;
+
+        if let Some(address) = options.and_then(|o| o.abstract_address.as_ref()) {
+            info!(
+                "listening for socket `{}` at abstract address `{}`",
+                socket, address
+            );
+            pair.listen(address)?;
+        }
+
+        sockets.insert(socket.to_string(), pair);
     }
 
     Ok(sockets)
 }
 
+/// Exponential backoff between restarts of an entrypoint, which resets along
+/// with the count of restarts in a row once the entrypoint has stayed up for
+/// longer than the maximum backoff.
+struct Backoff {
+    initial: Duration,
+    max_restarts: Option<u32>,
+
+    restarts: u32,
+    delay: Option<Duration>,
+    restarted: Option<Instant>,
+}
+
+impl Backoff {
+    fn new(initial: Duration, max_restarts: Option<u32>) -> Backoff {
+        Backoff {
+            initial,
+            max_restarts,
+            restarts: 0,
+            delay: None,
+            restarted: None,
+        }
+    }
+
+    /// The delay before the next restart, or None once the entrypoint has
+    /// restarted too many times in a row
+    fn next_delay(&mut self) -> Option<Duration> {
+        let max = RESTART_BACKOFF_MAX.max(self.initial);
+        let delay = match (self.delay, self.restarted) {
+            (Some(delay), Some(restarted)) if restarted.elapsed() < max => (delay * 2).min(max),
+            _ => {
+                self.restarts = 0;
+                self.initial
+            }
+        };
+
+        if matches!(self.max_restarts, Some(max) if self.restarts >= max) {
+            return None;
+        }
+
+        self.restarts += 1;
+        self.delay = Some(delay);
+        self.restarted = Some(Instant::now() + delay);
+        Some(delay)
+    }
+}
+
 pub struct PipePair {
     name: String,
 
@@ -146,18 +1013,38 @@ pub struct PipePair {
 }
 
 impl PipePair {
-    fn new(name: &str) -> Result<PipePair> {
-        let (read, write) = unistd::pipe2(OFlag::O_DIRECT).map_err(|e| Error::Nix {
+    fn new(name: &str, options: &PipeOptions) -> Result<PipePair> {
+        let mut flags = OFlag::O_DIRECT;
+        flags.set(OFlag::O_NONBLOCK, options.nonblocking);
+
+        let (read, write) = unistd::pipe2(flags).map_err(|e| Error::Nix {
             msg: "pipe2",
             src: e,
         })?;
 
+        // SAFETY: valid new fd as pipe2(2) returned successfully
+        let read = unsafe { File::from_raw_fd(read) };
+        // SAFETY: valid new fd as pipe2(2) returned successfully
+        let write = unsafe { File::from_raw_fd(write) };
+
+        if let Some(capacity) = options.capacity {
+            let size = capacity.try_into().unwrap_or(libc::c_int::MAX);
+            fcntl(write.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(size)).map_err(|e| {
+                Error::BadPipeCapacity {
+                    name: name.to_string(),
+                    capacity,
+                    max: fs::read_to_string(PIPE_MAX_SIZE)
+                        .map(|max| max.trim().to_string())
+                        .unwrap_or_else(|_| "unknown".to_string()),
+                    src: e,
+                }
+            })?;
+        }
+
         Ok(PipePair {
             name: name.to_string(),
-            // SAFETY: valid new fd as pipe2(2) returned successfully
-            read: Some(unsafe { File::from_raw_fd(read) }),
-            // SAFETY: valid new fd as pipe2(2) returned successfully
-            write: Some(unsafe { File::from_raw_fd(write) }),
+            read: Some(read),
+            write: Some(write),
         })
     }
 
@@ -174,18 +1061,40 @@ impl PipePair {
     }
 }
 
+fn create_readiness_pipes(names: Vec<&str>) -> Result<HashMap<String, ReadyPipe>> {
+    let mut pipes = HashMap::new();
+    for name in names {
+        info!("creating readiness pipe for `{}`", name);
+        pipes.insert(name.to_string(), ReadyPipe::new()?);
+    }
+
+    Ok(pipes)
+}
+
+/// A datagram or stream socketpair carrying files between entrypoints. The read end
+/// is taken by the single receiver, while the write end stays in the shim
+/// and is duplicated for every sender, so any number of voids may send.
 pub struct SocketPair {
     name: String,
 
     read: Option<File>,
     write: File,
+
+    /// A seqpacket socket listening at an abstract address for senders
+    /// outside the shim, taken with the read end
+    listener: Option<File>,
 }
 
 impl SocketPair {
-    fn new(name: &str) -> Result<SocketPair> {
+    fn new(name: &str, kind: SocketKind) -> Result<SocketPair> {
+        let sock_type = match kind {
+            SocketKind::Datagram => socket::SockType::Datagram,
+            SocketKind::Stream => socket::SockType::Stream,
+        };
+
         let (read, write) = socket::socketpair(
             socket::AddressFamily::Unix,
-            socket::SockType::Datagram,
+            sock_type,
             None,
             socket::SockFlag::empty(),
         )
@@ -200,9 +1109,49 @@ impl SocketPair {
             read: Some(unsafe { File::from_raw_fd(read) }),
             // SAFETY: valid new fd as socketpair(2) returned successfully
             write: unsafe { File::from_raw_fd(write) },
+            listener: None,
         })
     }
 
+    /**
+     * Listen at an abstract address, which belongs to the network namespace
+     * of the shim rather than the filesystem, so needs no cleanup.
+     */
+    fn listen(&mut self, address: &str) -> Result<()> {
+        let listener = socket::socket(
+            socket::AddressFamily::Unix,
+            socket::SockType::SeqPacket,
+            socket::SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .map_err(|e| Error::Nix {
+            msg: "socket",
+            src: e,
+        })?;
+        // SAFETY: valid new fd as socket(2) returned successfully
+        let listener = unsafe { File::from_raw_fd(listener) };
+
+        let addr = socket::UnixAddr::new_abstract(address.as_bytes()).map_err(|e| Error::Nix {
+            msg: "UnixAddr::new_abstract",
+            src: e,
+        })?;
+        socket::bind(listener.as_raw_fd(), &addr).map_err(|e| Error::Nix {
+            msg: "bind",
+            src: e,
+        })?;
+        socket::listen(listener.as_raw_fd(), 128).map_err(|e| Error::Nix {
+            msg: "listen",
+            src: e,
+        })?;
+
+        self.listener = Some(listener);
+        Ok(())
+    }
+
+    fn take_listener(&mut self) -> Option<File> {
+        self.listener.take()
+    }
+
     fn take_read(&mut self) -> Result<File> {
         self.read
             .take()
@@ -210,14 +1159,41 @@ impl SocketPair {
     }
 
     fn write(&self) -> Result<File> {
-        let dup_fd = nix::unistd::dup(self.write.as_raw_fd())
-            .map_err(|e| Error::Nix { msg: "dup", src: e })?;
-
-        // SAFETY: valid new fd as dup(2) returned successfully
-        Ok(unsafe { File::from_raw_fd(dup_fd) })
+        Ok(self.write.try_clone()?)
     }
 
     fn write_ref(&self) -> &File {
         &self.write
     }
 }
+
+/// A pipe an entrypoint writes to once it is ready. Both ends stay open in
+/// the shim, so a restarted entrypoint can be given the write end again.
+pub struct ReadyPipe {
+    read: File,
+    write: File,
+}
+
+impl ReadyPipe {
+    fn new() -> Result<ReadyPipe> {
+        let (read, write) = unistd::pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::Nix {
+            msg: "pipe2",
+            src: e,
+        })?;
+
+        Ok(ReadyPipe {
+            // SAFETY: valid new fd as pipe2(2) returned successfully
+            read: unsafe { File::from_raw_fd(read) },
+            // SAFETY: valid new fd as pipe2(2) returned successfully
+            write: unsafe { File::from_raw_fd(write) },
+        })
+    }
+
+    fn read_ref(&self) -> &File {
+        &self.read
+    }
+
+    fn write(&self) -> Result<File> {
+        Ok(self.write.try_clone()?)
+    }
+}
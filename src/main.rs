@@ -1,10 +1,14 @@
 use log::error;
 
-use void_orchestrator::{run, RunArgs};
+use void_orchestrator::{run, ExitPolicy, ExtraMount, RunArgs, Specification};
 
+use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
+use std::time::Duration;
 
 use clap::{Arg, Command};
+use nix::unistd::Pid;
 
 fn main() {
     // process arguments
@@ -17,7 +21,7 @@ fn main() {
             Arg::new("spec")
                 .long("specification")
                 .short('s')
-                .help("Provide the specification as an external JSON file.")
+                .help("Provide the specification as an external JSON file, taking precedence over $VOID_SPEC and an embedded specification.")
                 .takes_value(true),
         )
         .arg(
@@ -27,6 +31,14 @@ fn main() {
                 .help("Use verbose logging.")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("log_format")
+                .long("log-format")
+                .help("Format of the shim's logs, json emits one object per record.")
+                .takes_value(true)
+                .possible_values(["text", "json"])
+                .default_value("text"),
+        )
         .arg(
             Arg::new("debug")
                 .long("debug")
@@ -34,6 +46,53 @@ fn main() {
                 .help("Stop each spawned application process so that it can be attached to.")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("trace_builder")
+                .long("trace-builder")
+                .help("Log the full configuration of each void before it is spawned.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("audit")
+                .long("audit")
+                .help("Log the full argv and mounts each void is launched with, which may include secrets.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .help("Treat specification warnings as errors, exiting before spawning anything.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .help("Validate the specification and print what would be spawned, without spawning anything.")
+                .takes_value(false)
+                .conflicts_with("daemon"),
+        )
+        .arg(
+            Arg::new("exit_code")
+                .long("exit-code")
+                .help("How to choose the shim's exit code when more than one process fails.")
+                .takes_value(true)
+                .possible_values(["first-failure", "last-failure", "max-code"])
+                .default_value("last-failure"),
+        )
+        .arg(
+            Arg::new("max_restarts")
+                .long("max-restarts")
+                .help("Give up on an entrypoint after this many restarts in a row, unless its specification sets max_restarts.")
+                .takes_value(true)
+                .validator(|n| n.parse::<u32>()),
+        )
+        .arg(
+            Arg::new("restart_backoff")
+                .long("restart-backoff")
+                .help("Milliseconds before the first restart in a row, doubling for each after it, unless an entrypoint sets restart_backoff_ms.")
+                .takes_value(true)
+                .validator(parse_backoff),
+        )
         .arg(
             Arg::new("daemon")
                 .long("daemon")
@@ -41,6 +100,13 @@ fn main() {
                 .help("Detach the shim from all child processes and exit immediately.")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("pid_file")
+                .long("pid-file")
+                .help("Write the pid of each spawned void to this file, one per line, before detaching.")
+                .takes_value(true)
+                .requires("daemon"),
+        )
         .arg(
             Arg::new("stdout")
                 .long("stdout")
@@ -53,6 +119,14 @@ fn main() {
                 .help("Allow all spawned processes access to stderr (useful for debugging).")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("mount")
+                .long("mount")
+                .help("Bind mount a host path into every spawned process as HOST:DEST, read-only unless suffixed with :rw. Can be repeated.")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .validator(|m| parse_mount(m).map(drop)),
+        )
         .arg(
             Arg::new("binary")
                 .index(1)
@@ -60,6 +134,20 @@ fn main() {
                 .required(true)
                 .multiple_values(true),
         )
+        .subcommand(
+            Command::new("inspect")
+                .about("Print the specification embedded in a binary as JSON, without running it.")
+                .trailing_var_arg(true)
+                .arg(
+                    Arg::new("binary")
+                        .index(1)
+                        .help("Binary to inspect, and any arguments to fill its placeholders")
+                        .required(true)
+                        .multiple_values(true),
+                ),
+        )
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
         .get_matches();
 
     // setup logging
@@ -71,7 +159,24 @@ fn main() {
             "warn"
         },
     );
-    env_logger::init_from_env(env);
+    let mut logger = env_logger::Builder::from_env(env);
+    if matches.value_of("log_format") == Some("json") {
+        logger.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    logger.init();
+
+    if let Some(matches) = matches.subcommand_matches("inspect") {
+        let argv: Vec<&str> = matches.values_of("binary").unwrap().collect();
+        std::process::exit(inspect(Path::new(argv[0]), &argv));
+    }
 
     // launch process
     // execute shimmed process
@@ -89,16 +194,55 @@ fn main() {
             spec: matches.value_of("spec").map(Path::new),
             debug: matches.is_present("debug"),
             daemon: matches.is_present("daemon"),
+            trace_builder: matches.is_present("trace_builder"),
+            audit: matches.is_present("audit"),
+            strict: matches.is_present("strict"),
+            dry_run: matches.is_present("dry_run"),
+            exit_policy: match matches.value_of("exit_code") {
+                Some("first-failure") => ExitPolicy::FirstFailure,
+                Some("max-code") => ExitPolicy::MaxCode,
+                _ => ExitPolicy::LastFailure,
+            },
+            max_restarts: matches
+                .value_of("max_restarts")
+                .map(|n| n.parse().expect("validated by clap")),
+            restart_backoff: matches
+                .value_of("restart_backoff")
+                .map(|ms| parse_backoff(ms).expect("validated by clap")),
 
             stdout: matches.is_present("stdout"),
             stderr: matches.is_present("stderr"),
+            mounts: matches
+                .values_of("mount")
+                .into_iter()
+                .flatten()
+                .map(|m| parse_mount(m).expect("validated by clap"))
+                .collect(),
 
             binary,
             binary_args,
         };
 
         match run(&args) {
-            Ok(code) => code,
+            Ok(result) => {
+                // a detached shim reports what it spawned for supervisors to track
+                if args.daemon {
+                    for (pid, name) in &result.spawned {
+                        println!("{} {}", pid, name);
+                    }
+                }
+
+                match matches.value_of("pid_file").map(Path::new) {
+                    Some(path) => match write_pid_file(path, &result.spawned) {
+                        Ok(()) => result.exit_code,
+                        Err(e) => {
+                            error!("error writing pid file `{}`: {}", path.display(), e);
+                            exitcode::IOERR
+                        }
+                    },
+                    None => result.exit_code,
+                }
+            }
             Err(e) => {
                 error!("error: {}", e);
                 -1
@@ -106,3 +250,76 @@ fn main() {
         }
     })
 }
+
+/**
+ * Parse a mount given as HOST:DEST or HOST:DEST:rw, where both paths must be
+ * absolute.
+ */
+fn parse_mount(value: &str) -> Result<ExtraMount<'_>, String> {
+    let (paths, writable) = match value.strip_suffix(":rw") {
+        Some(paths) => (paths, true),
+        None => (value, false),
+    };
+
+    let (host_path, environment_path) = paths
+        .split_once(':')
+        .map(|(host, dst)| (Path::new(host), Path::new(dst)))
+        .ok_or_else(|| format!("expected HOST:DEST, got `{}`", value))?;
+
+    if !host_path.is_absolute() || !environment_path.is_absolute() {
+        return Err(format!("mount paths must be absolute, got `{}`", value));
+    }
+
+    Ok(ExtraMount {
+        host_path,
+        environment_path,
+        writable,
+    })
+}
+
+/**
+ * Parse a restart backoff in milliseconds, which must be non-zero so a crash
+ * loop cannot spin.
+ */
+fn parse_backoff(value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(0) => Err("the backoff must be non-zero".to_string()),
+        Ok(ms) => Ok(Duration::from_millis(ms)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn write_pid_file(path: &Path, spawned: &[(Pid, String)]) -> io::Result<()> {
+    let pids: String = spawned
+        .iter()
+        .map(|(pid, _)| format!("{}\n", pid))
+        .collect();
+    fs::write(path, pids)
+}
+
+/**
+ * Print the specification embedded in the binary, with placeholders filled
+ * from argv, returning the exit code.
+ */
+fn inspect(binary: &Path, argv: &[&str]) -> i32 {
+    match Specification::from_elf(binary, argv) {
+        Ok(Some(spec)) => match serde_json::to_string_pretty(&spec) {
+            Ok(json) => {
+                println!("{}", json);
+                exitcode::OK
+            }
+            Err(e) => {
+                error!("error: {}", e);
+                exitcode::SOFTWARE
+            }
+        },
+        Ok(None) => {
+            println!("no spec embedded");
+            exitcode::OK
+        }
+        Err(e) => {
+            error!("error: {}", e);
+            exitcode::DATAERR
+        }
+    }
+}
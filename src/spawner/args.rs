@@ -1,21 +1,75 @@
 use log::info;
 
+use super::secret::sealed_memfd;
 use super::{RpcHandler, Spawner, TriggerData};
-use crate::specification::{Arg, FileSocket, Pipe};
+use crate::specification::{Arg, Entrypoint, FileSocket, Pipe};
 use crate::void::VoidBuilder;
 use crate::{Error, Result};
 
+use std::collections::{HashMap, HashSet};
 use std::ffi::CString;
-use std::fs::File;
-use std::net::TcpListener;
+use std::fs::{self, File};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
 use std::os::unix::ffi::OsStrExt;
-use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
 
+use nix::fcntl::{fcntl, FcntlArg};
 use nix::sys::socket;
-use nix::unistd::{fork, ForkResult};
+use nix::unistd::{close, dup2, fork, getpid, ForkResult};
+
+/// The lowest port an unprivileged process can bind
+const UNPRIVILEGED_PORT_START: &str = "/proc/sys/net/ipv4/ip_unprivileged_port_start";
 
 pub struct PreparedArgs(Vec<PreparedArg>);
 
+/**
+ * Sockets bound by the shim for entrypoints spawned from a trigger's void,
+ * by address. Binding there would use the trigger void's network namespace
+ * and fail on every trigger after the first, so each triggered void is
+ * passed a duplicate of these instead.
+ */
+#[derive(Default)]
+pub struct BoundSockets {
+    tcp: HashMap<SocketAddr, TcpListener>,
+    udp: HashMap<SocketAddr, UdpSocket>,
+    unix: HashMap<PathBuf, UnixListener>,
+}
+
+impl BoundSockets {
+    /**
+     * Bind every listening argument with ambient authority before the
+     * trigger's void is cloned, so a failure such as EADDRINUSE stops the
+     * shim rather than the trigger. The sockets are kept open in the void.
+     */
+    pub fn bind(&mut self, builder: &mut VoidBuilder, args: &[Arg]) -> Result<()> {
+        for arg in args {
+            match arg.inner() {
+                Arg::TcpListener { addr } => {
+                    let socket = bind_tcp(addr)?;
+                    builder.keep_fd(&socket);
+                    self.tcp.insert(*addr, socket);
+                }
+                Arg::UdpSocket { addr } => {
+                    let socket = bind_udp(addr)?;
+                    builder.keep_fd(&socket);
+                    self.udp.insert(*addr, socket);
+                }
+                Arg::UnixListener { path } => {
+                    let socket = UnixListener::bind(path)?;
+                    builder.keep_fd(&socket);
+                    self.unix.insert(path.clone(), socket);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl PreparedArgs {
     /**
      * perform initial processing with ambient authority
@@ -26,12 +80,15 @@ impl PreparedArgs {
     pub fn prepare_ambient_mut(
         spawner: &mut Spawner,
         builder: &mut VoidBuilder,
+        entrypoint: &str,
         args: &[Arg],
     ) -> Result<Self> {
         let mut v = Vec::with_capacity(args.len());
 
         for arg in args {
-            v.push(PreparedArg::prepare_ambient_mut(spawner, builder, arg)?);
+            v.push(PreparedArg::prepare_ambient_mut(
+                spawner, builder, entrypoint, arg,
+            )?);
         }
 
         Ok(PreparedArgs(v))
@@ -45,12 +102,14 @@ impl PreparedArgs {
     pub fn prepare_ambient(
         spawner: &Spawner,
         builder: &mut VoidBuilder,
-        args: &[Arg],
+        entrypoint: &Entrypoint,
     ) -> Result<Self> {
-        let mut v = Vec::with_capacity(args.len());
+        let mut v = Vec::with_capacity(entrypoint.args.len());
 
-        for arg in args {
-            v.push(PreparedArg::prepare_ambient(spawner, builder, arg)?);
+        for arg in &entrypoint.args {
+            v.push(PreparedArg::prepare_ambient(
+                spawner, builder, entrypoint, arg,
+            )?);
         }
 
         Ok(PreparedArgs(v))
@@ -60,16 +119,55 @@ impl PreparedArgs {
         self,
         spawner: &Spawner,
         entrypoint: &str,
+        instance: u32,
         trigger: &mut TriggerData,
     ) -> Result<Vec<CString>> {
+        let mut args = self.0;
+
+        // move any fds sitting on a requested fixed fd out of the way first
+        let targets: HashSet<RawFd> = args
+            .iter()
+            .filter_map(|arg| match arg {
+                PreparedArg::FixedFd { fd, arg: _ } => Some(*fd),
+                _ => None,
+            })
+            .collect();
+
+        if let Some(max) = targets.iter().max() {
+            args = args
+                .into_iter()
+                .map(|arg| arg.relocate(&targets, max + 1))
+                .collect::<Result<_>>()?;
+
+            trigger.relocate(&targets, max + 1)?;
+        }
+
         let mut v = Vec::new();
 
-        for arg in self.0 {
-            v.extend(arg.prepare_void(spawner, entrypoint, trigger)?)
+        for arg in args {
+            v.extend(arg.prepare_void(spawner, entrypoint, instance, trigger)?)
         }
 
         Ok(v)
     }
+
+    /**
+     * The argv prepare_void will produce, for logging before the void is
+     * spawned. Fds are numbered as in the shim, which only differs in the
+     * void for those moved out of the way of a fixed fd.
+     */
+    pub(super) fn describe(
+        &self,
+        spawner: &Spawner,
+        entrypoint: &str,
+        instance: u32,
+        trigger: &TriggerData,
+    ) -> Vec<String> {
+        self.0
+            .iter()
+            .flat_map(|arg| arg.describe(spawner, entrypoint, instance, trigger))
+            .collect()
+    }
 }
 enum PreparedArg {
     /// The binary name, or argv[0], of the original program start
@@ -78,15 +176,30 @@ enum PreparedArg {
     /// The name of this entrypoint
     Entrypoint,
 
+    /// The index of this instance of the entrypoint
+    InstanceId,
+
+    /// The pid of the void, only known once it is running
+    Pid,
+
+    /// A fixed string from the specification
+    Literal(CString),
+
     /// A file descriptor for a file on the filesystem in the launching namespace
     File(File),
 
+    /// A sealed memfd holding a secret
+    Secret(File),
+
     /// A chosen end of a named pipe
     Pipe(File),
 
     /// File socket
     FileSocket(File),
 
+    /// The write end of this entrypoint's readiness pipe
+    Ready(File),
+
     /// A value specified by the trigger
     /// NOTE: Only valid if the trigger is of type Pipe(...) or FileSocket(...)
     Trigger,
@@ -94,11 +207,32 @@ enum PreparedArg {
     /// A TCP Listener
     TcpListener { socket: TcpListener },
 
+    /// A connected TCP stream
+    TcpConnect { socket: TcpStream },
+
+    /// A bound UDP socket
+    UdpSocket { socket: UdpSocket },
+
+    /// A Unix socket listener
+    UnixListener { socket: UnixListener },
+
     /// RPC
     Rpc { socket: File },
 
     /// The rest of argv[1..], 0 or more arguments
     Trailing,
+
+    /// One of argv[1..], or nothing if out of range
+    TrailingAt(usize),
+
+    /// The number of CPUs the void can use
+    CpuQuota(usize),
+
+    /// Another fd argument to be moved to a fixed fd
+    FixedFd { fd: RawFd, arg: Box<PreparedArg> },
+
+    /// An fd inherited by the shim, left at its number
+    InheritFd(RawFd),
 }
 
 impl PreparedArg {
@@ -112,13 +246,19 @@ impl PreparedArg {
     fn prepare_ambient_mut(
         spawner: &mut Spawner,
         builder: &mut VoidBuilder,
+        entrypoint: &str,
         arg: &Arg,
     ) -> Result<Self> {
         Ok(match arg {
             Arg::Pipe(p) => {
+                let pair = spawner
+                    .pipes
+                    .get_mut(p.get_name())
+                    .ok_or_else(|| Error::BadPipe(p.get_name().to_string()))?;
+
                 let pipe = match p {
-                    Pipe::Rx(s) => spawner.pipes.get_mut(s).unwrap().take_read(),
-                    Pipe::Tx(s) => spawner.pipes.get_mut(s).unwrap().take_write(),
+                    Pipe::Rx(_) => pair.take_read(),
+                    Pipe::Tx(_) => pair.take_write(),
                 }?;
 
                 builder.keep_fd(&pipe);
@@ -126,46 +266,123 @@ impl PreparedArg {
             }
 
             Arg::FileSocket(FileSocket::Rx(s)) => {
-                let socket = spawner.sockets.get_mut(s).unwrap().take_read()?;
+                let socket = spawner
+                    .sockets
+                    .get_mut(s)
+                    .ok_or_else(|| Error::BadFileSocket(s.to_string()))?
+                    .take_read()?;
 
                 builder.keep_fd(&socket);
                 PreparedArg::FileSocket(socket)
             }
 
-            arg => Self::prepare_ambient(spawner, builder, arg)?,
+            Arg::Ready => {
+                let pipe = spawner
+                    .readiness
+                    .get(entrypoint)
+                    .ok_or(Error::BadReadyArgument)?
+                    .write()?;
+
+                builder.keep_fd(&pipe);
+                PreparedArg::Ready(pipe)
+            }
+
+            Arg::FixedFd { fd, arg } => PreparedArg::FixedFd {
+                fd: *fd,
+                arg: Box::new(Self::prepare_ambient_mut(
+                    spawner, builder, entrypoint, arg,
+                )?),
+            },
+
+            arg => {
+                let spec = spawner.spec;
+                Self::prepare_ambient(spawner, builder, &spec.entrypoints[entrypoint], arg)?
+            }
         })
     }
 
-    fn prepare_ambient(spawner: &Spawner, builder: &mut VoidBuilder, arg: &Arg) -> Result<Self> {
+    fn prepare_ambient(
+        spawner: &Spawner,
+        builder: &mut VoidBuilder,
+        entrypoint: &Entrypoint,
+        arg: &Arg,
+    ) -> Result<Self> {
         Ok(match arg {
             Arg::Pipe(p) => return Err(Error::BadPipe(p.get_name().to_string())),
             Arg::FileSocket(FileSocket::Rx(s)) => return Err(Error::BadFileSocket(s.to_string())),
+            Arg::Ready => return Err(Error::BadReadyArgument),
 
             Arg::FileSocket(FileSocket::Tx(s)) => {
-                let socket = spawner.sockets.get(s).unwrap().write()?;
+                let socket = spawner
+                    .sockets
+                    .get(s)
+                    .ok_or_else(|| Error::BadFileSocket(s.to_string()))?
+                    .write()?;
 
                 builder.keep_fd(&socket);
                 PreparedArg::FileSocket(socket)
             }
 
-            Arg::File(path) => {
-                let fd = File::open(path)?;
+            Arg::File(file) => {
+                let fd = file.open()?;
                 builder.keep_fd(&fd);
 
                 PreparedArg::File(fd)
             }
 
+            Arg::Secret(path) => {
+                // every secret in the specification is read at startup
+                let contents = &spawner.secrets[path];
+
+                let memfd = sealed_memfd(contents)?;
+                builder.keep_fd(&memfd);
+
+                PreparedArg::Secret(memfd)
+            }
+
             Arg::TcpListener { addr } => {
-                let socket = TcpListener::bind(addr)?;
+                let socket = match spawner.bound_sockets.tcp.get(addr) {
+                    Some(socket) => socket.try_clone()?,
+                    None => bind_tcp(addr)?,
+                };
                 builder.keep_fd(&socket);
 
                 PreparedArg::TcpListener { socket }
             }
 
+            Arg::TcpConnect { addr } => {
+                let socket = TcpStream::connect(addr)?;
+                builder.keep_fd(&socket);
+
+                PreparedArg::TcpConnect { socket }
+            }
+
+            Arg::UdpSocket { addr } => {
+                let socket = match spawner.bound_sockets.udp.get(addr) {
+                    Some(socket) => socket.try_clone()?,
+                    None => bind_udp(addr)?,
+                };
+                builder.keep_fd(&socket);
+
+                PreparedArg::UdpSocket { socket }
+            }
+
+            Arg::UnixListener { path } => {
+                // fails with EADDRINUSE if the path already exists
+                let socket = match spawner.bound_sockets.unix.get(path) {
+                    Some(socket) => socket.try_clone()?,
+                    None => UnixListener::bind(path)?,
+                };
+                builder.keep_fd(&socket);
+
+                PreparedArg::UnixListener { socket }
+            }
+
             Arg::Rpc(specs) => {
+                // seqpacket keeps message boundaries and signals the void closing
                 let (ambient, void) = socket::socketpair(
                     socket::AddressFamily::Unix,
-                    socket::SockType::Datagram,
+                    socket::SockType::SeqPacket,
                     None,
                     socket::SockFlag::empty(),
                 )
@@ -189,25 +406,162 @@ impl PreparedArg {
 
                 match child {
                     ForkResult::Child => {
+                        // only the void may hold its end, so its exit ends the handler
+                        drop(void);
+
                         let handler = RpcHandler::new(specs);
                         handler.handle(ambient).unwrap();
+                        std::process::exit(exitcode::OK);
                     }
                     ForkResult::Parent { child } => {
                         info!("spawned rpc handler with pid {}", child);
+                        spawner.helpers.borrow_mut().insert(child);
                     }
                 };
 
+                builder.keep_fd(&void);
+
                 // SAFETY: safe as socketpair returned successfully
                 PreparedArg::Rpc { socket: void }
             }
 
+            Arg::FixedFd { fd, arg } => PreparedArg::FixedFd {
+                fd: *fd,
+                arg: Box::new(Self::prepare_ambient(spawner, builder, entrypoint, arg)?),
+            },
+
+            Arg::InheritFd(fd) => {
+                // kept fds are never checked, so would silently pass a closed fd
+                fcntl(*fd, FcntlArg::F_GETFD).map_err(|_| Error::BadInheritFd(*fd))?;
+                builder.keep_fd(fd);
+
+                PreparedArg::InheritFd(*fd)
+            }
+
             Arg::BinaryName => PreparedArg::BinaryName,
             Arg::Entrypoint => PreparedArg::Entrypoint,
+            Arg::InstanceId => PreparedArg::InstanceId,
+            Arg::Pid => PreparedArg::Pid,
+            Arg::Literal(s) => PreparedArg::Literal(
+                CString::new(s.as_str()).map_err(|_| Error::BadLiteral(s.to_string()))?,
+            ),
             Arg::Trigger => PreparedArg::Trigger,
             Arg::Trailing => PreparedArg::Trailing,
+            Arg::TrailingAt { index, .. } => PreparedArg::TrailingAt(*index),
+            Arg::CpuQuota => PreparedArg::CpuQuota(spawner.entrypoint_cpus(entrypoint)),
+        })
+    }
+
+    /**
+     * Move any fd held by this argument which occupies one of the
+     * target fds to a free fd at or above min
+     */
+    fn relocate(self, targets: &HashSet<RawFd>, min: RawFd) -> Result<Self> {
+        Ok(match self {
+            PreparedArg::File(f) => PreparedArg::File(relocate_fd(f, targets, min)?),
+            PreparedArg::Secret(f) => PreparedArg::Secret(relocate_fd(f, targets, min)?),
+            PreparedArg::Pipe(p) => PreparedArg::Pipe(relocate_fd(p, targets, min)?),
+            PreparedArg::FileSocket(s) => PreparedArg::FileSocket(relocate_fd(s, targets, min)?),
+            PreparedArg::Ready(p) => PreparedArg::Ready(relocate_fd(p, targets, min)?),
+            PreparedArg::TcpListener { socket } => PreparedArg::TcpListener {
+                socket: relocate_fd(socket, targets, min)?,
+            },
+            PreparedArg::TcpConnect { socket } => PreparedArg::TcpConnect {
+                socket: relocate_fd(socket, targets, min)?,
+            },
+            PreparedArg::UdpSocket { socket } => PreparedArg::UdpSocket {
+                socket: relocate_fd(socket, targets, min)?,
+            },
+            PreparedArg::UnixListener { socket } => PreparedArg::UnixListener {
+                socket: relocate_fd(socket, targets, min)?,
+            },
+            PreparedArg::Rpc { socket } => PreparedArg::Rpc {
+                socket: relocate_fd(socket, targets, min)?,
+            },
+            PreparedArg::FixedFd { fd, arg } => PreparedArg::FixedFd {
+                fd,
+                arg: Box::new(arg.relocate(targets, min)?),
+            },
+
+            arg => arg,
         })
     }
 
+    /**
+     * Take ownership of the fd held by this argument, if any
+     */
+    fn into_raw_fd(self) -> Option<RawFd> {
+        match self {
+            PreparedArg::File(f) => Some(f.into_raw_fd()),
+            PreparedArg::Secret(f) => Some(f.into_raw_fd()),
+            PreparedArg::Pipe(p) => Some(p.into_raw_fd()),
+            PreparedArg::FileSocket(s) => Some(s.into_raw_fd()),
+            PreparedArg::Ready(p) => Some(p.into_raw_fd()),
+            PreparedArg::TcpListener { socket } => Some(socket.into_raw_fd()),
+            PreparedArg::TcpConnect { socket } => Some(socket.into_raw_fd()),
+            PreparedArg::UdpSocket { socket } => Some(socket.into_raw_fd()),
+            PreparedArg::UnixListener { socket } => Some(socket.into_raw_fd()),
+            PreparedArg::Rpc { socket } => Some(socket.into_raw_fd()),
+            PreparedArg::InheritFd(fd) => Some(fd),
+
+            _ => None,
+        }
+    }
+
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        match self {
+            PreparedArg::File(f) => Some(f.as_raw_fd()),
+            PreparedArg::Secret(f) => Some(f.as_raw_fd()),
+            PreparedArg::Pipe(p) => Some(p.as_raw_fd()),
+            PreparedArg::FileSocket(s) => Some(s.as_raw_fd()),
+            PreparedArg::Ready(p) => Some(p.as_raw_fd()),
+            PreparedArg::TcpListener { socket } => Some(socket.as_raw_fd()),
+            PreparedArg::TcpConnect { socket } => Some(socket.as_raw_fd()),
+            PreparedArg::UdpSocket { socket } => Some(socket.as_raw_fd()),
+            PreparedArg::UnixListener { socket } => Some(socket.as_raw_fd()),
+            PreparedArg::Rpc { socket } => Some(socket.as_raw_fd()),
+            PreparedArg::InheritFd(fd) => Some(*fd),
+            PreparedArg::FixedFd { fd, .. } => Some(*fd),
+
+            _ => None,
+        }
+    }
+
+    /**
+     * The arguments prepare_void will produce, without consuming the argument
+     */
+    fn describe(
+        &self,
+        spawner: &Spawner,
+        entrypoint: &str,
+        instance: u32,
+        trigger: &TriggerData,
+    ) -> Vec<String> {
+        match self {
+            PreparedArg::BinaryName => vec![spawner.binary.display().to_string()],
+            PreparedArg::Entrypoint => vec![entrypoint.to_string()],
+            PreparedArg::InstanceId => vec![instance.to_string()],
+            // only known once the void is running
+            PreparedArg::Pid => vec!["<pid>".to_string()],
+            PreparedArg::Literal(s) => vec![s.to_string_lossy().into_owned()],
+            PreparedArg::Trigger => trigger.describe(),
+            PreparedArg::Trailing => spawner.binary_args.iter().map(|s| s.to_string()).collect(),
+            PreparedArg::TrailingAt(index) => spawner
+                .binary_args
+                .get(*index)
+                .map(|s| s.to_string())
+                .into_iter()
+                .collect(),
+            PreparedArg::CpuQuota(cpus) => vec![cpus.to_string()],
+
+            arg => arg
+                .as_raw_fd()
+                .map(|fd| fd.to_string())
+                .into_iter()
+                .collect(),
+        }
+    }
+
     /**
      * Complete argument preparation in the void
      */
@@ -215,6 +569,7 @@ impl PreparedArg {
         self,
         spawner: &Spawner,
         entrypoint: &str,
+        instance: u32,
         trigger: &mut TriggerData,
     ) -> Result<Vec<CString>> {
         match self {
@@ -224,13 +579,18 @@ impl PreparedArg {
                 ])
             }
             PreparedArg::Entrypoint => Ok(vec![CString::new(entrypoint).unwrap()]),
+            PreparedArg::InstanceId => Ok(vec![CString::new(instance.to_string()).unwrap()]),
+            PreparedArg::Pid => Ok(vec![CString::new(getpid().to_string()).unwrap()]),
+            PreparedArg::Literal(s) => Ok(vec![s]),
 
             PreparedArg::Pipe(p) => Ok(vec![CString::new(p.into_raw_fd().to_string()).unwrap()]),
             PreparedArg::FileSocket(s) => {
                 Ok(vec![CString::new(s.into_raw_fd().to_string()).unwrap()])
             }
+            PreparedArg::Ready(p) => Ok(vec![CString::new(p.into_raw_fd().to_string()).unwrap()]),
 
             PreparedArg::File(f) => Ok(vec![CString::new(f.into_raw_fd().to_string()).unwrap()]),
+            PreparedArg::Secret(f) => Ok(vec![CString::new(f.into_raw_fd().to_string()).unwrap()]),
 
             PreparedArg::Trigger => Ok(trigger.args()),
 
@@ -238,6 +598,18 @@ impl PreparedArg {
                 Ok(vec![CString::new(socket.into_raw_fd().to_string()).unwrap()])
             }
 
+            PreparedArg::TcpConnect { socket } => {
+                Ok(vec![CString::new(socket.into_raw_fd().to_string()).unwrap()])
+            }
+
+            PreparedArg::UdpSocket { socket } => {
+                Ok(vec![CString::new(socket.into_raw_fd().to_string()).unwrap()])
+            }
+
+            PreparedArg::UnixListener { socket } => {
+                Ok(vec![CString::new(socket.into_raw_fd().to_string()).unwrap()])
+            }
+
             PreparedArg::Rpc { socket } => {
                 Ok(vec![CString::new(socket.into_raw_fd().to_string()).unwrap()])
             }
@@ -247,6 +619,94 @@ impl PreparedArg {
                 .iter()
                 .map(|s| CString::new(*s).unwrap())
                 .collect()),
+
+            PreparedArg::TrailingAt(index) => Ok(spawner
+                .binary_args
+                .get(index)
+                .map(|s| CString::new(*s).unwrap())
+                .into_iter()
+                .collect()),
+
+            PreparedArg::CpuQuota(cpus) => Ok(vec![CString::new(cpus.to_string()).unwrap()]),
+
+            PreparedArg::InheritFd(fd) => Ok(vec![CString::new(fd.to_string()).unwrap()]),
+
+            PreparedArg::FixedFd { fd, arg } => {
+                let src = arg.into_raw_fd().ok_or(Error::BadFixedFd(fd))?;
+
+                if src != fd {
+                    // dup2(2) leaves the new fd without FD_CLOEXEC
+                    dup2(src, fd).map_err(|e| Error::Nix {
+                        msg: "dup2",
+                        src: e,
+                    })?;
+                    close(src).map_err(|e| Error::Nix {
+                        msg: "close",
+                        src: e,
+                    })?;
+                }
+
+                Ok(vec![CString::new(fd.to_string()).unwrap()])
+            }
         }
     }
 }
+
+/**
+ * Move fd to a new fd at or above min if it is one of targets,
+ * leaving the new fd inheritable across exec
+ */
+pub(super) fn relocate_fd<T: IntoRawFd + FromRawFd>(
+    fd: T,
+    targets: &HashSet<RawFd>,
+    min: RawFd,
+) -> Result<T> {
+    let fd = fd.into_raw_fd();
+    if !targets.contains(&fd) {
+        // SAFETY: fd was owned and has not been closed
+        return Ok(unsafe { T::from_raw_fd(fd) });
+    }
+
+    let new_fd = fcntl(fd, FcntlArg::F_DUPFD(min)).map_err(|e| Error::Nix {
+        msg: "fcntl",
+        src: e,
+    })?;
+    close(fd).map_err(|e| Error::Nix {
+        msg: "close",
+        src: e,
+    })?;
+
+    // SAFETY: valid new fd as fcntl(2) returned successfully
+    Ok(unsafe { T::from_raw_fd(new_fd) })
+}
+
+/**
+ * Bind a TCP listener with the shim's privileges, which voids never have, so
+ * ports below 1024 can be bound with CAP_NET_BIND_SERVICE.
+ */
+fn bind_tcp(addr: &SocketAddr) -> Result<TcpListener> {
+    TcpListener::bind(addr).map_err(|e| bind_error(addr, e))
+}
+
+/**
+ * Bind a UDP socket with the shim's privileges, as for bind_tcp.
+ */
+fn bind_udp(addr: &SocketAddr) -> Result<UdpSocket> {
+    UdpSocket::bind(addr).map_err(|e| bind_error(addr, e))
+}
+
+/**
+ * Name the missing capability if a port below the unprivileged range was
+ * refused, passing any other error through, such as a denial by an LSM.
+ */
+fn bind_error(addr: &SocketAddr, e: io::Error) -> Error {
+    let start = fs::read_to_string(UNPRIVILEGED_PORT_START)
+        .ok()
+        .and_then(|s| s.trim().parse::<u16>().ok())
+        .unwrap_or(1024);
+
+    match e.kind() {
+        io::ErrorKind::PermissionDenied if addr.port() < start => Error::PrivilegedPort(*addr),
+        _ => e.into(),
+    }
+}
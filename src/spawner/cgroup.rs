@@ -0,0 +1,214 @@
+use log::{debug, warn};
+
+use crate::specification::Specification;
+use crate::{Error, Result};
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Period of a cpu.max quota in microseconds, the kernel's default
+const CPU_PERIOD: u64 = 100_000;
+
+/**
+ * The cgroup (v2) the shim runs in, or None if cgroup v2 is not in use.
+ */
+pub fn own_cgroup() -> io::Result<Option<PathBuf>> {
+    let cgroup = fs::read_to_string("/proc/self/cgroup")?;
+
+    Ok(cgroup
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))
+        .map(|path| Path::new(CGROUP_ROOT).join(path.trim_start_matches('/'))))
+}
+
+/// The leaf cgroups of the entrypoints with resource limits, and what the
+/// shim changed in its own cgroup to create them. Everything is undone when
+/// dropped, unless persisted.
+pub struct Cgroups {
+    /// The cgroup the shim was started in
+    own: Option<PathBuf>,
+    /// The leaf the shim moved itself into, once it has
+    shim: Option<PathBuf>,
+    /// Controllers the shim enabled for the children of its own cgroup
+    enabled: Vec<&'static str>,
+    /// Each entrypoint's leaf, with an open directory to clone its voids into
+    leaves: HashMap<String, (PathBuf, File)>,
+    persist: bool,
+    /// The shim's pid, as a void unwinding would drop its copy
+    pid: u32,
+}
+
+impl Cgroups {
+    /// The open directory of an entrypoint's leaf, if it has limits
+    pub fn get(&self, name: &str) -> Option<&File> {
+        self.leaves.get(name).map(|(_, dir)| dir)
+    }
+
+    /// Leave the cgroups in place once dropped, for voids outliving the shim
+    pub fn persist(&mut self) {
+        self.persist = true;
+    }
+
+    /**
+     * Remove the leaves, disable the controllers the shim enabled and move
+     * the shim back into its own cgroup, in that order, as controllers can
+     * only be disabled once no child uses them and the shim can only return
+     * once none are enabled. Each step is attempted even if one fails.
+     */
+    fn restore(&mut self) {
+        for (name, (leaf, dir)) in self.leaves.drain() {
+            drop(dir);
+            debug!("removing cgroup {:?} of entrypoint `{}`", leaf, name);
+            if let Err(e) = fs::remove_dir(&leaf) {
+                warn!("failed to remove cgroup {:?}: {}", leaf, e);
+            }
+        }
+
+        let own = match &self.own {
+            Some(own) => own,
+            None => return,
+        };
+
+        if !self.enabled.is_empty() {
+            let disable: Vec<String> = self.enabled.iter().map(|c| format!("-{}", c)).collect();
+            if let Err(e) = fs::write(own.join("cgroup.subtree_control"), disable.join(" ")) {
+                warn!("failed to disable controllers in {:?}: {}", own, e);
+            }
+        }
+
+        if let Some(shim) = &self.shim {
+            debug!("moving the shim back into {:?}", own);
+            if let Err(e) = fs::write(own.join("cgroup.procs"), "0") {
+                warn!("failed to move the shim back into {:?}: {}", own, e);
+            } else if let Err(e) = fs::remove_dir(shim) {
+                warn!("failed to remove cgroup {:?}: {}", shim, e);
+            }
+        }
+    }
+}
+
+impl Drop for Cgroups {
+    fn drop(&mut self) {
+        if !self.persist && std::process::id() == self.pid {
+            self.restore();
+        }
+    }
+}
+
+/**
+ * Create a leaf cgroup for each entrypoint with resource limits, with an
+ * open directory of each to clone its voids into. Controllers can only be
+ * enabled for the children of a cgroup without processes, so the shim first
+ * moves itself into a `shim` leaf of its own cgroup, which must have been
+ * delegated to it with no other processes. Leaves are named `void-$NAME`.
+ * The returned cgroups restore the shim's own cgroup when dropped, as does
+ * a failure partway through.
+ */
+pub fn limit_entrypoints(spec: &Specification) -> Result<Cgroups> {
+    let limited: Vec<_> = spec
+        .entrypoints
+        .iter()
+        .filter(|(_, e)| e.memory_max.is_some() || e.cpu_max.is_some())
+        .collect();
+
+    let mut cgroups = Cgroups {
+        own: None,
+        shim: None,
+        enabled: Vec::new(),
+        leaves: HashMap::new(),
+        persist: false,
+        pid: std::process::id(),
+    };
+    if limited.is_empty() {
+        return Ok(cgroups);
+    }
+
+    let own = own_cgroup()?
+        .filter(|c| c.join("cgroup.controllers").exists())
+        .ok_or_else(|| Error::CgroupUnavailable("no cgroup v2 hierarchy".to_string()))?;
+
+    let memory = limited.iter().any(|(_, e)| e.memory_max.is_some());
+    let cpu = limited.iter().any(|(_, e)| e.cpu_max.is_some());
+
+    cgroups.own = Some(own.clone());
+
+    debug!("moving the shim into a leaf of {:?}", own);
+    let shim = create_leaf(&own, "shim")?;
+    fs::write(shim.join("cgroup.procs"), "0")
+        .map_err(|e| Error::CgroupUnavailable(format!("moving the shim to {:?}: {}", shim, e)))?;
+    cgroups.shim = Some(shim);
+
+    // only those not already enabled, so restoring leaves the rest alone
+    let subtree_control = fs::read_to_string(own.join("cgroup.subtree_control"))?;
+    let enabled: Vec<&str> = subtree_control.split_whitespace().collect();
+    let controllers: Vec<&'static str> = [(memory, "memory"), (cpu, "cpu")]
+        .into_iter()
+        .filter(|(used, c)| *used && !enabled.contains(c))
+        .map(|(_, c)| c)
+        .collect();
+
+    if !controllers.is_empty() {
+        let enable: Vec<String> = controllers.iter().map(|c| format!("+{}", c)).collect();
+        fs::write(own.join("cgroup.subtree_control"), enable.join(" ")).map_err(|e| {
+            Error::CgroupUnavailable(format!("enabling {} in {:?}: {}", enable.join(" "), own, e))
+        })?;
+        cgroups.enabled = controllers;
+    }
+
+    for (name, entrypoint) in limited {
+        let leaf = create_leaf(&own, &format!("void-{}", name))?;
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC)
+            .open(&leaf)?;
+        cgroups.leaves.insert(name.clone(), (leaf.clone(), dir));
+
+        // write every enabled limit, as a leaf left by a daemon may hold an old one
+        if memory {
+            let max = entrypoint.memory_max.map(|m| m.to_string());
+            fs::write(leaf.join("memory.max"), max.as_deref().unwrap_or("max"))?;
+
+            // without swap the void is killed rather than swapped out
+            match fs::write(
+                leaf.join("memory.swap.max"),
+                if max.is_some() { "0" } else { "max" },
+            ) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+        if cpu {
+            let max = entrypoint
+                .cpu_max
+                .map(|cpus| {
+                    format!(
+                        "{} {}",
+                        (cpus * CPU_PERIOD as f64).ceil() as u64,
+                        CPU_PERIOD
+                    )
+                })
+                .unwrap_or_else(|| format!("max {}", CPU_PERIOD));
+            fs::write(leaf.join("cpu.max"), max)?;
+        }
+
+        debug!("limiting entrypoint `{}` with cgroup {:?}", name, leaf);
+    }
+
+    Ok(cgroups)
+}
+
+fn create_leaf(parent: &Path, name: &str) -> Result<PathBuf> {
+    let leaf = parent.join(name);
+
+    match fs::create_dir(&leaf) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => Err(Error::CgroupUnavailable(
+            format!("creating {:?}: {}", leaf, e),
+        )),
+        _ => Ok(leaf),
+    }
+}
@@ -0,0 +1,92 @@
+use log::debug;
+
+use super::cgroup::{own_cgroup, CGROUP_ROOT};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nix::sched::{sched_getaffinity, CpuSet};
+use nix::unistd::Pid;
+
+/**
+ * The number of CPUs the shim can make use of: those in the affinity mask,
+ * limited by the cpu.max quota of the cgroup (v2) the shim runs in and each
+ * of its ancestors. Voids are cloned beneath this cgroup, so the count bounds
+ * theirs, and an entrypoint's own cpu_max may lower it further.
+ *
+ * Falls back to the affinity mask if the cgroup cannot be read.
+ */
+pub fn effective_cpus() -> usize {
+    let available = affinity_cpus();
+
+    match cgroup_cpu_limit() {
+        Ok(Some(limit)) => available.min(limit),
+        Ok(None) => available,
+        Err(e) => {
+            debug!("failed to read cgroup cpu limit: {}", e);
+            available
+        }
+    }
+}
+
+fn affinity_cpus() -> usize {
+    match sched_getaffinity(Pid::from_raw(0)) {
+        Ok(set) => (0..CpuSet::count())
+            .filter(|cpu| set.is_set(*cpu).unwrap_or(false))
+            .count()
+            .max(1),
+        Err(e) => {
+            debug!("failed to read cpu affinity: {}", e);
+            1
+        }
+    }
+}
+
+/**
+ * The tightest cpu.max quota from the shim's cgroup up to the root, rounded
+ * up to whole CPUs. None if no cgroup in the hierarchy is limited.
+ */
+fn cgroup_cpu_limit() -> io::Result<Option<usize>> {
+    let mut dir: PathBuf = match own_cgroup()? {
+        Some(dir) => dir,
+        None => return Ok(None), // cgroup v1 is not supported
+    };
+
+    let root = Path::new(CGROUP_ROOT);
+    let mut limit = None;
+
+    while dir.starts_with(root) {
+        match fs::read_to_string(dir.join("cpu.max")) {
+            Ok(max) => {
+                if let Some(cpus) = parse_cpu_max(&max) {
+                    limit = Some(limit.map_or(cpus, |l: usize| l.min(cpus)));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        if !dir.pop() {
+            break;
+        }
+    }
+
+    Ok(limit)
+}
+
+/**
+ * Parse a cpu.max of the form "$MAX $PERIOD", where $MAX may be "max".
+ */
+fn parse_cpu_max(max: &str) -> Option<usize> {
+    let mut parts = max.split_whitespace();
+    let quota: u64 = parts.next()?.parse().ok()?;
+    let period: u64 = parts.next()?.parse().ok()?;
+
+    if period == 0 {
+        return None;
+    }
+
+    let cpus = (quota as f64 / period as f64).ceil();
+    Some(cpus.max(1.0) as usize)
+}
@@ -0,0 +1,205 @@
+use log::{debug, error};
+
+use crate::void::{interface_up, set_ipv4_address};
+use crate::{Error, Result};
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sched::{setns, CloneFlags};
+use nix::sys::socket::{
+    self, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType,
+};
+use nix::sys::wait::waitpid;
+use nix::unistd::{fork, pipe2, ForkResult};
+
+/// Attribute of veth link data holding the peer's ifinfomsg and attributes
+const VETH_INFO_PEER: u16 = 1;
+
+/// Size of the netlink message header
+const NLMSG_HDRLEN: usize = 16;
+
+/// Size of `struct ifinfomsg`
+const IFINFOMSG_LEN: usize = 16;
+
+/// One end of a link, as a pidfd of a process in its network namespace and
+/// the address it takes
+pub struct LinkEnd<'a> {
+    pub owner: &'a File,
+    pub address: Ipv4Addr,
+}
+
+/**
+ * Join the network namespaces of two processes with a veth pair, named
+ * `name` at both ends, addressing each end with the subnet's prefix and
+ * bringing both up. The work is done by a forked helper entering each
+ * namespace in turn, so the shim stays in its own.
+ */
+pub fn create_veth(name: &str, first: LinkEnd, second: LinkEnd, prefix: u8) -> Result<()> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::Nix {
+        msg: "pipe2",
+        src: e,
+    })?;
+
+    // SAFETY: valid new fds as pipe2(2) returned successfully
+    let (mut read, mut write) = unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) };
+
+    // SAFETY: the shim is single threaded
+    match unsafe { fork() }.map_err(|e| Error::Nix {
+        msg: "fork",
+        src: e,
+    })? {
+        ForkResult::Parent { child } => {
+            drop(write);
+
+            let mut buf = [0_u8; 4];
+            let received = read.read_exact(&mut buf);
+
+            waitpid(child, None).map_err(|e| Error::Nix {
+                msg: "waitpid",
+                src: e,
+            })?;
+
+            let errno = match received {
+                Ok(()) => i32::from_ne_bytes(buf),
+                Err(_) => Errno::ECHILD as i32,
+            };
+
+            if errno != 0 {
+                return Err(Error::LinkUnavailable {
+                    name: name.to_string(),
+                    src: Errno::from_i32(errno),
+                });
+            }
+
+            debug!("created link `{}`", name);
+            Ok(())
+        }
+        ForkResult::Child => {
+            drop(read);
+
+            let errno = match link_namespaces(name, &first, &second, prefix) {
+                Ok(()) => 0,
+                Err(e) => {
+                    error!("error creating link `{}`: {}", name, e);
+                    e as i32
+                }
+            };
+
+            let code = match write.write_all(&errno.to_ne_bytes()) {
+                Ok(()) => exitcode::OK,
+                Err(_) => exitcode::IOERR,
+            };
+            std::process::exit(code)
+        }
+    }
+}
+
+fn link_namespaces(name: &str, first: &LinkEnd, second: &LinkEnd, prefix: u8) -> nix::Result<()> {
+    // the peer is created straight into the second namespace, by an fd to it
+    setns(second.owner.as_raw_fd(), CloneFlags::CLONE_NEWNET)?;
+    let peer_ns = File::open("/proc/thread-self/ns/net").map_err(|_| Errno::last())?;
+
+    setns(first.owner.as_raw_fd(), CloneFlags::CLONE_NEWNET)?;
+    new_veth(name, peer_ns.as_raw_fd())?;
+    configure(name, first.address, prefix)?;
+
+    setns(peer_ns.as_raw_fd(), CloneFlags::CLONE_NEWNET)?;
+    configure(name, second.address, prefix)
+}
+
+/**
+ * Create a veth pair in the calling thread's network namespace, with its
+ * peer in the namespace of `peer_ns`, using an RTM_NEWLINK request.
+ */
+fn new_veth(name: &str, peer_ns: RawFd) -> nix::Result<()> {
+    let mut peer = vec![0_u8; IFINFOMSG_LEN];
+    push_attr(&mut peer, libc::IFLA_IFNAME, &nul_terminated(name));
+    push_attr(
+        &mut peer,
+        libc::IFLA_NET_NS_FD,
+        &(peer_ns as u32).to_ne_bytes(),
+    );
+
+    let mut data = Vec::new();
+    push_attr(&mut data, VETH_INFO_PEER, &peer);
+
+    let mut info = Vec::new();
+    push_attr(&mut info, libc::IFLA_INFO_KIND, b"veth");
+    push_attr(&mut info, libc::IFLA_INFO_DATA, &data);
+
+    let mut body = vec![0_u8; IFINFOMSG_LEN];
+    push_attr(&mut body, libc::IFLA_IFNAME, &nul_terminated(name));
+    push_attr(&mut body, libc::IFLA_LINKINFO, &info);
+
+    let flags = libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE | libc::NLM_F_EXCL;
+    let mut msg = Vec::with_capacity(NLMSG_HDRLEN + body.len());
+    msg.extend_from_slice(&((NLMSG_HDRLEN + body.len()) as u32).to_ne_bytes());
+    msg.extend_from_slice(&libc::RTM_NEWLINK.to_ne_bytes());
+    msg.extend_from_slice(&(flags as u16).to_ne_bytes());
+    msg.extend_from_slice(&1_u32.to_ne_bytes()); // sequence number
+    msg.extend_from_slice(&0_u32.to_ne_bytes()); // port id, the kernel's
+    msg.extend_from_slice(&body);
+
+    let fd = socket::socket(
+        AddressFamily::Netlink,
+        SockType::Raw,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::NetlinkRoute,
+    )?;
+    // SAFETY: valid new fd as socket(2) returned successfully
+    let sock = unsafe { File::from_raw_fd(fd) };
+
+    socket::sendto(
+        sock.as_raw_fd(),
+        &msg,
+        &NetlinkAddr::new(0, 0),
+        MsgFlags::empty(),
+    )?;
+
+    // the acknowledgement is an NLMSG_ERROR, whose error is zero on success
+    let mut buf = [0_u8; 1024];
+    let len = socket::recv(sock.as_raw_fd(), &mut buf, MsgFlags::empty())?;
+    if len < NLMSG_HDRLEN + 4 {
+        return Err(Errno::EBADMSG);
+    }
+
+    let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+    if kind != libc::NLMSG_ERROR as u16 {
+        return Err(Errno::EBADMSG);
+    }
+
+    let error = i32::from_ne_bytes([buf[16], buf[17], buf[18], buf[19]]);
+    match error {
+        0 => Ok(()),
+        e => Err(Errno::from_i32(-e)),
+    }
+}
+
+/// A netlink attribute, its length including the header and its data padded
+/// to four bytes
+fn push_attr(buf: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    buf.extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(data);
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+fn nul_terminated(name: &str) -> Vec<u8> {
+    let mut bytes = name.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/**
+ * Address an interface of the calling thread's network namespace and bring
+ * it up.
+ */
+fn configure(name: &str, address: Ipv4Addr, prefix: u8) -> nix::Result<()> {
+    set_ipv4_address(name, address, prefix)?;
+    interface_up(name)
+}
@@ -1,40 +1,115 @@
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
 mod args;
+mod cgroup;
+mod cpus;
+mod link;
 mod rpc;
+mod secret;
 
-use args::PreparedArgs;
+pub use cgroup::{limit_entrypoints, Cgroups};
+pub use cpus::effective_cpus;
+pub use secret::read_secrets;
+
+pub use args::BoundSockets;
+use args::{relocate_fd, PreparedArgs};
+use link::{create_veth, LinkEnd};
 use rpc::RpcHandler;
 
-use crate::specification::{Arg, Entrypoint, Environment, Specification, Trigger};
-use crate::void::VoidBuilder;
+use crate::specification::{
+    Arg, Entrypoint, Environment, Namespace, Namespaces, Network, Overlap, Specification, Trigger,
+};
+use crate::void::{MountPlan, VoidBuilder, VoidHandle};
 use crate::{Error, Result};
-use crate::{PipePair, SocketPair};
+use crate::{PipePair, ReadyPipe, SocketPair};
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CString;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{File, OpenOptions};
+use std::io::{IoSliceMut, Read, Write};
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::path::{Path, PathBuf};
-
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::mount::MsFlags;
+use nix::poll::{poll, PollFd, PollFlags};
 use nix::sys::signal::{kill, Signal};
-use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
-use nix::sys::wait::{waitid, Id, WaitPidFlag, WaitStatus};
-use nix::unistd::{self, fork, ForkResult, Pid};
+use nix::sys::socket::{self, recvmsg, sockopt, ControlMessageOwned, MsgFlags, SockFlag};
+use nix::sys::wait::{waitid, waitpid, Id, WaitPidFlag, WaitStatus};
+use nix::unistd::{self, fork, ForkResult, Pid, Uid};
 use nix::Error as NixError;
 
-const BUFFER_SIZE: usize = 1024;
-const MAX_FILE_DESCRIPTORS: usize = 16;
+/// A whole packet of an O_DIRECT pipe, as a shorter read discards the remainder.
+/// Writes larger than this are split by the kernel and trigger once per packet.
+const BUFFER_SIZE: usize = libc::PIPE_BUF;
 
 pub struct Spawner<'a> {
     pub spec: &'a Specification,
     pub binary: &'a Path,
+    /// The binary with symlinks resolved, as mounted for each entrypoint
+    pub canonical_binary: &'a Path,
     pub binary_args: &'a Vec<&'a str>,
     pub debug: bool,
+    pub trace_builder: bool,
+    pub audit: bool,
+    /// Capture the stdout and stderr of every void through its handle
+    pub capture_output: bool,
+
+    /// CPUs available to a void, resolved with ambient authority at startup
+    /// as nested voids cannot read the cgroup hierarchy
+    pub cpus: usize,
 
     pub pipes: HashMap<String, PipePair>,
     pub sockets: HashMap<String, SocketPair>,
+    pub readiness: HashMap<String, ReadyPipe>,
+
+    /// pidfds of the voids that created each named network namespace
+    pub network_namespaces: HashMap<String, File>,
+
+    /// Leaf cgroups of the entrypoints with resource limits, created at startup
+    pub cgroups: Cgroups,
+
+    /// Sockets bound for triggered entrypoints before their trigger's void
+    pub bound_sockets: BoundSockets,
+
+    /// Contents of each secret passed by the specification, read at startup
+    pub secrets: HashMap<PathBuf, Vec<u8>>,
+
+    /// Mounts resolved for the last void of each entrypoint, reused while
+    /// its voids keep the same mounts
+    pub mount_plans: RefCell<HashMap<String, Rc<MountPlan>>>,
+
+    /// pids of the rpc handlers forked for voids, reaped by the shim
+    /// alongside the voids themselves
+    pub helpers: RefCell<HashSet<Pid>>,
+}
+
+/// What a trigger void listens on for one of its entrypoint's triggers
+enum TriggerSource {
+    /// The read end of a pipe, and whether its data is passed on as a pipe
+    Pipe {
+        pipe: File,
+        stream: bool,
+    },
+
+    /// The receiving socket of a file socket pair, any abstract listener with
+    /// the uid allowed to connect to it, and whether the data of each message
+    /// is passed on
+    FileSocket {
+        socket: File,
+        listener: Option<(File, Uid)>,
+        body: bool,
+    },
+
+    Interval {
+        period: Duration,
+        overlap: Overlap,
+    },
 }
 
 enum TriggerData<'a> {
@@ -44,8 +119,15 @@ enum TriggerData<'a> {
     /// A string sent across a pipe
     Pipe(&'a str),
 
-    /// File(s) sent over a file socket
-    FileSocket(Vec<File>),
+    /// Data sent across a pipe, passed on as the read end of a pipe holding it
+    Stream(Option<File>),
+
+    /// File(s) sent over a file socket, after the message's data if it is
+    /// passed on
+    FileSocket {
+        body: Option<&'a str>,
+        fds: Vec<File>,
+    },
 }
 
 impl<'a> TriggerData<'a> {
@@ -53,106 +135,542 @@ impl<'a> TriggerData<'a> {
         match self {
             TriggerData::None => vec![],
             TriggerData::Pipe(s) => vec![CString::new(s.to_string()).unwrap()],
-            TriggerData::FileSocket(fs) => fs
-                .drain(..)
+            TriggerData::Stream(f) => f
+                .take()
                 .map(|f| CString::new(f.into_raw_fd().to_string()).unwrap())
+                .into_iter()
+                .collect(),
+            TriggerData::FileSocket { body, fds } => body
+                .map(|b| CString::new(b).unwrap())
+                .into_iter()
+                .chain(
+                    fds.drain(..)
+                        .map(|f| CString::new(f.into_raw_fd().to_string()).unwrap()),
+                )
+                .collect(),
+        }
+    }
+
+    /**
+     * The arguments args would produce, without taking the fds, for logging
+     * before the void is spawned.
+     */
+    fn describe(&self) -> Vec<String> {
+        match self {
+            TriggerData::None => vec![],
+            TriggerData::Pipe(s) => vec![s.to_string()],
+            TriggerData::Stream(f) => f.iter().map(|f| f.as_raw_fd().to_string()).collect(),
+            TriggerData::FileSocket { body, fds } => body
+                .map(|b| b.to_string())
+                .into_iter()
+                .chain(fds.iter().map(|f| f.as_raw_fd().to_string()))
                 .collect(),
         }
     }
+
+    fn relocate(&mut self, targets: &HashSet<RawFd>, min: RawFd) -> Result<()> {
+        match self {
+            TriggerData::FileSocket { fds, .. } => {
+                *fds = fds
+                    .drain(..)
+                    .map(|f| relocate_fd(f, targets, min))
+                    .collect::<Result<_>>()?;
+            }
+            TriggerData::Stream(f) => {
+                if let Some(file) = f.take() {
+                    *f = Some(relocate_fd(file, targets, min)?);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
 }
 
 impl<'a> Spawner<'a> {
-    pub fn spawn(&mut self) -> Result<()> {
-        for (name, entrypoint) in &self.spec.entrypoints {
-            info!("spawning entrypoint `{}`", name.as_str());
+    /**
+     * Spawn every entrypoint in the specification, returning each spawned void
+     * with the name of the entrypoint it belongs to. Oneshot entrypoints are run to
+     * completion first, and any failing stops the rest being spawned. The
+     * rest are spawned in dependency order, each once its dependencies are
+     * ready, and if any fails the voids already spawned are shut down. Signal
+     * triggered entrypoints are left for the shim to spawn on each signal.
+     */
+    pub fn spawn(&mut self) -> Result<HashMap<Pid, (String, VoidHandle)>> {
+        let spec = self.spec;
+
+        let (oneshots, long_lived): (Vec<_>, Vec<_>) = spec
+            .entrypoints
+            .iter()
+            .filter(|(_, e)| !matches!(e.trigger, Trigger::Signal(_)))
+            .partition(|(_, e)| e.oneshot);
+
+        let mut pending = Vec::with_capacity(oneshots.len());
+        for (name, entrypoint) in oneshots {
+            let deadline = entrypoint.timeout().map(|t| Instant::now() + t);
+            pending.push((self.spawn_entrypoint(name, entrypoint)?, name, deadline));
+        }
 
-            match &entrypoint.trigger {
-                Trigger::Startup => {
-                    let mut builder = VoidBuilder::new();
-                    self.mount_entrypoint(&mut builder, self.binary)?;
-                    self.prepare_env(&mut builder, &entrypoint.environment);
+        let mut failed = None;
+        for (void, name, deadline) in pending {
+            if let Some(deadline) = deadline {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                if !void.wait(Some(timeout))? {
+                    warn!("oneshot entrypoint `{}` timed out, sending SIGKILL", name);
+                    // the void may have exited since, so ignore failures
+                    let _ = void.signal(Signal::SIGKILL);
+                }
+            }
 
-                    let args =
-                        PreparedArgs::prepare_ambient_mut(self, &mut builder, &entrypoint.args)?;
+            let status = waitpid(void.pid(), None).map_err(|e| Error::Nix {
+                msg: "waitpid",
+                src: e,
+            })?;
 
-                    let closure = || {
-                        if self.debug {
-                            Self::stop_self(name).unwrap()
-                        }
+            match status {
+                WaitStatus::Exited(_, 0) => info!("oneshot entrypoint `{}` completed", name),
+                status => {
+                    error!("oneshot entrypoint `{}` failed: {:?}", name, status);
+                    failed.get_or_insert(name);
+                }
+            }
+        }
 
-                        let args = args
-                            .prepare_void(self, name, &mut TriggerData::None)
-                            .unwrap();
+        if let Some(name) = failed {
+            return Err(Error::OneshotFailed(name.to_string()));
+        }
 
-                        if let Err(e) = unistd::execv(&CString::new("/entrypoint").unwrap(), &args)
-                            .map_err(|e| Error::Nix {
-                                msg: "execv",
-                                src: e,
-                            })
-                        {
-                            error!("error: {}", e);
-                            1
-                        } else {
-                            0
-                        }
-                    };
+        let order = spec.dependency_order()?;
+        let mut long_lived = long_lived;
+        long_lived.sort_by_key(|(name, _)| order.iter().position(|n| n == name));
+
+        let mut voids: HashMap<Pid, (String, VoidHandle)> =
+            HashMap::with_capacity(long_lived.len());
+        if let Err(e) = self.spawn_in_order(long_lived, &mut voids) {
+            // the voids already running would otherwise outlive the error
+            let spawned = voids.values().map(|(_, void)| void);
+            if let Err(e) = crate::shutdown(spawned, self.helpers.take()) {
+                error!("failed to shut down the spawned voids: {}", e);
+            }
+            return Err(e);
+        }
 
-                    let void = builder.spawn(closure)?;
-                    info!("spawned entrypoint `{}` as {}", name.as_str(), void);
+        Ok(voids)
+    }
+
+    /**
+     * Spawn each entrypoint in turn once its dependencies are ready, adding
+     * its voids to those spawned so far.
+     */
+    fn spawn_in_order(
+        &mut self,
+        entrypoints: Vec<(&'a String, &'a Entrypoint)>,
+        voids: &mut HashMap<Pid, (String, VoidHandle)>,
+    ) -> Result<()> {
+        let spec = self.spec;
+        let mut ready = HashSet::new();
+
+        for (name, entrypoint) in entrypoints {
+            for dependency in &entrypoint.depends_on {
+                let oneshot = spec
+                    .entrypoints
+                    .get(dependency)
+                    .ok_or_else(|| Error::BadDependency(name.to_string()))?
+                    .oneshot;
+
+                // oneshots have already completed
+                if oneshot || ready.contains(dependency) {
+                    continue;
                 }
 
-                Trigger::Pipe(s) => {
-                    let mut builder = VoidBuilder::new();
-                    self.prepare_spawner(&mut builder, &entrypoint.environment, &entrypoint.args)?;
+                self.wait_ready(
+                    dependency,
+                    voids
+                        .values()
+                        .filter(|(n, _)| n == dependency)
+                        .map(|(_, void)| void),
+                )?;
+                ready.insert(dependency);
+            }
 
-                    let pipe = self.pipes.get_mut(s).unwrap().take_read()?;
-                    builder.keep_fd(&pipe);
+            let spawned = match entrypoint.trigger {
+                Trigger::Startup => self.spawn_instances(name, entrypoint, entrypoint.instances)?,
+                _ => vec![self.spawn_entrypoint(name, entrypoint)?],
+            };
 
-                    let closure = || match self.pipe_trigger(pipe, entrypoint, name) {
-                        Ok(()) => exitcode::OK,
-                        Err(e) => {
-                            error!("error in pipe_trigger: {}", e);
-                            1
-                        }
-                    };
+            for void in spawned {
+                voids.insert(void.pid(), (name.to_string(), void));
+            }
+        }
 
-                    let void = builder.spawn(closure)?;
-                    info!(
-                        "spawned pipe trigger for entrypoint `{}` as {}",
-                        name.as_str(),
-                        void
+        Ok(())
+    }
+
+    /**
+     * Spawn a single entrypoint again, for example to restart it or when its
+     * signal is received.
+     */
+    pub fn respawn(&mut self, name: &str) -> Result<VoidHandle> {
+        let spec = self.spec;
+        let entrypoint = spec
+            .entrypoints
+            .get(name)
+            .ok_or_else(|| Error::BadEntrypoint(name.to_string()))?;
+
+        self.spawn_entrypoint(name, entrypoint)
+    }
+
+    /**
+     * Spawn instances of an entrypoint that starts straight away, each sharing
+     * the fds prepared for its arguments so they can, for example, accept on
+     * one listener.
+     */
+    fn spawn_instances(
+        &mut self,
+        name: &str,
+        entrypoint: &Entrypoint,
+        instances: u32,
+    ) -> Result<Vec<VoidHandle>> {
+        let mut builder = VoidBuilder::new();
+        self.mount_entrypoint(&mut builder, self.canonical_binary)?;
+        self.prepare_env(&mut builder, name, &entrypoint.environment)?;
+        self.prepare_output(&mut builder, false);
+        self.prepare_id_maps(&mut builder, &entrypoint.environment, false);
+        self.prepare_namespaces(&mut builder, &entrypoint.namespaces, false);
+        self.prepare_cgroup(&mut builder, name);
+        self.prepare_process(&mut builder, entrypoint);
+        if let Some(pid) = entrypoint.pid {
+            builder.set_tid(Pid::from_raw(pid));
+        }
+
+        // prepared once so every instance shares the same fds, such as a listener
+        let args = PreparedArgs::prepare_ambient_mut(self, &mut builder, name, &entrypoint.args)?;
+
+        // each void takes the args from its own copy of this cell
+        let args = RefCell::new(Some(args));
+
+        let mut voids = Vec::with_capacity(instances as usize);
+        for instance in 0..instances {
+            let closure =
+                || {
+                    if self.debug {
+                        Self::stop_self(name).unwrap()
+                    }
+
+                    let args = args
+                        .borrow_mut()
+                        .take()
+                        .unwrap()
+                        .prepare_void(self, name, instance, &mut TriggerData::None)
+                        .unwrap();
+
+                    if let Err(e) = unistd::execv(&CString::new("/entrypoint").unwrap(), &args)
+                        .map_err(|e| Error::Nix {
+                            msg: "execv",
+                            src: e,
+                        })
+                    {
+                        error!("error: {}", e);
+                        1
+                    } else {
+                        0
+                    }
+                };
+
+            self.plan_mounts(&mut builder, name)?;
+            self.log_builder(name, &builder);
+            self.audit_spawn(
+                name,
+                &builder,
+                args.borrow().as_ref().unwrap(),
+                instance,
+                &TriggerData::None,
+            );
+
+            let void = builder.spawn(closure)?;
+            info!(
+                "spawned instance {} of entrypoint `{}` as {}",
+                instance, name, void
+            );
+            self.announce_stop(name, &void, false);
+
+            // later instances join a network namespace created by the first
+            self.own_network_namespace(name, &entrypoint.namespaces, &void)?;
+            self.prepare_namespaces(&mut builder, &entrypoint.namespaces, false);
+
+            voids.push(void);
+        }
+
+        Ok(voids)
+    }
+
+    fn spawn_entrypoint(&mut self, name: &str, entrypoint: &Entrypoint) -> Result<VoidHandle> {
+        info!("spawning entrypoint `{}`", name);
+
+        let void = match &entrypoint.trigger {
+            Trigger::Startup | Trigger::Signal(_) => {
+                let mut voids = self.spawn_instances(name, entrypoint, 1)?;
+                voids.remove(0)
+            }
+
+            trigger => {
+                let mut builder = VoidBuilder::new();
+                self.prepare_spawner(&mut builder, &entrypoint.environment, &entrypoint.args)?;
+                self.prepare_output(&mut builder, false);
+                self.prepare_namespaces(&mut builder, &entrypoint.namespaces, false);
+                self.prepare_cgroup(&mut builder, name);
+                self.bound_sockets.bind(&mut builder, &entrypoint.args)?;
+
+                let mut sources = Vec::new();
+                for trigger in trigger.sources() {
+                    sources.push(self.prepare_trigger(&mut builder, trigger)?);
+                }
+
+                let closure = || self.run_triggers(sources, entrypoint, name);
+
+                self.plan_mounts(&mut builder, name)?;
+                self.log_builder(name, &builder);
+
+                let void = builder.spawn(closure)?;
+                let kind = match trigger {
+                    Trigger::Pipe(_) => "pipe trigger",
+                    Trigger::FileSocket(_) => "socket trigger",
+                    Trigger::Interval { .. } => "interval trigger",
+                    _ => "triggers",
+                };
+                info!("spawned {} for entrypoint `{}` as {}", kind, name, void);
+                if self.debug {
+                    eprintln!(
+                        "entrypoint `{}` is spawned by its trigger at PID {}",
+                        name,
+                        void.pid()
                     );
                 }
 
-                Trigger::FileSocket(s) => {
-                    let mut builder = VoidBuilder::new();
-                    self.prepare_spawner(&mut builder, &entrypoint.environment, &entrypoint.args)?;
+                void
+            }
+        };
 
-                    let socket = self.sockets.get_mut(s).unwrap().take_read()?;
-                    builder.keep_fd(&socket);
+        self.own_network_namespace(name, &entrypoint.namespaces, &void)?;
+        Ok(void)
+    }
 
-                    let closure = || match self.file_socket_trigger(socket, entrypoint, name) {
-                        Ok(()) => exitcode::OK,
-                        Err(e) => {
-                            error!("error in file_socket_trigger: {}", e);
-                            1
-                        }
-                    };
+    /**
+     * Take what a trigger listens on from the shim, passing it to the
+     * trigger's void.
+     */
+    fn prepare_trigger(
+        &mut self,
+        builder: &mut VoidBuilder,
+        trigger: &Trigger,
+    ) -> Result<TriggerSource> {
+        Ok(match trigger {
+            Trigger::Pipe(s) => {
+                let pipe = self
+                    .pipes
+                    .get_mut(s)
+                    .ok_or_else(|| Error::BadPipe(s.to_string()))?
+                    .take_read()?;
+                builder.keep_fd(&pipe);
+
+                let stream = matches!(self.spec.pipe_options.get(s), Some(o) if o.stream);
+                TriggerSource::Pipe { pipe, stream }
+            }
 
-                    let void = builder.spawn(closure)?;
-                    info!(
-                        "spawned socket trigger for entrypoint `{}` as {}",
-                        name.as_str(),
-                        void
-                    );
+            Trigger::FileSocket(s) => {
+                let pair = self
+                    .sockets
+                    .get_mut(s)
+                    .ok_or_else(|| Error::BadFileSocket(s.to_string()))?;
+                let socket = pair.take_read()?;
+                builder.keep_fd(&socket);
+
+                let options = self.spec.socket_options.get(s);
+                let listener = pair.take_listener().map(|listener| {
+                    let uid = options
+                        .and_then(|o| o.allowed_uid)
+                        .map(Uid::from_raw)
+                        .unwrap_or_else(Uid::effective);
+                    (listener, uid)
+                });
+                if let Some((listener, _)) = &listener {
+                    builder.keep_fd(listener);
+                }
+
+                let body = matches!(options, Some(o) if o.body);
+                TriggerSource::FileSocket {
+                    socket,
+                    listener,
+                    body,
+                }
+            }
+
+            Trigger::Interval { period_ms, overlap } => TriggerSource::Interval {
+                period: Duration::from_millis(*period_ms),
+                overlap: *overlap,
+            },
+
+            // rejected by validation
+            _ => unreachable!("not a trigger void's trigger: {:?}", trigger),
+        })
+    }
+
+    /**
+     * Listen on every source of a trigger void, each but the last in a forked
+     * process of its own. The void runs until every source has stopped.
+     */
+    fn run_triggers(
+        &self,
+        sources: Vec<TriggerSource>,
+        entrypoint: &Entrypoint,
+        name: &str,
+    ) -> i32 {
+        let mut sources = sources;
+        let last = sources.pop().expect("a trigger void has a source");
+
+        for source in sources {
+            // SAFETY: only unsafe in a multi-threaded program
+            match unsafe { fork() } {
+                Ok(ForkResult::Child) => {
+                    std::process::exit(self.run_trigger(source, entrypoint, name))
+                }
+                Ok(ForkResult::Parent { .. }) => {}
+                Err(e) => {
+                    error!("error forking for a trigger: {}", e);
+                    return 1;
                 }
             }
         }
 
-        Ok(())
+        self.run_trigger(last, entrypoint, name)
     }
 
-    fn pipe_trigger(&self, mut pipe: File, spec: &Entrypoint, name: &str) -> Result<()> {
+    fn run_trigger(&self, source: TriggerSource, entrypoint: &Entrypoint, name: &str) -> i32 {
+        let result = match source {
+            TriggerSource::Pipe { pipe, stream } => self
+                .pipe_trigger(pipe, stream, entrypoint, name)
+                .map_err(|e| ("pipe_trigger", e)),
+            TriggerSource::FileSocket {
+                socket,
+                listener,
+                body,
+            } => self
+                .file_socket_trigger(socket, listener, body, entrypoint, name)
+                .map_err(|e| ("file_socket_trigger", e)),
+            TriggerSource::Interval { period, overlap } => self
+                .interval_trigger(period, overlap, entrypoint, name)
+                .map_err(|e| ("interval_trigger", e)),
+        };
+
+        match result {
+            Ok(()) => exitcode::OK,
+            Err((trigger, e)) => {
+                error!("error in {}: {}", trigger, e);
+                1
+            }
+        }
+    }
+
+    fn interval_trigger(
+        &self,
+        period: Duration,
+        overlap: Overlap,
+        spec: &Entrypoint,
+        name: &str,
+    ) -> Result<()> {
+        // put the work in a forked process that can handle signals
+        Self::fork_for_trigger()?;
+
+        let mut next = Instant::now() + period;
+        let mut running: Option<Pid> = None;
+
+        loop {
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+
+            if let Some(pid) = running {
+                let exited = match overlap {
+                    Overlap::Skip => WaitPidFlag::WNOHANG,
+                    Overlap::Queue => {
+                        debug!("waiting for the previous run to exit");
+                        WaitPidFlag::empty()
+                    }
+                };
+
+                match waitpid(pid, Some(exited)).map_err(|e| Error::Nix {
+                    msg: "waitpid",
+                    src: e,
+                })? {
+                    WaitStatus::StillAlive => {
+                        debug!("skipping interval as the previous run is still alive");
+                        next += period;
+                        continue;
+                    }
+                    status => debug!("previous run exited: {:?}", status),
+                }
+            }
+
+            // drop any periods missed while queued rather than running in a burst
+            let now = Instant::now();
+            while next <= now {
+                next += period;
+            }
+
+            debug!("triggering from interval");
+
+            let mut builder = VoidBuilder::new();
+            builder.mount("/entrypoint", "/entrypoint");
+
+            self.prepare_env(&mut builder, name, &spec.environment)?;
+            self.prepare_output(&mut builder, true);
+            self.prepare_id_maps(&mut builder, &spec.environment, true);
+            self.prepare_namespaces(&mut builder, &spec.namespaces, true);
+            self.prepare_process(&mut builder, spec);
+
+            let args = PreparedArgs::prepare_ambient(self, &mut builder, spec)?;
+
+            self.plan_mounts(&mut builder, name)?;
+            self.log_builder(name, &builder);
+            self.audit_spawn(name, &builder, &args, 0, &TriggerData::None);
+
+            let closure =
+                || {
+                    if self.debug {
+                        Self::stop_self(name).unwrap()
+                    }
+
+                    let args = args
+                        .prepare_void(self, name, 0, &mut TriggerData::None)
+                        .unwrap();
+
+                    if let Err(e) = unistd::execv(&CString::new("/entrypoint").unwrap(), &args)
+                        .map_err(|e| Error::Nix {
+                            msg: "execv",
+                            src: e,
+                        })
+                    {
+                        error!("error: {}", e);
+                        1
+                    } else {
+                        0
+                    }
+                };
+
+            let void = builder.spawn(closure)?;
+            info!("spawned entrypoint `{}` as {}", name, void);
+            self.announce_stop(name, &void, spec.namespaces.pid != Namespace::Host);
+
+            running = Some(void.pid());
+        }
+    }
+
+    fn pipe_trigger(
+        &self,
+        mut pipe: File,
+        stream: bool,
+        spec: &Entrypoint,
+        name: &str,
+    ) -> Result<()> {
         // put the work in a forked process that can handle signals
         Self::fork_for_trigger()?;
 
@@ -165,6 +683,21 @@ impl<'a> Spawner<'a> {
                         return Ok(());
                     }
 
+                    // a nonblocking pipe is empty, so wait for the next write
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        let mut fds = [PollFd::new(pipe.as_raw_fd(), PollFlags::POLLIN)];
+                        match poll(&mut fds, -1) {
+                            Ok(_) => continue,
+                            Err(NixError::EINTR) => return Ok(()),
+                            Err(e) => {
+                                return Err(Error::Nix {
+                                    msg: "poll",
+                                    src: e,
+                                })
+                            }
+                        }
+                    }
+
                     Err(e)
                 }
             }?;
@@ -172,14 +705,46 @@ impl<'a> Spawner<'a> {
                 return Ok(());
             }
 
+            Self::reap_exited()?;
+
+            // an argument must be a string without nul bytes
+            let data = match std::str::from_utf8(&buf[0..read_bytes]) {
+                Ok(s) if !s.contains('\0') => Some(s),
+                _ if stream => None,
+                _ => {
+                    warn!(
+                        "skipping {} bytes sent to `{}` which are not a valid argument",
+                        read_bytes, name
+                    );
+                    continue;
+                }
+            };
+
             debug!("triggering from pipe read");
 
             let mut builder = VoidBuilder::new();
             builder.mount("/entrypoint", "/entrypoint");
 
-            self.prepare_env(&mut builder, &spec.environment);
+            self.prepare_env(&mut builder, name, &spec.environment)?;
+            self.prepare_output(&mut builder, true);
+            self.prepare_id_maps(&mut builder, &spec.environment, true);
+            self.prepare_namespaces(&mut builder, &spec.namespaces, true);
+            self.prepare_process(&mut builder, spec);
+
+            let args = PreparedArgs::prepare_ambient(self, &mut builder, spec)?;
 
-            let args = PreparedArgs::prepare_ambient(self, &mut builder, &spec.args)?;
+            let mut trigger = match data {
+                Some(s) if !stream => TriggerData::Pipe(s),
+                _ => {
+                    let payload = payload_pipe(&buf[0..read_bytes])?;
+                    builder.keep_fd(&payload);
+                    TriggerData::Stream(Some(payload))
+                }
+            };
+
+            self.plan_mounts(&mut builder, name)?;
+            self.log_builder(name, &builder);
+            self.audit_spawn(name, &builder, &args, 0, &trigger);
 
             let closure =
                 || {
@@ -187,11 +752,7 @@ impl<'a> Spawner<'a> {
                         Self::stop_self(name).unwrap()
                     }
 
-                    let pipe_trigger = std::str::from_utf8(&buf[0..read_bytes]).unwrap();
-
-                    let args = args
-                        .prepare_void(self, name, &mut TriggerData::Pipe(pipe_trigger))
-                        .unwrap();
+                    let args = args.prepare_void(self, name, 0, &mut trigger).unwrap();
 
                     if let Err(e) = unistd::execv(&CString::new("/entrypoint").unwrap(), &args)
                         .map_err(|e| Error::Nix {
@@ -208,118 +769,387 @@ impl<'a> Spawner<'a> {
 
             let void = builder.spawn(closure)?;
             info!("spawned entrypoint `{}` as {}", name, void);
+            self.announce_stop(name, &void, spec.namespaces.pid != Namespace::Host);
         }
     }
 
-    fn file_socket_trigger(&self, socket: File, spec: &Entrypoint, name: &str) -> Result<()> {
+    /**
+     * Spawn a void for each message received on the socket, passing it the fds
+     * the message carries. Spawning does not wait on the void, so the next
+     * message is received straight away and handlers run concurrently.
+     */
+    fn file_socket_trigger(
+        &self,
+        socket: File,
+        listener: Option<(File, Uid)>,
+        body: bool,
+        spec: &Entrypoint,
+        name: &str,
+    ) -> Result<()> {
         // put the work in a forked process that can handle signals
         Self::fork_for_trigger()?;
 
-        let mut cmsg_buf = nix::cmsg_space!([RawFd; MAX_FILE_DESCRIPTORS]);
+        // sized at runtime as cmsg_space! only takes a type
+        let fds_len = spec.max_file_descriptors * std::mem::size_of::<RawFd>();
+        // SAFETY: CMSG_SPACE only calculates a size
+        let mut cmsg_buf = Vec::with_capacity(unsafe { libc::CMSG_SPACE(fds_len as u32) } as usize);
+
+        // connections accepted from the abstract address, each sending files as a void would
+        let mut connections: Vec<File> = Vec::new();
 
         loop {
-            let msg = match recvmsg::<()>(
-                socket.as_raw_fd(),
-                &mut [],
-                Some(&mut cmsg_buf),
-                MsgFlags::empty(),
-            ) {
-                Ok(m) => Ok(m),
+            let mut fds: Vec<PollFd> = std::iter::once(&socket)
+                .chain(listener.iter().map(|(l, _)| l))
+                .chain(connections.iter())
+                .map(|f| PollFd::new(f.as_raw_fd(), PollFlags::POLLIN))
+                .collect();
+
+            match poll(&mut fds, -1) {
+                Ok(_) => {}
+                Err(NixError::EINTR) => return Ok(()),
                 Err(e) => {
-                    if e == NixError::EINTR {
-                        return Ok(());
+                    return Err(Error::Nix {
+                        msg: "poll",
+                        src: e,
+                    })
+                }
+            }
+
+            let ready: Vec<bool> = fds
+                .iter()
+                .map(|fd| matches!(fd.revents(), Some(r) if !r.is_empty()))
+                .collect();
+            let (socket_ready, ready) = ready.split_first().unwrap();
+            let (listener_ready, connections_ready) = ready.split_at(listener.iter().len());
+
+            if *socket_ready && !self.receive_files(&socket, &mut cmsg_buf, body, spec, name)? {
+                return Ok(());
+            }
+
+            // an error from a process outside the shim only closes its connection
+            for i in (0..connections.len()).rev() {
+                if !connections_ready[i] {
+                    continue;
+                }
+
+                match self.receive_files(&connections[i], &mut cmsg_buf, body, spec, name) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        debug!("connection to socket trigger closed");
+                        connections.swap_remove(i);
+                    }
+                    Err(e) => {
+                        warn!("closing connection to socket trigger: {}", e);
+                        connections.swap_remove(i);
                     }
+                }
+            }
+
+            if let (Some((listener, uid)), [true]) = (&listener, listener_ready) {
+                match Self::accept_connection(listener, *uid) {
+                    Ok(Some(connection)) => connections.push(connection),
+                    Ok(None) => {}
+                    Err(e) => warn!("failed to accept a connection to socket trigger: {}", e),
+                }
+            }
+        }
+    }
 
-                    Err(Error::Nix {
-                        msg: "recvmsg",
+    /**
+     * Accept a connection at an abstract address, which any process in the
+     * shim's network namespace may reach, so closing it unless the peer runs
+     * as the allowed uid. Returns None for a refused connection.
+     */
+    fn accept_connection(listener: &File, uid: Uid) -> Result<Option<File>> {
+        let connection = loop {
+            match socket::accept4(listener.as_raw_fd(), SockFlag::SOCK_CLOEXEC) {
+                // SAFETY: valid new fd as accept4(2) returned successfully
+                Ok(fd) => break unsafe { File::from_raw_fd(fd) },
+                Err(NixError::EINTR) => continue,
+                Err(e) => {
+                    return Err(Error::Nix {
+                        msg: "accept4",
                         src: e,
                     })
                 }
-            }?;
+            }
+        };
+
+        let credentials = socket::getsockopt(connection.as_raw_fd(), sockopt::PeerCredentials)
+            .map_err(|e| Error::Nix {
+                msg: "getsockopt",
+                src: e,
+            })?;
 
-            debug!("triggering from socket recvmsg");
+        if credentials.uid() != uid.as_raw() {
+            warn!(
+                "refusing connection to socket trigger from uid {}",
+                credentials.uid()
+            );
+            return Ok(None);
+        }
 
+        Ok(Some(connection))
+    }
+
+    /**
+     * Receive one message from a socket, spawning a void for each set of
+     * files it carries. Returns false once the peer has closed the socket.
+     */
+    fn receive_files(
+        &self,
+        socket: &File,
+        cmsg_buf: &mut Vec<u8>,
+        body: bool,
+        spec: &Entrypoint,
+        name: &str,
+    ) -> Result<bool> {
+        // the data is always read, as a stream only passes files with the
+        // bytes they were sent with, but only passed on if requested
+        let mut data = [0; BUFFER_SIZE];
+        let msg = match recvmsg::<()>(
+            socket.as_raw_fd(),
+            &mut [IoSliceMut::new(&mut data)],
+            Some(cmsg_buf),
+            MsgFlags::empty(),
+        ) {
+            Ok(m) => m,
+            Err(NixError::EINTR) => return Ok(false),
+            Err(e) => {
+                return Err(Error::Nix {
+                    msg: "recvmsg",
+                    src: e,
+                })
+            }
+        };
+
+        // a closed peer reads as an empty message without any fds
+        if msg.bytes == 0 && msg.cmsgs().next().is_none() {
+            return Ok(false);
+        }
+
+        let received: usize = msg
+            .cmsgs()
+            .map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmRights(fds) => fds.len(),
+                _ => 0,
+            })
+            .sum();
+
+        // the buffer is padded for alignment, so may have fit more than the max
+        let truncated_fds =
+            msg.flags.contains(MsgFlags::MSG_CTRUNC) || received > spec.max_file_descriptors;
+        // a datagram longer than the buffer has the rest of its data discarded
+        let truncated_data = msg.flags.contains(MsgFlags::MSG_TRUNC);
+
+        if truncated_fds || truncated_data {
+            // close the fds that did fit, which are of no use without the rest
             for cmsg in msg.cmsgs() {
-                match cmsg {
-                    ControlMessageOwned::ScmRights(fds) => {
-                        let fds = fds
-                            .into_iter()
-                            .map(|fd| unsafe { File::from_raw_fd(fd) })
-                            .collect();
-
-                        let mut builder = VoidBuilder::new();
-                        builder.mount("/entrypoint", "/entrypoint");
-                        for fd in &fds {
-                            builder.keep_fd(fd);
-                        }
+                if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                    fds.into_iter()
+                        .for_each(|fd| drop(unsafe { File::from_raw_fd(fd) }));
+                }
+            }
 
-                        self.prepare_env(&mut builder, &spec.environment);
+            return Err(if truncated_fds {
+                Error::TruncatedFileDescriptors(name.to_string())
+            } else {
+                Error::TruncatedMessage(name.to_string())
+            });
+        }
 
-                        let args = PreparedArgs::prepare_ambient(self, &mut builder, &spec.args)?;
+        // an argument must be a string without nul bytes
+        let body = match std::str::from_utf8(&data[0..msg.bytes]) {
+            _ if !body => None,
+            Ok(s) if !s.contains('\0') => Some(s),
+            _ => {
+                warn!(
+                    "skipping a message of {} bytes sent to `{}` which are not a valid argument",
+                    msg.bytes, name
+                );
+                for cmsg in msg.cmsgs() {
+                    if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                        fds.into_iter()
+                            .for_each(|fd| drop(unsafe { File::from_raw_fd(fd) }));
+                    }
+                }
+                return Ok(true);
+            }
+        };
 
-                        let closure = || {
-                            if self.debug {
-                                Self::stop_self(name).unwrap()
-                            }
+        Self::reap_exited()?;
 
-                            let args = args
-                                .prepare_void(self, name, &mut TriggerData::FileSocket(fds))
-                                .unwrap();
-
-                            if let Err(e) =
-                                unistd::execv(&CString::new("/entrypoint").unwrap(), &args).map_err(
-                                    |e| Error::Nix {
-                                        msg: "execv",
-                                        src: e,
-                                    },
-                                )
-                            {
-                                error!("error: {}", e);
-                                1
-                            } else {
-                                0
-                            }
-                        };
+        debug!("triggering from socket recvmsg");
 
-                        let void = builder.spawn(closure)?;
-                        info!("spawned entrypoint `{}` as {}", name, void);
+        for cmsg in msg.cmsgs() {
+            match cmsg {
+                ControlMessageOwned::ScmRights(fds) => {
+                    let fds = fds
+                        .into_iter()
+                        .map(|fd| unsafe { File::from_raw_fd(fd) })
+                        .collect();
+
+                    let mut builder = VoidBuilder::new();
+                    builder.mount("/entrypoint", "/entrypoint");
+                    for fd in &fds {
+                        builder.keep_fd(fd);
                     }
-                    _ => unimplemented!(),
+
+                    self.prepare_env(&mut builder, name, &spec.environment)?;
+                    self.prepare_output(&mut builder, true);
+                    self.prepare_id_maps(&mut builder, &spec.environment, true);
+                    self.prepare_namespaces(&mut builder, &spec.namespaces, true);
+                    self.prepare_process(&mut builder, spec);
+
+                    let args = PreparedArgs::prepare_ambient(self, &mut builder, spec)?;
+                    let mut trigger = TriggerData::FileSocket { body, fds };
+
+                    self.plan_mounts(&mut builder, name)?;
+                    self.log_builder(name, &builder);
+                    self.audit_spawn(name, &builder, &args, 0, &trigger);
+
+                    let closure = || {
+                        if self.debug {
+                            Self::stop_self(name).unwrap()
+                        }
+
+                        let args = args.prepare_void(self, name, 0, &mut trigger).unwrap();
+
+                        if let Err(e) = unistd::execv(&CString::new("/entrypoint").unwrap(), &args)
+                            .map_err(|e| Error::Nix {
+                                msg: "execv",
+                                src: e,
+                            })
+                        {
+                            error!("error: {}", e);
+                            1
+                        } else {
+                            0
+                        }
+                    };
+
+                    let void = builder.spawn(closure)?;
+                    info!("spawned entrypoint `{}` as {}", name, void);
+                    self.announce_stop(name, &void, spec.namespaces.pid != Namespace::Host);
+                }
+                ControlMessageOwned::ScmCredentials(creds) => {
+                    // nothing consumes credentials yet, this is where they will arrive
+                    debug!(
+                        "ignoring credentials from pid {} (uid {}, gid {})",
+                        creds.pid(),
+                        creds.uid(),
+                        creds.gid()
+                    );
                 }
+                cmsg => warn!("ignoring unexpected control message: {:?}", cmsg),
             }
         }
+
+        Ok(true)
     }
 
     fn fork_for_trigger() -> Result<()> {
         // SAFETY: only unsafe in a multi-threaded program
-        if let ForkResult::Parent { child: _pid } = unsafe { fork() }.map_err(|e| Error::Nix {
+        if let ForkResult::Parent { child } = unsafe { fork() }.map_err(|e| Error::Nix {
             msg: "fork",
             src: e,
         })? {
-            let status = waitid(Id::All, WaitPidFlag::WEXITED).map_err(|e| Error::Nix {
-                msg: "waitpid",
-                src: e,
-            })?;
+            // as init of the pid namespace, keep reaping until every spawned void
+            // has exited, as exiting kills everything remaining in the namespace
+            let mut exit_code = -1;
+
+            loop {
+                let status = match waitid(Id::All, WaitPidFlag::WEXITED) {
+                    Ok(v) => Ok(v),
+                    Err(NixError::ECHILD) => std::process::exit(exit_code),
+                    Err(e) => Err(Error::Nix {
+                        msg: "waitpid",
+                        src: e,
+                    }),
+                }?;
 
-            match status {
-                WaitStatus::Exited(_pid, code) => {
-                    std::process::exit(code);
-                }
-                WaitStatus::Signaled(pid, sig, _coredump) => {
-                    debug!(
-                        "trigger: forked child {} was terminated with signal {}",
-                        pid, sig
-                    );
-                    std::process::exit(-1);
+                match status {
+                    WaitStatus::Exited(pid, code) => {
+                        if pid == child {
+                            exit_code = code;
+                        }
+                    }
+                    WaitStatus::Signaled(pid, sig, _coredump) => {
+                        debug!("trigger: child {} was terminated with signal {}", pid, sig);
+                    }
+                    _ => unreachable!(),
                 }
-                _ => unreachable!(),
             }
         }
 
         Ok(())
     }
 
+    /**
+     * Reap any voids spawned by a trigger that have exited, without waiting on
+     * those still running.
+     */
+    fn reap_exited() -> Result<()> {
+        loop {
+            match waitid(Id::All, WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG) {
+                Ok(WaitStatus::StillAlive) | Err(NixError::ECHILD) => return Ok(()),
+                Ok(status) => debug!("trigger: reaped {:?}", status),
+                Err(e) => {
+                    return Err(Error::Nix {
+                        msg: "waitpid",
+                        src: e,
+                    })
+                }
+            }
+        }
+    }
+
+    fn log_builder(&self, name: &str, builder: &VoidBuilder) {
+        if self.trace_builder {
+            info!("spawning void for entrypoint `{}`: {:#?}", name, builder);
+        }
+    }
+
+    /**
+     * Log the argv and mounts a void is spawned with when auditing, from the
+     * shim before the void is cloned. Skipped otherwise, as arguments may hold
+     * secrets.
+     */
+    fn audit_spawn(
+        &self,
+        name: &str,
+        builder: &VoidBuilder,
+        args: &PreparedArgs,
+        instance: u32,
+        trigger: &TriggerData,
+    ) {
+        if !self.audit {
+            return;
+        }
+
+        let mounts: Vec<String> = builder
+            .mounts()
+            .map(|(src, dst)| format!("{} -> {}", src.display(), dst.display()))
+            .collect();
+        info!(
+            "spawning entrypoint `{}` as {:?} with mounts [{}]",
+            name,
+            args.describe(self, name, instance, trigger),
+            mounts.join(", ")
+        );
+    }
+
     fn stop_self(name: &str) -> Result<()> {
+        // under Yama a debugger can otherwise only attach to its own descendants
+        // SAFETY: PR_SET_PTRACER takes no pointers
+        if unsafe { libc::prctl(libc::PR_SET_PTRACER, libc::PR_SET_PTRACER_ANY, 0, 0, 0) } == -1 {
+            debug!(
+                "not allowing any process to trace `{}`: {}",
+                name,
+                Errno::last()
+            );
+        }
+
         info!("stopping process `{}`", name);
 
         kill(Pid::this(), Signal::SIGSTOP).map_err(|e| Error::Nix {
@@ -331,8 +1161,54 @@ impl<'a> Spawner<'a> {
         Ok(())
     }
 
+    /**
+     * Tell the user which pid to attach a debugger to for a void stopped by
+     * `--debug`. Written straight to stderr, as the logs of a busy shim bury
+     * it. A void spawned by a trigger is numbered in the trigger's pid
+     * namespace unless it shares the host's, in which case `nested` is set.
+     */
+    fn announce_stop(&self, name: &str, void: &VoidHandle, nested: bool) {
+        if !self.debug {
+            return;
+        }
+
+        if nested {
+            eprintln!(
+                "attach to PID {} in the pid namespace of its trigger for entrypoint `{}` (SIGCONT to resume)",
+                void.pid(),
+                name
+            );
+        } else {
+            eprintln!(
+                "attach to PID {} for entrypoint `{}` (SIGCONT to resume)",
+                void.pid(),
+                name
+            );
+        }
+    }
+
+    /**
+     * Give the builder the resolved mounts of the entrypoint's last void,
+     * resolving them again only if its mounts have changed. Triggered
+     * entrypoints spawn a void per trigger, each with the same mounts.
+     */
+    fn plan_mounts(&self, builder: &mut VoidBuilder, name: &str) -> Result<()> {
+        let mut plans = self.mount_plans.borrow_mut();
+
+        let plan = match plans.get(name) {
+            Some(plan) if plan.matches(builder) => plan.clone(),
+            _ => {
+                let plan = Rc::new(builder.plan_mounts()?);
+                plans.insert(name.to_string(), plan.clone());
+                plan
+            }
+        };
+
+        builder.mount_plan(plan);
+        Ok(())
+    }
+
     fn mount_entrypoint(&self, builder: &mut VoidBuilder, binary: &Path) -> Result<()> {
-        let binary = PathBuf::from(binary).canonicalize()?;
         builder.mount(binary, "/entrypoint");
 
         Ok(())
@@ -345,43 +1221,93 @@ impl<'a> Spawner<'a> {
         arguments: impl IntoIterator<Item = &'b Arg>,
     ) {
         for env in environment {
-            if let Environment::Filesystem {
-                host_path,
-                environment_path: _,
-            } = env
-            {
-                builder.mount(host_path, host_path);
+            match env {
+                Environment::Filesystem { host_path, .. } => {
+                    builder.mount(host_path, host_path);
+                }
+                Environment::Tty => {
+                    builder.mount("/dev/tty", "/dev/tty");
+                }
+                Environment::StdoutFile(path) | Environment::StderrFile(path) => {
+                    builder.mount(path, path);
+                }
+                _ => {}
             }
         }
 
         for arg in arguments {
-            if let Arg::File(host_path) = arg {
-                builder.mount(host_path, host_path);
+            if let Arg::File(file) = arg.inner() {
+                builder.mount(&file.path, &file.path);
             }
         }
     }
 
+    /**
+     * Check writable files can be opened as requested with ambient authority,
+     * creating them if necessary so they exist to be mounted.
+     */
+    fn prepare_files<'b>(
+        &self,
+        environment: impl IntoIterator<Item = &'b Environment>,
+        arguments: impl IntoIterator<Item = &'b Arg>,
+    ) -> Result<()> {
+        for env in environment {
+            if let Environment::StdoutFile(path) | Environment::StderrFile(path) = env {
+                open_output(path)?;
+            }
+        }
+
+        for arg in arguments {
+            if let Arg::File(file) = arg.inner() {
+                if file.writable {
+                    file.open()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn forward_files<'b>(
         &self,
         builder: &mut VoidBuilder,
         arguments: impl IntoIterator<Item = &'b Arg>,
-    ) {
+    ) -> Result<()> {
         for arg in arguments {
-            if let Arg::FileSocket(socket) = arg {
-                builder.keep_fd(self.sockets.get(socket.get_name()).unwrap().write_ref());
+            match arg.inner() {
+                Arg::FileSocket(socket) => {
+                    let name = socket.get_name();
+                    let pair = self
+                        .sockets
+                        .get(name)
+                        .ok_or_else(|| Error::BadFileSocket(name.to_string()))?;
+
+                    builder.keep_fd(pair.write_ref());
+                }
+
+                // the trigger's voids take the fd from the trigger's void
+                Arg::InheritFd(fd) => {
+                    builder.keep_fd(fd);
+                }
+
+                _ => {}
             }
         }
+
+        Ok(())
     }
 
     fn prepare_spawner<'b>(
         &self,
         builder: &mut VoidBuilder,
-        environment: impl IntoIterator<Item = &'b Environment>,
+        environment: impl IntoIterator<Item = &'b Environment> + Copy,
         args: impl IntoIterator<Item = &'b Arg> + Copy,
     ) -> Result<()> {
-        self.mount_entrypoint(builder, self.binary)?;
+        self.mount_entrypoint(builder, self.canonical_binary)?;
+        self.prepare_files(environment, args)?;
         self.forward_mounts(builder, environment, args);
-        self.forward_files(builder, args);
+        self.forward_files(builder, args)?;
+        self.prepare_id_maps(builder, environment, false);
 
         builder.mount("/dev/null", "/dev/null");
         builder.mount("/proc", "/proc").remount_proc();
@@ -389,32 +1315,73 @@ impl<'a> Spawner<'a> {
         builder.keep_fd(&1);
         builder.keep_fd(&2);
 
+        // voids can only share the network namespace of the trigger's void
+        if environment.into_iter().any(|env| {
+            matches!(
+                env,
+                Environment::Network(Network::InternetV4 | Network::InternetV6)
+            )
+        }) {
+            builder.share_network();
+        }
+
+        // stdout and stderr carry the trigger's logs, but stdin is only
+        // forwarded for the voids of entrypoints that ask for it
+        if environment
+            .into_iter()
+            .any(|env| env == &Environment::Stdin)
+        {
+            builder.keep_fd(&0);
+        }
+
         Ok(())
     }
 
     fn prepare_env<'b>(
         &self,
         builder: &mut VoidBuilder,
+        name: &str,
         environment: impl IntoIterator<Item = &'b Environment>,
-    ) {
+    ) -> Result<()> {
+        // {pid} is left for the void to replace once it exists
+        if let Some(hostname) = &self.spec.hostname {
+            builder.set_hostname(hostname.replace("{entrypoint}", name));
+        }
+        if let Some(domain_name) = &self.spec.domain_name {
+            builder.set_domain_name(domain_name.replace("{entrypoint}", name));
+        }
+
         for env in environment {
             match env {
                 Environment::Filesystem {
                     host_path,
                     environment_path,
+                    noexec,
+                    nosuid,
+                    nodev,
+                    readonly,
                 } => {
-                    builder.mount(host_path, environment_path);
+                    let mut flags = MsFlags::empty();
+                    flags.set(MsFlags::MS_NOEXEC, *noexec);
+                    flags.set(MsFlags::MS_NOSUID, *nosuid);
+                    flags.set(MsFlags::MS_NODEV, *nodev);
+                    flags.set(MsFlags::MS_RDONLY, *readonly);
+
+                    builder.mount_with_flags(host_path, environment_path, flags);
                 }
 
-                Environment::Hostname(name) => {
-                    builder.set_hostname(name);
+                Environment::Hostname(hostname) => {
+                    builder.set_hostname(hostname.replace("{entrypoint}", name));
                 }
-                Environment::DomainName(name) => {
-                    builder.set_domain_name(name);
+                Environment::DomainName(domain_name) => {
+                    builder.set_domain_name(domain_name.replace("{entrypoint}", name));
                 }
 
-                Environment::Procfs => {
+                Environment::Procfs { masked } => {
                     builder.mount("/proc", "/proc").remount_proc();
+                    if *masked {
+                        builder.mask_proc();
+                    }
                 }
 
                 Environment::Stdin => {
@@ -426,7 +1393,336 @@ impl<'a> Spawner<'a> {
                 Environment::Stderr => {
                     builder.keep_fd(&2);
                 }
+                Environment::StdoutFile(path) => {
+                    builder.redirect_fd(1, open_output(path)?);
+                }
+                Environment::StderrFile(path) => {
+                    builder.redirect_fd(2, open_output(path)?);
+                }
+
+                Environment::Tty => {
+                    warn!("passing the controlling terminal into a void, this is intended for debugging only");
+                    builder.attach_tty();
+                }
+
+                // handled by prepare_id_maps
+                Environment::UidMap { .. } | Environment::GidMap { .. } => {}
+
+                Environment::Network(Network::InternetV4 | Network::InternetV6) => {
+                    builder.share_network();
+                }
+                Environment::Network(network) => {
+                    if let Some((address, prefix)) = network.private_host(1) {
+                        builder.loopback_address(address, prefix);
+                    }
+                }
             }
         }
+
+        Ok(())
     }
+
+    /**
+     * Share the namespaces the entrypoint opts out of. Nested voids can only
+     * share with their parent, so trigger voids must share the same ones.
+     */
+    fn prepare_namespaces(&self, builder: &mut VoidBuilder, namespaces: &Namespaces, nested: bool) {
+        match &namespaces.network {
+            Namespace::Void => {
+                if namespaces.loopback {
+                    builder.loopback();
+                }
+            }
+            Namespace::Host => {
+                builder.share_network();
+            }
+            // the trigger void has already joined the named namespace
+            Namespace::Named(_) if nested => {
+                builder.share_network();
+            }
+            Namespace::Named(ns) => match self.network_namespace_owner(ns) {
+                Some(owner) => {
+                    debug!("joining network namespace `{}`", ns);
+                    builder.join_network(owner.as_raw_fd());
+                }
+                // shared over loopback, so it must be up
+                None => {
+                    builder.loopback();
+                }
+            },
+        }
+
+        if namespaces.pid == Namespace::Host {
+            builder.share_pid();
+        }
+        if namespaces.ipc == Namespace::Host {
+            builder.share_ipc();
+        }
+        if namespaces.uts == Namespace::Host {
+            builder.share_uts();
+        }
+        if namespaces.cgroup == Namespace::Host {
+            builder.share_cgroup();
+        }
+        if namespaces.user == Namespace::Host {
+            builder.share_user();
+        }
+        // the trigger void's offsets already apply to the voids it spawns
+        if let (Some(time), false) = (namespaces.time, nested) {
+            builder.time_offsets(time.monotonic_ms, time.boottime_ms);
+        }
+    }
+
+    /**
+     * Block until an entrypoint writes to its readiness pipe, failing if any
+     * of its voids exits first.
+     */
+    fn wait_ready<'b>(
+        &self,
+        name: &str,
+        voids: impl IntoIterator<Item = &'b VoidHandle>,
+    ) -> Result<()> {
+        let pipe = self
+            .readiness
+            .get(name)
+            .ok_or_else(|| Error::BadDependency(name.to_string()))?;
+
+        // a pidfd becomes readable once its process exits
+        let mut fds = vec![PollFd::new(pipe.read_ref().as_raw_fd(), PollFlags::POLLIN)];
+        fds.extend(
+            voids
+                .into_iter()
+                .map(|void| PollFd::new(void.pidfd().as_raw_fd(), PollFlags::POLLIN)),
+        );
+
+        info!("waiting for entrypoint `{}` to be ready", name);
+        loop {
+            match poll(&mut fds, -1) {
+                Ok(_) => break,
+                Err(NixError::EINTR) => {}
+                Err(e) => {
+                    return Err(Error::Nix {
+                        msg: "poll",
+                        src: e,
+                    })
+                }
+            }
+        }
+
+        let readable = matches!(fds[0].revents(), Some(r) if r.contains(PollFlags::POLLIN));
+        if !readable {
+            return Err(Error::NotReady(name.to_string()));
+        }
+
+        let mut buf = [0_u8; 1];
+        pipe.read_ref().read_exact(&mut buf)?;
+
+        info!("entrypoint `{}` is ready", name);
+        Ok(())
+    }
+
+    /**
+     * The pidfd of the void that created a named network namespace, if it is
+     * still running. The namespace only lives as long as this void.
+     */
+    fn network_namespace_owner(&self, name: &str) -> Option<&File> {
+        let owner = self.network_namespaces.get(name)?;
+
+        // a pidfd becomes readable once its process exits
+        let mut fds = [PollFd::new(owner.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, 0) {
+            Ok(0) => Some(owner),
+            _ => None,
+        }
+    }
+
+    /**
+     * Record a void that created a named network namespace, so later voids
+     * naming it join the same one.
+     */
+    fn own_network_namespace(
+        &mut self,
+        name: &str,
+        namespaces: &Namespaces,
+        void: &VoidHandle,
+    ) -> Result<()> {
+        if let Namespace::Named(ns) = &namespaces.network {
+            if self.network_namespace_owner(ns).is_some() {
+                return Ok(());
+            }
+
+            info!("entrypoint `{}` created network namespace `{}`", name, ns);
+            self.network_namespaces
+                .insert(ns.clone(), void.pidfd().try_clone()?);
+
+            self.link_network_namespace(ns)?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Create the links of a newly created named network namespace whose other
+     * end already exists. A link dies with either namespace, so is created
+     * again along with whichever replaces it.
+     */
+    fn link_network_namespace(&self, ns: &str) -> Result<()> {
+        for (index, link) in self.spec.links.iter().enumerate() {
+            let (first, second) = &link.networks;
+            if first != ns && second != ns {
+                continue;
+            }
+
+            let (first_owner, second_owner) = match (
+                self.network_namespace_owner(first),
+                self.network_namespace_owner(second),
+            ) {
+                (Some(first), Some(second)) => (first, second),
+                _ => continue,
+            };
+
+            let (first_address, second_address, prefix) = link
+                .addresses()
+                .ok_or_else(|| Error::BadLink(format!("veth{}", index)))?;
+
+            info!(
+                "linking network namespaces `{}` at {} and `{}` at {}",
+                first, first_address, second, second_address
+            );
+            create_veth(
+                &format!("veth{}", index),
+                LinkEnd {
+                    owner: first_owner,
+                    address: first_address,
+                },
+                LinkEnd {
+                    owner: second_owner,
+                    address: second_address,
+                },
+                prefix,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Clone a void into its entrypoint's leaf cgroup, if it has limits. Voids
+     * spawned by triggers inherit the cgroup of the trigger's void.
+     */
+    fn prepare_cgroup(&self, builder: &mut VoidBuilder, name: &str) {
+        if let Some(dir) = self.cgroups.get(name) {
+            builder.cgroup_fd(dir);
+        }
+    }
+
+    /**
+     * The number of CPUs the voids of an entrypoint can use: those available
+     * to the shim, limited by the entrypoint's own cpu_max rounded up, as its
+     * leaf cgroup is beneath the shim's.
+     */
+    fn entrypoint_cpus(&self, entrypoint: &Entrypoint) -> usize {
+        match entrypoint.cpu_max {
+            Some(cpu_max) => self.cpus.min(cpu_max.ceil() as usize),
+            None => self.cpus,
+        }
+    }
+
+    /**
+     * Apply the entrypoint's seccomp filter, working directory, root limits,
+     * capabilities and init, which are the same however its void is spawned.
+     */
+    fn prepare_process(&self, builder: &mut VoidBuilder, entrypoint: &Entrypoint) {
+        if let Some(syscalls) = &entrypoint.seccomp {
+            builder.seccomp(syscalls);
+        }
+        if let Some(workdir) = &entrypoint.workdir {
+            builder.workdir(workdir);
+        }
+        if let Some(size) = entrypoint.root.size {
+            builder.root_size(size);
+        }
+        if let Some(inodes) = entrypoint.root.inodes {
+            builder.root_inodes(inodes);
+        }
+        builder.keep_capabilities(entrypoint.capabilities.iter().copied());
+        if entrypoint.init {
+            builder.init();
+        }
+    }
+
+    /**
+     * Capture the output of a void the shim spawns. Voids spawned by triggers
+     * instead write to their trigger's captured output, whatever their
+     * environment asks for.
+     */
+    fn prepare_output(&self, builder: &mut VoidBuilder, nested: bool) {
+        if !self.capture_output {
+            return;
+        }
+
+        if nested {
+            builder.inherit_output();
+        } else {
+            builder.capture_output();
+        }
+    }
+
+    /**
+     * Apply the id maps of the environment. A void nested within a trigger
+     * void has already had the ranges mapped from the launching namespace,
+     * so maps each range onto itself.
+     */
+    fn prepare_id_maps<'b>(
+        &self,
+        builder: &mut VoidBuilder,
+        environment: impl IntoIterator<Item = &'b Environment>,
+        nested: bool,
+    ) {
+        for env in environment {
+            match env {
+                Environment::UidMap {
+                    inside,
+                    outside,
+                    count,
+                } => {
+                    builder.uid_map(*inside, if nested { *inside } else { *outside }, *count);
+                }
+                Environment::GidMap {
+                    inside,
+                    outside,
+                    count,
+                } => {
+                    builder.gid_map(*inside, if nested { *inside } else { *outside }, *count);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/**
+ * Open a file for a void's output to be appended to, creating it if missing.
+ */
+fn open_output(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().append(true).create(true).open(path)?)
+}
+
+/**
+ * Create a pipe holding data and closed for writing, returning the read end.
+ * The data is at most a pipe packet, so it is written without blocking.
+ */
+fn payload_pipe(data: &[u8]) -> Result<File> {
+    let (read, write) = unistd::pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::Nix {
+        msg: "pipe2",
+        src: e,
+    })?;
+
+    // SAFETY: valid new fd as pipe2(2) returned successfully
+    let read = unsafe { File::from_raw_fd(read) };
+    // SAFETY: valid new fd as pipe2(2) returned successfully
+    let mut write = unsafe { File::from_raw_fd(write) };
+
+    write.write_all(data)?;
+    Ok(read)
 }
@@ -1,18 +1,27 @@
 use log::{debug, error};
 
-use crate::specification::{AddressFamily as SpecAddressFamily, RpcSpecification};
+use crate::specification::{
+    host_ip, valid_host, AddressFamily as SpecAddressFamily, RpcSpecification,
+};
 use crate::Error;
 
-use std::ffi::CStr;
+use std::ffi::{CStr, OsStr};
 use std::fs::File;
-use std::net::{TcpStream, UdpSocket};
-use std::os::raw::c_char;
-use std::os::unix::io::AsRawFd;
-
+use std::io;
+use std::io::IoSliceMut;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Component, Path};
+
+use nix::fcntl::{openat, OFlag};
 use nix::sys::socket::AddressFamily;
-use nix::sys::socket::{recv, send, sendmsg, ControlMessage, MsgFlags};
+use nix::sys::socket::{recvmsg, send, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+use nix::sys::stat::Mode;
 
 const MAX_MSG_LENGTH: usize = 4096;
+const MAX_MSG_FDS: usize = 16;
 
 pub struct RpcHandler<'a> {
     permitted_rpcs: &'a [RpcSpecification],
@@ -26,31 +35,54 @@ impl<'a> RpcHandler<'a> {
     pub(super) fn handle(&self, socket: File) -> Result<(), Error> {
         let mut buf = vec![0; MAX_MSG_LENGTH];
 
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; MAX_MSG_FDS]);
+
         loop {
-            let read_bytes =
-                recv(socket.as_raw_fd(), &mut buf, MsgFlags::empty()).map_err(|e| Error::Nix {
+            let (read_bytes, fds) = {
+                let mut iov = [IoSliceMut::new(&mut buf)];
+                let msg = recvmsg::<()>(
+                    socket.as_raw_fd(),
+                    &mut iov,
+                    Some(&mut cmsg_buf),
+                    MsgFlags::empty(),
+                )
+                .map_err(|e| Error::Nix {
                     msg: "recvmsg",
                     src: e,
                 })?;
 
-            debug!("handling rpc");
+                // take ownership of any fds sent alongside the message so they are
+                // closed if the rpc does not use them
+                let mut fds = Vec::new();
+                for cmsg in msg.cmsgs() {
+                    match cmsg {
+                        ControlMessageOwned::ScmRights(received) => fds.extend(
+                            received
+                                .into_iter()
+                                // SAFETY: valid new fds as they were just received
+                                .map(|fd| unsafe { File::from_raw_fd(fd) }),
+                        ),
+                        cmsg => debug!("ignoring unexpected control message: {:?}", cmsg),
+                    }
+                }
 
-            if read_bytes < 4 {
-                error!("received rpc too short");
-                continue;
-            }
+                // the void closing its end reads as an empty message without any fds
+                if msg.bytes == 0 && fds.is_empty() {
+                    return Ok(());
+                }
 
-            // SAFETY: safe as the enum repr is non_exhaustive so any value is valid and the buffer is long enough
-            let kind = unsafe { *(buf.as_ptr() as *const RpcKind) };
+                (msg.bytes, fds)
+            };
 
-            let fds = Vec::new();
-            if kind.num_fds() > 0 {
-                // get any fds to go alongside the message
-                // nothing which requires this currently exists
-                unimplemented!()
-            }
+            debug!("handling rpc");
 
-            let resp = handle_rpc(self.permitted_rpcs, kind, &buf[4..], &fds);
+            let resp = match RpcKind::parse(&buf[..read_bytes]) {
+                Ok(kind) => handle_rpc(self.permitted_rpcs, kind, &buf[4..read_bytes], &fds),
+                Err(error) => {
+                    error!("received rpc without a known kind");
+                    RpcResult::Error { error }
+                }
+            };
 
             let (msg, fds) = RpcResultSend::new(resp);
 
@@ -87,55 +119,216 @@ impl<'a> RpcHandler<'a> {
     }
 }
 
+/// The kind of an RPC, the native endian u32 that starts every request
 #[repr(u32)]
-#[non_exhaustive]
-#[allow(dead_code)]
 #[derive(Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
 pub enum RpcKind {
-    OpenTcpSocket,
-    OpenUdpSocket,
+    OpenTcpSocket = 0,
+    OpenUdpSocket = 1,
+    OpenUnixSocket = 2,
+    OpenFile = 3,
 }
 
 impl RpcKind {
+    fn parse(data: &[u8]) -> Result<Self, RpcError> {
+        let kind = data.get(0..4).ok_or(RpcError::BadlyFormedRequest)?;
+
+        match u32::from_ne_bytes(kind.try_into().unwrap()) {
+            0 => Ok(RpcKind::OpenTcpSocket),
+            1 => Ok(RpcKind::OpenUdpSocket),
+            2 => Ok(RpcKind::OpenUnixSocket),
+            3 => Ok(RpcKind::OpenFile),
+            _ => Err(RpcError::BadlyFormedRequest),
+        }
+    }
+
     fn num_fds(&self) -> usize {
         match self {
             RpcKind::OpenTcpSocket => 0,
             RpcKind::OpenUdpSocket => 0,
+            RpcKind::OpenUnixSocket => 0,
+            RpcKind::OpenFile => 0,
         }
     }
 }
 
-pub struct OpenSocket {
-    pub family: AddressFamily,
+/// A request to open a TCP or UDP socket: a native endian i32 address
+/// family (AF_INET or AF_INET6), a native endian u16 port, then the nul
+/// terminated host. The host is a name or an IP address without a port,
+/// and an IPv6 address may be in brackets.
+pub struct OpenSocket<'a> {
+    pub family: SpecAddressFamily,
     pub port: u16,
-    pub host: [c_char],
+    pub host: &'a str,
+}
+
+impl<'a> OpenSocket<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, RpcError> {
+        if data.len() < 6 {
+            return Err(RpcError::BadlyFormedRequest);
+        }
+
+        let family =
+            match AddressFamily::from_i32(i32::from_ne_bytes(data[0..4].try_into().unwrap())) {
+                Some(AddressFamily::Inet) => SpecAddressFamily::Inet,
+                Some(AddressFamily::Inet6) => SpecAddressFamily::Inet6,
+                _ => return Err(RpcError::BadlyFormedRequest),
+            };
+        let port = u16::from_ne_bytes(data[4..6].try_into().unwrap());
+        let host = CStr::from_bytes_with_nul(&data[6..])
+            .map_err(|_| RpcError::BadlyFormedRequest)?
+            .to_str()
+            .map_err(|_| RpcError::BadlyFormedRequest)?;
+
+        if port == 0 || !valid_host(host) || !family.permits(host) {
+            return Err(RpcError::BadlyFormedRequest);
+        }
+
+        Ok(Self { family, port, host })
+    }
+
+    /**
+     * Resolve the host to addresses of the requested family, failing with
+     * EADDRNOTAVAIL if it has none.
+     */
+    fn resolve(&self) -> Result<Vec<SocketAddr>, RpcError> {
+        let host = match host_ip(self.host) {
+            Some(ip) => ip.to_string(),
+            None => self.host.to_string(),
+        };
+
+        let addrs: Vec<SocketAddr> = (host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(io_error)?
+            .filter(|addr| match self.family {
+                SpecAddressFamily::Inet => addr.is_ipv4(),
+                SpecAddressFamily::Inet6 => addr.is_ipv6(),
+            })
+            .collect();
+
+        if addrs.is_empty() {
+            return Err(RpcError::Io {
+                errno: libc::EADDRNOTAVAIL,
+            });
+        }
+
+        Ok(addrs)
+    }
+}
+
+/// A request to connect to a Unix socket: the nul terminated path
+pub struct OpenUnixSocket<'a> {
+    pub path: &'a Path,
+}
+
+impl<'a> OpenUnixSocket<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, RpcError> {
+        let path = CStr::from_bytes_with_nul(data).map_err(|_| RpcError::BadlyFormedRequest)?;
+
+        Ok(Self {
+            path: Path::new(OsStr::from_bytes(path.to_bytes())),
+        })
+    }
+}
+
+/// A request to open a file: a native endian u32, non-zero to open the file
+/// for writing too, then the nul terminated path
+pub struct OpenFile<'a> {
+    pub write: bool,
+    pub path: &'a Path,
+}
+
+impl<'a> OpenFile<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, RpcError> {
+        if data.len() < 4 {
+            return Err(RpcError::BadlyFormedRequest);
+        }
+
+        let write = u32::from_ne_bytes(data[0..4].try_into().unwrap()) != 0;
+        let path =
+            CStr::from_bytes_with_nul(&data[4..]).map_err(|_| RpcError::BadlyFormedRequest)?;
+
+        Ok(Self {
+            write,
+            path: Path::new(OsStr::from_bytes(path.to_bytes())),
+        })
+    }
 }
 
 pub enum RpcResult {
     OpenTcpSocket { socket: TcpStream },
     OpenUdpSocket { socket: UdpSocket },
+    OpenUnixSocket { socket: UnixStream },
+    OpenFile { file: File },
 
     Error { error: RpcError },
 }
 
-pub enum RpcResultSend {
-    OpenTcpSocket,
-    OpenUdpSocket,
+/**
+ * The fixed size response sent for every RPC, in native byte order:
+ *
+ * | offset | type | field                                  |
+ * |--------|------|----------------------------------------|
+ * | 0      | u32  | tag, an `RpcResultTag`                 |
+ * | 4      | i32  | errno, 0 on success                    |
+ *
+ * Any fds produced by the RPC are sent in a message of their own
+ * (SCM_RIGHTS) immediately before the response.
+ */
+#[repr(C)]
+pub struct RpcResultSend {
+    tag: RpcResultTag,
+    errno: i32,
+}
 
-    Error { error: RpcError },
+/// The outcome of an RPC, the first field of an `RpcResultSend`
+#[repr(u32)]
+#[derive(Clone, Copy)]
+pub enum RpcResultTag {
+    /// The RPC succeeded and its fds were sent
+    Ok = 0,
+
+    /// The request could not be parsed, errno is EINVAL
+    BadlyFormedRequest = 1,
+
+    /// The request is not permitted by the specification, errno is EPERM
+    OperationNotPermitted = 2,
+
+    /// The request was permitted but failed, errno is from the failing call
+    Io = 3,
 }
 
 impl RpcResultSend {
     fn new(from: RpcResult) -> (Self, Vec<Box<dyn AsRawFd>>) {
-        match from {
-            RpcResult::OpenTcpSocket { socket } => (Self::OpenTcpSocket, vec![Box::new(socket)]),
-            RpcResult::OpenUdpSocket { socket } => (Self::OpenUdpSocket, vec![Box::new(socket)]),
-            RpcResult::Error { error } => (Self::Error { error }, vec![]),
-        }
+        let fds: Vec<Box<dyn AsRawFd>> = match from {
+            RpcResult::OpenTcpSocket { socket } => vec![Box::new(socket)],
+            RpcResult::OpenUdpSocket { socket } => vec![Box::new(socket)],
+            RpcResult::OpenUnixSocket { socket } => vec![Box::new(socket)],
+            RpcResult::OpenFile { file } => vec![Box::new(file)],
+            RpcResult::Error { error } => return (error.into(), vec![]),
+        };
+
+        let ok = Self {
+            tag: RpcResultTag::Ok,
+            errno: 0,
+        };
+        (ok, fds)
+    }
+}
+
+impl From<RpcError> for RpcResultSend {
+    fn from(error: RpcError) -> Self {
+        let (tag, errno) = match error {
+            RpcError::BadlyFormedRequest => (RpcResultTag::BadlyFormedRequest, libc::EINVAL),
+            RpcError::OperationNotPermitted => (RpcResultTag::OperationNotPermitted, libc::EPERM),
+            RpcError::Io { errno } => (RpcResultTag::Io, errno),
+        };
+
+        Self { tag, errno }
     }
 }
 
-#[repr(C)]
 pub enum RpcError {
     BadlyFormedRequest,
     OperationNotPermitted,
@@ -146,8 +339,19 @@ fn handle_rpc(
     permitted_rpcs: &[RpcSpecification],
     kind: RpcKind,
     data: &[u8],
-    _fds: &[File],
+    fds: &[File],
 ) -> RpcResult {
+    if fds.len() != kind.num_fds() {
+        error!(
+            "rpc expected {} fds but received {}",
+            kind.num_fds(),
+            fds.len()
+        );
+        return RpcResult::Error {
+            error: RpcError::BadlyFormedRequest,
+        };
+    }
+
     fn inner(
         permitted_rpcs: &[RpcSpecification],
         kind: RpcKind,
@@ -155,23 +359,43 @@ fn handle_rpc(
     ) -> Result<RpcResult, RpcError> {
         match kind {
             RpcKind::OpenTcpSocket => {
-                let data = unsafe { &*(data as *const [u8] as *const OpenSocket) };
-                if !validate_open_tcp_socket(permitted_rpcs, data)? {
+                let data = OpenSocket::parse(data)?;
+                if !validate_open_tcp_socket(permitted_rpcs, &data)? {
                     Ok(RpcResult::Error {
                         error: RpcError::OperationNotPermitted,
                     })
                 } else {
-                    handle_open_tcp_socket(data)
+                    handle_open_tcp_socket(&data)
                 }
             }
             RpcKind::OpenUdpSocket => {
-                let data = unsafe { &*(data as *const [u8] as *const OpenSocket) };
-                if !validate_open_udp_socket(permitted_rpcs, data)? {
+                let data = OpenSocket::parse(data)?;
+                if !validate_open_udp_socket(permitted_rpcs, &data)? {
                     Ok(RpcResult::Error {
                         error: RpcError::OperationNotPermitted,
                     })
                 } else {
-                    handle_open_udp_socket(data)
+                    handle_open_udp_socket(&data)
+                }
+            }
+            RpcKind::OpenUnixSocket => {
+                let data = OpenUnixSocket::parse(data)?;
+                if !validate_open_unix_socket(permitted_rpcs, &data)? {
+                    Ok(RpcResult::Error {
+                        error: RpcError::OperationNotPermitted,
+                    })
+                } else {
+                    handle_open_unix_socket(&data)
+                }
+            }
+            RpcKind::OpenFile => {
+                let data = OpenFile::parse(data)?;
+                if !validate_open_file(permitted_rpcs, &data)? {
+                    Ok(RpcResult::Error {
+                        error: RpcError::OperationNotPermitted,
+                    })
+                } else {
+                    handle_open_file(&data)
                 }
             }
         }
@@ -187,33 +411,85 @@ fn validate_open_tcp_socket(
     permitted_rpcs: &[RpcSpecification],
     req: &OpenSocket,
 ) -> Result<bool, RpcError> {
-    for each in permitted_rpcs {
-        if let RpcSpecification::OpenTcpSocket { family, port, host } = each {
-            let mut allowed = true;
+    Ok(permitted_rpcs.iter().any(|each| match each {
+        RpcSpecification::OpenTcpSocket { family, port, host } => {
+            socket_permitted(family, port, host, req)
+        }
+        _ => false,
+    }))
+}
 
-            allowed &= match family {
-                None => true,
-                Some(fam) => match req.family {
-                    AddressFamily::Inet => *fam == SpecAddressFamily::Inet,
-                    AddressFamily::Inet6 => *fam == SpecAddressFamily::Inet6,
-                    _ => false,
-                },
-            };
+fn handle_open_tcp_socket(req: &OpenSocket) -> Result<RpcResult, RpcError> {
+    let socket = TcpStream::connect(&req.resolve()?[..]).map_err(io_error)?;
 
-            allowed &= match port {
-                None => true,
-                Some(p) => req.port == *p,
-            };
+    Ok(RpcResult::OpenTcpSocket { socket })
+}
+
+fn validate_open_udp_socket(
+    permitted_rpcs: &[RpcSpecification],
+    req: &OpenSocket,
+) -> Result<bool, RpcError> {
+    Ok(permitted_rpcs.iter().any(|each| match each {
+        RpcSpecification::OpenUdpSocket { family, port, host } => {
+            socket_permitted(family, port, host, req)
+        }
+        _ => false,
+    }))
+}
+
+fn handle_open_udp_socket(req: &OpenSocket) -> Result<RpcResult, RpcError> {
+    let local: SocketAddr = match req.family {
+        SpecAddressFamily::Inet => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SpecAddressFamily::Inet6 => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let socket = UdpSocket::bind(local).map_err(io_error)?;
+    socket.connect(&req.resolve()?[..]).map_err(io_error)?;
 
-            allowed &= match host {
+    Ok(RpcResult::OpenUdpSocket { socket })
+}
+
+/**
+ * Whether a socket request matches a specification, where each field left
+ * as None permits any value. Addresses compare by value, so `::1` permits
+ * `[::1]`, and names compare ignoring case.
+ */
+fn socket_permitted(
+    family: &Option<SpecAddressFamily>,
+    port: &Option<u16>,
+    host: &Option<String>,
+    req: &OpenSocket,
+) -> bool {
+    let family = family.as_ref().map(|f| *f == req.family).unwrap_or(true);
+    let port = port.map(|p| p == req.port).unwrap_or(true);
+    let host = host
+        .as_deref()
+        .map(|h| match (host_ip(h), host_ip(req.host)) {
+            (Some(permitted), Some(requested)) => permitted == requested,
+            (None, None) => h.eq_ignore_ascii_case(req.host),
+            _ => false,
+        })
+        .unwrap_or(true);
+
+    family && port && host
+}
+
+fn io_error(e: io::Error) -> RpcError {
+    // failed name lookups carry no errno
+    RpcError::Io {
+        errno: e.raw_os_error().unwrap_or(libc::EHOSTUNREACH),
+    }
+}
+
+fn validate_open_unix_socket(
+    permitted_rpcs: &[RpcSpecification],
+    req: &OpenUnixSocket,
+) -> Result<bool, RpcError> {
+    for each in permitted_rpcs {
+        if let RpcSpecification::OpenUnixSocket { path } = each {
+            let allowed = match path {
                 None => true,
-                Some(h) => {
-                    CStr::from_bytes_with_nul(as_u8_slice(&req.host))
-                        .map_err(|_| RpcError::BadlyFormedRequest)?
-                        .to_string_lossy()
-                        .as_ref()
-                        == h
-                }
+                Some(p) => req.path == Path::new(p),
             };
 
             if allowed {
@@ -225,49 +501,31 @@ fn validate_open_tcp_socket(
     Ok(false)
 }
 
-fn handle_open_tcp_socket(req: &OpenSocket) -> Result<RpcResult, RpcError> {
-    let host = CStr::from_bytes_with_nul(as_u8_slice(&req.host))
-        .map_err(|_| RpcError::BadlyFormedRequest)?;
-    let host = host.to_str().map_err(|_| RpcError::BadlyFormedRequest)?;
-
-    let socket = TcpStream::connect(host).map_err(|e| RpcError::Io {
-        errno: e.raw_os_error().unwrap(),
+fn handle_open_unix_socket(req: &OpenUnixSocket) -> Result<RpcResult, RpcError> {
+    // a path too long for sun_path fails before connect(2), without an errno
+    let socket = UnixStream::connect(req.path).map_err(|e| RpcError::Io {
+        errno: e.raw_os_error().unwrap_or(libc::ENAMETOOLONG),
     })?;
 
-    Ok(RpcResult::OpenTcpSocket { socket })
+    Ok(RpcResult::OpenUnixSocket { socket })
 }
 
-fn validate_open_udp_socket(
+fn validate_open_file(
     permitted_rpcs: &[RpcSpecification],
-    req: &OpenSocket,
+    req: &OpenFile,
 ) -> Result<bool, RpcError> {
-    for each in permitted_rpcs {
-        if let RpcSpecification::OpenUdpSocket { family, port, host } = each {
-            let mut allowed = true;
-
-            allowed &= match family {
-                None => true,
-                Some(fam) => match req.family {
-                    AddressFamily::Inet => *fam == SpecAddressFamily::Inet,
-                    AddressFamily::Inet6 => *fam == SpecAddressFamily::Inet6,
-                    _ => false,
-                },
-            };
+    // `..` could otherwise climb out of the prefix
+    if !req.path.is_absolute() || req.path.components().any(|c| c == Component::ParentDir) {
+        return Err(RpcError::BadlyFormedRequest);
+    }
 
-            allowed &= match port {
-                None => true,
-                Some(p) => req.port == *p,
-            };
+    for each in permitted_rpcs {
+        if let RpcSpecification::OpenFile { path_prefix, write } = each {
+            let mut allowed = !req.write || *write;
 
-            allowed &= match host {
+            allowed &= match path_prefix {
                 None => true,
-                Some(h) => {
-                    CStr::from_bytes_with_nul(as_u8_slice(&req.host))
-                        .map_err(|_| RpcError::BadlyFormedRequest)?
-                        .to_string_lossy()
-                        .as_ref()
-                        == h
-                }
+                Some(p) => req.path.starts_with(p),
             };
 
             if allowed {
@@ -279,22 +537,44 @@ fn validate_open_udp_socket(
     Ok(false)
 }
 
-fn handle_open_udp_socket(req: &OpenSocket) -> Result<RpcResult, RpcError> {
-    let host = CStr::from_bytes_with_nul(as_u8_slice(&req.host))
-        .map_err(|_| RpcError::BadlyFormedRequest)?;
-    let host = host.to_str().map_err(|_| RpcError::BadlyFormedRequest)?;
-
-    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| RpcError::Io {
-        errno: e.raw_os_error().unwrap(),
-    })?;
-
-    socket.connect(host).map_err(|e| RpcError::Io {
-        errno: e.raw_os_error().unwrap(),
-    })?;
+/**
+ * Open the file one component at a time without following symlinks, so a
+ * symlink beneath a permitted prefix cannot point the open elsewhere.
+ */
+fn handle_open_file(req: &OpenFile) -> Result<RpcResult, RpcError> {
+    let mut components: Vec<&OsStr> = req
+        .path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    let name = components.pop().ok_or(RpcError::BadlyFormedRequest)?;
+
+    let open = |dir: &File, name: &OsStr, flags: OFlag| {
+        openat(
+            dir.as_raw_fd(),
+            name,
+            flags | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        // SAFETY: valid new fd as openat(2) returned successfully
+        .map(|fd| unsafe { File::from_raw_fd(fd) })
+        .map_err(|e| RpcError::Io { errno: e as i32 })
+    };
+
+    let mut dir = File::open("/").map_err(io_error)?;
+    for component in components {
+        dir = open(&dir, component, OFlag::O_PATH | OFlag::O_DIRECTORY)?;
+    }
 
-    Ok(RpcResult::OpenUdpSocket { socket })
-}
+    let mode = if req.write {
+        OFlag::O_RDWR
+    } else {
+        OFlag::O_RDONLY
+    };
+    let file = open(&dir, name, mode)?;
 
-fn as_u8_slice(s: &[c_char]) -> &[u8] {
-    unsafe { std::slice::from_raw_parts(s.as_ptr() as *const u8, s.len()) }
+    Ok(RpcResult::OpenFile { file })
 }
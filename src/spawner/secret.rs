@@ -0,0 +1,76 @@
+use log::debug;
+
+use crate::specification::{Arg, Specification};
+use crate::{Error, Result};
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+
+use nix::fcntl::{fcntl, FcntlArg, SealFlag};
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+
+/**
+ * Read every secret the specification passes with ambient authority, so a
+ * missing secret stops the shim before anything is spawned. Voids spawned
+ * by a trigger are given their secrets from this copy, as the trigger's
+ * void cannot read the host's filesystem.
+ */
+pub fn read_secrets(spec: &Specification) -> Result<HashMap<PathBuf, Vec<u8>>> {
+    let mut secrets = HashMap::new();
+
+    for entrypoint in spec.entrypoints.values() {
+        for arg in &entrypoint.args {
+            if let Arg::Secret(path) = arg.inner() {
+                if secrets.contains_key(path) {
+                    continue;
+                }
+
+                let contents = fs::read(path).map_err(|e| Error::SecretUnavailable {
+                    path: path.clone(),
+                    src: e,
+                })?;
+
+                debug!("read secret {:?}", path);
+                secrets.insert(path.clone(), contents);
+            }
+        }
+    }
+
+    Ok(secrets)
+}
+
+/**
+ * Copy a secret into an anonymous memfd, sealed so neither its contents nor
+ * its size can change, with its offset at the start.
+ */
+pub fn sealed_memfd(contents: &[u8]) -> Result<File> {
+    let fd = memfd_create(
+        &CString::new("secret").unwrap(),
+        MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+    )
+    .map_err(|e| Error::Nix {
+        msg: "memfd_create",
+        src: e,
+    })?;
+
+    // SAFETY: valid new fd as memfd_create(2) returned successfully
+    let mut memfd = unsafe { File::from_raw_fd(fd) };
+    memfd.write_all(contents)?;
+
+    let seals = SealFlag::F_SEAL_SEAL
+        | SealFlag::F_SEAL_SHRINK
+        | SealFlag::F_SEAL_GROW
+        | SealFlag::F_SEAL_WRITE;
+    fcntl(memfd.as_raw_fd(), FcntlArg::F_ADD_SEALS(seals)).map_err(|e| Error::Nix {
+        msg: "fcntl",
+        src: e,
+    })?;
+
+    memfd.seek(SeekFrom::Start(0))?;
+
+    Ok(memfd)
+}
@@ -1,22 +1,155 @@
 use log::debug;
 
-use crate::{Error, Result};
+use crate::{Error, Result, Warning};
 
 use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use caps::Capability;
 use ipnetwork::{Ipv4Network, Ipv6Network};
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::signal::Signal;
+use object::{Object, ObjectSection};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The ELF section a specification is embedded in
+pub const SPECIFICATION_SECTION: &str = ".void_spec";
+
+/// Signals the shim handles itself, or that cannot be caught at all
+const RESERVED_SIGNALS: &[Signal] = &[
+    Signal::SIGCHLD,
+    Signal::SIGTERM,
+    Signal::SIGINT,
+    Signal::SIGKILL,
+    Signal::SIGSTOP,
+];
+
+/// The only version of the specification format this shim understands
+pub const SPEC_VERSION: u32 = 1;
+
+/// Most fds the kernel passes in one socket message
+const SCM_MAX_FD: usize = 253;
+
+/// Least cpu_max, as the kernel's smallest cpu.max quota is 1ms of a 100ms period
+const MIN_CPU_MAX: f64 = 0.01;
+
+#[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Specification {
+    /// Version of the specification format, where none is the current one
+    #[serde(default)]
+    pub version: Option<u32>,
+
     pub entrypoints: HashMap<String, Entrypoint>,
+
+    /// Options for the pipes of the same name, any pipe not listed blocks
+    #[serde(default)]
+    pub pipe_options: HashMap<String, PipeOptions>,
+
+    /// Options for the file sockets of the same name
+    #[serde(default)]
+    pub socket_options: HashMap<String, SocketOptions>,
+
+    /// Hostname of voids with their own uts namespace that don't set one,
+    /// where `{entrypoint}` and `{pid}` are replaced per void
+    #[serde(default)]
+    pub hostname: Option<String>,
+
+    /// As hostname, for the domain name
+    #[serde(default)]
+    pub domain_name: Option<String>,
+
+    /// Veth pairs joining named network namespaces, each named `vethN` in
+    /// both namespaces after its position in this list
+    #[serde(default)]
+    pub links: Vec<Link>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Entrypoint {
+pub struct Link {
+    /// The named network namespaces at either end
+    pub networks: (String, String),
+
+    /// A PrivateV4 subnet addressing the ends, where the first namespace
+    /// takes its first host address and the second the next
+    pub subnet: Network,
+}
+
+impl Link {
+    /// The addresses of the first and second ends, if the subnet is usable
+    pub(crate) fn addresses(&self) -> Option<(Ipv4Addr, Ipv4Addr, u8)> {
+        match (self.subnet.private_host(1)?, self.subnet.private_host(2)?) {
+            ((IpAddr::V4(first), prefix), (IpAddr::V4(second), _)) => Some((first, second, prefix)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct SocketOptions {
+    /// Also accept connections at this abstract unix socket address in the
+    /// shim's network namespace, each sending files as a void would, so
+    /// processes outside the shim can trigger the socket's entrypoint
+    #[serde(default)]
+    pub abstract_address: Option<String>,
+
+    /// The only uid allowed to connect at the abstract address, checked with
+    /// `SO_PEERCRED`, defaulting to the effective uid of the shim
+    #[serde(default)]
+    pub allowed_uid: Option<u32>,
+
+    /// Type of the socket pair carrying files between voids
     #[serde(default)]
+    pub kind: SocketKind,
+
+    /// Pass the data of each message, which must be UTF-8 without nul bytes,
+    /// as the first argument of the entrypoint's trigger, before the files
+    #[serde(default)]
+    pub body: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum SocketKind {
+    /// Each message is received whole, and sends of files without data work
+    Datagram,
+
+    /// Data is a byte stream, and files must be sent with at least one byte
+    Stream,
+}
+
+impl Default for SocketKind {
+    fn default() -> Self {
+        Self::Datagram
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PipeOptions {
+    /// Open both ends with O_NONBLOCK, so reading an empty or writing a full
+    /// pipe fails with EAGAIN rather than waiting
+    #[serde(default)]
+    pub nonblocking: bool,
+
+    /// Capacity in bytes, rounded up to a power of two pages. Above
+    /// /proc/sys/fs/pipe-max-size requires CAP_SYS_RESOURCE.
+    #[serde(default)]
+    pub capacity: Option<usize>,
+
+    /// Pass the data that triggers an entrypoint as the read end of a new
+    /// pipe holding it, rather than as an argument, so it need not be UTF-8
+    #[serde(default)]
+    pub stream: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Entrypoint {
+    #[serde(default, deserialize_with = "Trigger::one_or_list")]
     pub trigger: Trigger,
 
     #[serde(default = "Arg::default_vec")]
@@ -24,6 +157,258 @@ pub struct Entrypoint {
 
     #[serde(default)]
     pub environment: HashSet<Environment>,
+
+    /// Allowlist of syscalls by name, any other syscall kills the void. Applied
+    /// before the entrypoint is executed, so must include `execve`. No filter if unset.
+    #[serde(default)]
+    pub seccomp: Option<Vec<String>>,
+
+    /// Capabilities kept in the bounding set of the entrypoint, all others are
+    /// dropped before it is executed.
+    #[serde(default)]
+    pub capabilities: HashSet<Capability>,
+
+    #[serde(default)]
+    pub restart: RestartPolicy,
+
+    /// Give up on the entrypoint after this many restarts in a row without it
+    /// staying up, overriding the shim's `--max-restarts`
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+
+    /// Delay before the first restart in a row, doubling for each after it,
+    /// overriding the shim's `--restart-backoff`
+    #[serde(default)]
+    pub restart_backoff_ms: Option<u64>,
+
+    /// Run to completion before any other entrypoint is spawned, aborting
+    /// startup if it fails. For setup tasks such as migrations.
+    #[serde(default)]
+    pub oneshot: bool,
+
+    /// Entrypoints that must be ready before this one is spawned. An entrypoint
+    /// is ready once it writes to its `Ready` argument, or a oneshot once it completes.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    /// Number of voids spawned at startup, which share the fds of their
+    /// arguments. For example, prefork workers accepting on one listener.
+    #[serde(default = "Entrypoint::default_instances")]
+    pub instances: u32,
+
+    /// Working directory of the entrypoint within the void, `/` if unset
+    #[serde(default)]
+    pub workdir: Option<PathBuf>,
+
+    /// Namespaces shared with the host instead of voided. Mount and user
+    /// namespaces are always voided.
+    #[serde(default)]
+    pub namespaces: Namespaces,
+
+    #[serde(default)]
+    pub root: RootLimits,
+
+    /// Most fds one message can carry to a file socket triggered entrypoint.
+    /// Messages carrying more stop the trigger rather than silently dropping some.
+    #[serde(default = "Entrypoint::default_max_file_descriptors")]
+    pub max_file_descriptors: usize,
+
+    /// Pid of the void in the shim's pid namespace, for reproducible tests.
+    /// Requires CAP_SYS_ADMIN there; the void is still pid 1 in its own.
+    #[serde(default)]
+    pub pid: Option<i32>,
+
+    /// Run a minimal init as pid 1 of the void, reaping the orphans of
+    /// entrypoints that fork. Has no effect in a shared pid namespace.
+    #[serde(default)]
+    pub init: bool,
+
+    /// Most memory in bytes the entrypoint's voids may use together before
+    /// being OOM killed, set as memory.max of a leaf cgroup for it
+    #[serde(default)]
+    pub memory_max: Option<u64>,
+
+    /// Most CPUs the entrypoint's voids may use together, which may be
+    /// fractional, set as the cpu.max quota of a leaf cgroup for it
+    #[serde(default)]
+    pub cpu_max: Option<f64>,
+
+    /// Kill each void of a startup entrypoint that has not exited within this
+    /// many milliseconds of being spawned, for batch jobs that must not run forever
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Limits on the tmpfs root of a void, which otherwise may use half of RAM
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct RootLimits {
+    /// Maximum size in bytes, rounded up to whole pages
+    #[serde(default)]
+    pub size: Option<u64>,
+
+    /// Maximum number of inodes
+    #[serde(default)]
+    pub inodes: Option<u64>,
+}
+
+impl Default for Entrypoint {
+    /// An entrypoint as a specification with only its name would give, for
+    /// building one field by field
+    fn default() -> Self {
+        Entrypoint {
+            trigger: Trigger::default(),
+            args: Arg::default_vec(),
+            environment: HashSet::new(),
+            seccomp: None,
+            capabilities: HashSet::new(),
+            restart: RestartPolicy::default(),
+            max_restarts: None,
+            restart_backoff_ms: None,
+            oneshot: false,
+            depends_on: Vec::new(),
+            instances: Entrypoint::default_instances(),
+            workdir: None,
+            namespaces: Namespaces::default(),
+            root: RootLimits::default(),
+            max_file_descriptors: Entrypoint::default_max_file_descriptors(),
+            pid: None,
+            init: false,
+            memory_max: None,
+            cpu_max: None,
+            timeout_ms: None,
+        }
+    }
+}
+
+impl Entrypoint {
+    /// How long each void of the entrypoint may run before being killed
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        match self.trigger {
+            Trigger::Startup => self.timeout_ms.map(Duration::from_millis),
+            _ => None,
+        }
+    }
+
+    fn default_instances() -> u32 {
+        1
+    }
+
+    fn default_max_file_descriptors() -> usize {
+        16
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Namespaces {
+    #[serde(default)]
+    pub network: Namespace,
+
+    #[serde(default)]
+    pub pid: Namespace,
+
+    #[serde(default)]
+    pub ipc: Namespace,
+
+    #[serde(default)]
+    pub uts: Namespace,
+
+    #[serde(default)]
+    pub cgroup: Namespace,
+
+    /// Sharing the user namespace skips mapping ids, so the void runs with
+    /// the shim's real ids. Only a shim running as root can share it, as
+    /// creating the other namespaces needs CAP_SYS_ADMIN.
+    #[serde(default)]
+    pub user: Namespace,
+
+    /// Bring up the loopback interface of a new network namespace, so the
+    /// void can reach itself over localhost
+    #[serde(default = "Namespaces::default_loopback")]
+    pub loopback: bool,
+
+    /// Give the void a new time namespace with its clocks offset, rather than
+    /// sharing the host's. Voids spawned by a trigger share their trigger's.
+    #[serde(default)]
+    pub time: Option<TimeOffsets>,
+}
+
+/// Offsets of the clocks in a time namespace, which may be negative as long
+/// as the clocks stay positive
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct TimeOffsets {
+    #[serde(default)]
+    pub monotonic_ms: i64,
+
+    #[serde(default)]
+    pub boottime_ms: i64,
+}
+
+impl Namespaces {
+    fn default_loopback() -> bool {
+        true
+    }
+}
+
+impl Default for Namespaces {
+    fn default() -> Self {
+        Self {
+            network: Namespace::default(),
+            pid: Namespace::default(),
+            ipc: Namespace::default(),
+            uts: Namespace::default(),
+            cgroup: Namespace::default(),
+            user: Namespace::default(),
+            loopback: Self::default_loopback(),
+            time: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Namespace {
+    /// Create a new, empty namespace
+    Void,
+
+    /// Share the namespace of the host
+    Host,
+
+    /// Share a new namespace between every entrypoint giving this name. Only
+    /// supported for the network namespace.
+    Named(String),
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Self::Void
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum RestartPolicy {
+    /// Leave the entrypoint exited
+    Never,
+
+    /// Restart the entrypoint if it exits unsuccessfully or is killed by a signal
+    OnFailure,
+
+    /// Restart the entrypoint whenever it exits
+    Always,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self::Never
+    }
+}
+
+impl RestartPolicy {
+    pub(crate) fn should_restart(&self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -36,6 +421,48 @@ pub enum Trigger {
 
     /// Trigger this entrypoint when a named file socket receives data
     FileSocket(String),
+
+    /// Trigger this entrypoint every period, starting one period after startup
+    Interval {
+        period_ms: u64,
+
+        #[serde(default)]
+        overlap: Overlap,
+    },
+
+    /// Trigger this entrypoint each time the shim receives this signal
+    Signal(i32),
+
+    /// Trigger this entrypoint from any of several pipe, file socket and
+    /// interval triggers, given in a specification as a list of them
+    Any(Vec<Trigger>),
+}
+
+impl Trigger {
+    /// Deserialize a trigger, or a list of them as Any. Not untagged, so
+    /// errors in a trigger are reported rather than matching neither form.
+    fn one_or_list<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Trigger, D::Error> {
+        use serde::de::Error as _;
+
+        let value = serde_json::Value::deserialize(deserializer)?;
+        if value.is_array() {
+            serde_json::from_value(value)
+                .map(Trigger::Any)
+                .map_err(D::Error::custom)
+        } else {
+            serde_json::from_value(value).map_err(D::Error::custom)
+        }
+    }
+
+    /// Each trigger that spawns the entrypoint, which is only this one unless it is Any
+    pub(crate) fn sources(&self) -> Vec<&Trigger> {
+        match self {
+            Trigger::Any(triggers) => triggers.iter().collect(),
+            trigger => vec![trigger],
+        }
+    }
 }
 
 impl Default for Trigger {
@@ -44,6 +471,22 @@ impl Default for Trigger {
     }
 }
 
+/// What an interval trigger does when the previous run is still alive
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Overlap {
+    /// Skip this run and wait for the next period
+    Skip,
+
+    /// Run as soon as the previous run exits, dropping any further missed periods
+    Queue,
+}
+
+impl Default for Overlap {
+    fn default() -> Self {
+        Self::Skip
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub enum Arg {
     /// The binary name, or argv[0], of the original program start
@@ -52,8 +495,22 @@ pub enum Arg {
     /// The name of this entrypoint
     Entrypoint,
 
+    /// The index of this instance of the entrypoint, from 0
+    InstanceId,
+
+    /// The pid of the void in its own pid namespace, 1 unless the pid
+    /// namespace is shared
+    Pid,
+
+    /// A fixed string from the specification, such as a mode flag
+    Literal(String),
+
     /// A file descriptor for a file on the filesystem in the launching namespace
-    File(PathBuf),
+    File(HostFile),
+
+    /// A file descriptor for a sealed, read-only memfd holding a copy of a
+    /// file read by the shim at startup, leaving no path to it in the void
+    Secret(PathBuf),
 
     /// A chosen end of a named pipe
     Pipe(Pipe),
@@ -68,20 +525,79 @@ pub enum Arg {
     /// A TCP Listener
     TcpListener { addr: SocketAddr },
 
+    /// A TCP stream connected from the launching namespace at spawn time
+    TcpConnect { addr: SocketAddr },
+
+    /// A bound UDP socket
+    UdpSocket { addr: SocketAddr },
+
+    /// A Unix socket listener, bound at a path in the launching namespace
+    UnixListener { path: PathBuf },
+
     /// An RPC socket that accepts specified commands
     Rpc(Vec<RpcSpecification>),
 
     /// The rest of argv[1..], 0 or more arguments
     Trailing,
+
+    /// A single argument of argv[1..], by index from 0. Fails to spawn if out
+    /// of range unless optional, when no argument is passed instead.
+    TrailingAt {
+        index: usize,
+        #[serde(default)]
+        optional: bool,
+    },
+
+    /// A pipe to write a byte to once ready, releasing the entrypoints that
+    /// depend on this one
+    Ready,
+
+    /// The number of CPUs the void can use, accounting for the cgroup cpu.max
+    /// quota and the entrypoint's cpu_max rounded up, for sizing thread pools
+    CpuQuota,
+
+    /// Another argument which produces a file descriptor, moved to a fixed
+    /// fd number in the void (for example, fd 3 for systemd style activation)
+    FixedFd { fd: RawFd, arg: Box<Arg> },
+
+    /// A file descriptor the shim was started with, passed to the void at the
+    /// same number
+    InheritFd(RawFd),
 }
 
 impl Arg {
     fn default_vec() -> Vec<Arg> {
         vec![Arg::BinaryName]
     }
+
+    /// The argument with any fixed fd wrapper removed
+    pub(crate) fn inner(&self) -> &Arg {
+        match self {
+            Arg::FixedFd { fd: _, arg } => arg,
+            arg => arg,
+        }
+    }
+
+    fn produces_fd(&self) -> bool {
+        matches!(
+            self,
+            Arg::File(_)
+                | Arg::Secret(_)
+                | Arg::Pipe(_)
+                | Arg::FileSocket(_)
+                | Arg::TcpListener { .. }
+                | Arg::TcpConnect { .. }
+                | Arg::UdpSocket { .. }
+                | Arg::UnixListener { .. }
+                | Arg::Rpc(_)
+                | Arg::Ready
+                | Arg::InheritFd(_)
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum RpcSpecification {
     /// Open a TCP socket
     ///
@@ -102,6 +618,78 @@ pub enum RpcSpecification {
         port: Option<u16>,
         host: Option<String>,
     },
+
+    /// Connect to a Unix socket in the launching namespace
+    ///
+    /// None means that any path is allowed in the call.
+    /// A specified path restricts to exactly that.
+    OpenUnixSocket { path: Option<String> },
+
+    /// Open a file in the launching namespace, read-only unless write is set
+    ///
+    /// None means that any path is allowed in the call. A specified prefix
+    /// restricts to paths beneath it. Paths cannot contain symlinks.
+    OpenFile {
+        path_prefix: Option<String>,
+        write: bool,
+    },
+}
+
+impl RpcSpecification {
+    fn validate(&self) -> Result<()> {
+        let valid = match self {
+            RpcSpecification::OpenTcpSocket { family, port, host }
+            | RpcSpecification::OpenUdpSocket { family, port, host } => {
+                *port != Some(0)
+                    && host
+                        .as_deref()
+                        .map(|h| {
+                            valid_host(h) && family.as_ref().map(|f| f.permits(h)).unwrap_or(true)
+                        })
+                        .unwrap_or(true)
+            }
+            RpcSpecification::OpenUnixSocket { path }
+            | RpcSpecification::OpenFile {
+                path_prefix: path,
+                write: _,
+            } => path
+                .as_deref()
+                .map(|p| Path::new(p).is_absolute())
+                .unwrap_or(true),
+        };
+
+        if !valid {
+            return Err(Error::BadRpcSpecification(format!("{:?}", self)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether host is an IP address or DNS name, without a port
+pub(crate) fn valid_host(host: &str) -> bool {
+    if host_ip(host).is_some() {
+        return true;
+    }
+
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// The address of a host given as an IP address, with an IPv6 address
+/// optionally in brackets
+pub(crate) fn host_ip(host: &str) -> Option<IpAddr> {
+    match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        Some(v6) => v6.parse::<Ipv6Addr>().ok().map(IpAddr::V6),
+        None => host.parse().ok(),
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -113,6 +701,73 @@ pub enum AddressFamily {
     Inet6,
 }
 
+impl AddressFamily {
+    /// Whether host can be reached with this family, which is false only for
+    /// an IP address of the other family
+    pub(crate) fn permits(&self, host: &str) -> bool {
+        match host_ip(host) {
+            Some(IpAddr::V4(_)) => *self == AddressFamily::Inet,
+            Some(IpAddr::V6(_)) => *self == AddressFamily::Inet6,
+            None => true,
+        }
+    }
+}
+
+/// A file in the launching namespace, given either as a path (opened read only)
+/// or with explicit options
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(from = "HostFileSpec")]
+pub struct HostFile {
+    pub path: PathBuf,
+    pub writable: bool,
+    pub create: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HostFileSpec {
+    Path(PathBuf),
+    Options {
+        path: PathBuf,
+        #[serde(default)]
+        writable: bool,
+        #[serde(default)]
+        create: bool,
+    },
+}
+
+impl From<HostFileSpec> for HostFile {
+    fn from(spec: HostFileSpec) -> Self {
+        match spec {
+            HostFileSpec::Path(path) => HostFile {
+                path,
+                writable: false,
+                create: false,
+            },
+            HostFileSpec::Options {
+                path,
+                writable,
+                create,
+            } => HostFile {
+                path,
+                writable,
+                create,
+            },
+        }
+    }
+}
+
+impl HostFile {
+    /// Open the file with the requested access, creating it if requested
+    pub fn open(&self) -> std::io::Result<std::fs::File> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(self.writable)
+            .create(self.create)
+            .open(&self.path)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub enum Pipe {
     Rx(String),
@@ -148,38 +803,166 @@ pub enum Environment {
     Filesystem {
         host_path: PathBuf,
         environment_path: PathBuf,
+
+        /// Refuse to execute files from the mount
+        #[serde(default)]
+        noexec: bool,
+
+        /// Ignore setuid and setgid bits on files in the mount
+        #[serde(default)]
+        nosuid: bool,
+
+        /// Refuse access to device files in the mount
+        #[serde(default)]
+        nodev: bool,
+
+        /// Refuse writes to the mount
+        #[serde(default)]
+        readonly: bool,
     },
 
+    /// Hostname of the void, where `{entrypoint}` and `{pid}` are replaced
+    /// with the entrypoint's name and the void's pid as seen by its spawner
     Hostname(String),
+    /// As Hostname, for the domain name
     DomainName(String),
 
-    Procfs,
+    /// A fresh /proc for the pid namespace, optionally with the sensitive
+    /// entries masked and the kernel tunables read-only
+    Procfs {
+        #[serde(default)]
+        masked: bool,
+    },
 
     Stdin,
     Stdout,
     Stderr,
+
+    /// Append stdout to a file in the launching namespace, created if missing,
+    /// in place of the shim's stdout
+    StdoutFile(PathBuf),
+    /// As StdoutFile, for stderr
+    StderrFile(PathBuf),
+
+    /// The controlling terminal of the shim as stdin, stdout and stderr.
+    /// An escape hatch for interactive debugging, not for production use.
+    Tty,
+
+    /// Map count uids from inside in the void to outside in the launching
+    /// namespace, replacing the default mapping of root to the launching user.
+    /// Mapping ids other than your own requires privilege.
+    UidMap {
+        inside: u32,
+        outside: u32,
+        count: u32,
+    },
+    /// As UidMap, for gids
+    GidMap {
+        inside: u32,
+        outside: u32,
+        count: u32,
+    },
+
+    /// The network the void is attached to, in place of an empty namespace
+    Network(Network),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
 pub enum Network {
+    /// Share the host's network namespace, for access to the IPv4 internet
     InternetV4,
+    /// As InternetV4, for the IPv6 internet, which shares the same namespace
     InternetV6,
+    /// A new network namespace whose loopback interface also holds the first
+    /// host address of this private subnet, so the void can bind to it
     PrivateV4(Ipv4Network),
+    /// As PrivateV4, for a unique local IPv6 subnet
     PrivateV6(Ipv6Network),
 }
 
+impl Network {
+    /// The nth host address of a private subnet with room for two hosts,
+    /// along with the subnet's prefix
+    pub(crate) fn private_host(&self, n: u32) -> Option<(IpAddr, u8)> {
+        match self {
+            Network::PrivateV4(subnet)
+                if subnet.prefix() <= 30
+                    && subnet.network().is_private()
+                    && subnet.broadcast().is_private()
+                    && (1..=2).contains(&n) =>
+            {
+                Some((IpAddr::V4(subnet.nth(n)?), subnet.prefix()))
+            }
+            Network::PrivateV6(subnet)
+                if subnet.prefix() >= 7
+                    && subnet.prefix() <= 126
+                    && subnet.network().segments()[0] & 0xfe00 == 0xfc00
+                    && (1..=2).contains(&n) =>
+            {
+                let host = u128::from(subnet.network()) + n as u128;
+                Some((IpAddr::V6(Ipv6Addr::from(host)), subnet.prefix()))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl Specification {
-    pub fn pipes(&self) -> (Vec<&str>, Vec<&str>) {
+    /// Read a specification from a JSON file, substituting `argv` placeholders
+    pub fn from_file(path: &Path, argv: &[&str]) -> Result<Specification> {
+        if path.extension().map(|e| e == "json") == Some(true) {
+            Self::parse(&fs::read(path)?, argv)
+        } else {
+            Err(Error::BadSpecType)
+        }
+    }
+
+    /// Extract the specification embedded in an ELF binary, if there is one,
+    /// substituting `argv` placeholders
+    pub fn from_elf(binary: &Path, argv: &[&str]) -> Result<Option<Specification>> {
+        let data = read_binary(binary)?;
+        let elf = object::File::parse(data.as_slice())?;
+
+        match elf.section_by_name(SPECIFICATION_SECTION) {
+            Some(section) => Ok(Some(Self::parse(section.data()?, argv)?)),
+            None => Ok(None),
+        }
+    }
+
+    /**
+     * Parse a JSON specification, replacing each `${N}` in a string with the
+     * Nth argument of the launched binary, where `${0}` is the binary itself.
+     * Substituting before deserializing lets placeholders appear inside typed
+     * values such as socket addresses.
+     */
+    fn parse(data: &[u8], argv: &[&str]) -> Result<Specification> {
+        let mut value: serde_json::Value = serde_json::from_slice(data)?;
+
+        // before deserializing, as a newer specification may not parse at all
+        match value.get("version") {
+            None | Some(serde_json::Value::Null) => {}
+            Some(v) if v.as_u64() == Some(SPEC_VERSION.into()) => {}
+            Some(v) => return Err(Error::UnsupportedSpecVersion(v.to_string())),
+        }
+
+        substitute_args(&mut value, argv)?;
+
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub(crate) fn pipes(&self) -> (Vec<&str>, Vec<&str>) {
         let mut read = Vec::new();
         let mut write = Vec::new();
 
         for entry in self.entrypoints.values() {
-            if let Trigger::Pipe(s) = &entry.trigger {
-                read.push(s.as_str());
+            for trigger in entry.trigger.sources() {
+                if let Trigger::Pipe(s) = trigger {
+                    read.push(s.as_str());
+                }
             }
 
             for arg in &entry.args {
-                if let Arg::Pipe(p) = arg {
+                if let Arg::Pipe(p) = arg.inner() {
                     match p {
                         Pipe::Rx(s) => read.push(s.as_str()),
                         Pipe::Tx(s) => write.push(s.as_str()),
@@ -193,17 +976,98 @@ impl Specification {
         (read, write)
     }
 
-    pub fn sockets(&self) -> (Vec<&str>, Vec<&str>) {
+    /**
+     * The entrypoints triggered by each signal. Signals that do not exist
+     * are ignored, as validation rejects them.
+     */
+    pub(crate) fn signal_triggers(&self) -> HashMap<Signal, Vec<&str>> {
+        let mut triggers: HashMap<Signal, Vec<&str>> = HashMap::new();
+
+        for (name, entry) in &self.entrypoints {
+            if let Trigger::Signal(sig) = entry.trigger {
+                if let Ok(sig) = Signal::try_from(sig) {
+                    triggers.entry(sig).or_default().push(name.as_str());
+                }
+            }
+        }
+
+        debug!("signal triggers: {:?}", &triggers);
+        triggers
+    }
+
+    /// Entrypoints signalling readiness, which each need a readiness pipe
+    pub(crate) fn readiness(&self) -> Vec<&str> {
+        let ready: Vec<&str> = self
+            .entrypoints
+            .iter()
+            .filter(|(_, e)| e.args.iter().any(|arg| arg.inner() == &Arg::Ready))
+            .map(|(name, _)| name.as_str())
+            .collect();
+
+        debug!("readiness pipes: {:?}", &ready);
+        ready
+    }
+
+    /**
+     * Every entrypoint name, ordered so each comes after the entrypoints it
+     * depends on. Fails with the entrypoints forming a cycle if there is one.
+     */
+    pub(crate) fn dependency_order(&self) -> Result<Vec<&str>> {
+        let mut names: Vec<&str> = self.entrypoints.keys().map(String::as_str).collect();
+        names.sort_unstable();
+
+        let mut order = Vec::with_capacity(names.len());
+        let mut visiting = Vec::new();
+        for name in names {
+            self.visit_dependencies(name, &mut visiting, &mut order)?;
+        }
+
+        Ok(order)
+    }
+
+    fn visit_dependencies<'a>(
+        &'a self,
+        name: &'a str,
+        visiting: &mut Vec<&'a str>,
+        order: &mut Vec<&'a str>,
+    ) -> Result<()> {
+        if order.contains(&name) {
+            return Ok(());
+        }
+
+        if let Some(start) = visiting.iter().position(|n| *n == name) {
+            let mut cycle = visiting[start..].to_vec();
+            cycle.push(name);
+            return Err(Error::DependencyCycle(cycle.join(" -> ")));
+        }
+
+        // unknown dependencies are reported separately by validate
+        if let Some(entrypoint) = self.entrypoints.get(name) {
+            visiting.push(name);
+            for dependency in &entrypoint.depends_on {
+                self.visit_dependencies(dependency, visiting, order)?;
+            }
+            visiting.pop();
+
+            order.push(name);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn sockets(&self) -> (Vec<&str>, Vec<&str>) {
         let mut read = Vec::new();
         let mut write = Vec::new();
 
         for entry in self.entrypoints.values() {
-            if let Trigger::FileSocket(s) = &entry.trigger {
-                read.push(s.as_str());
+            for trigger in entry.trigger.sources() {
+                if let Trigger::FileSocket(s) = trigger {
+                    read.push(s.as_str());
+                }
             }
 
             for arg in &entry.args {
-                if let Arg::FileSocket(p) = arg {
+                if let Arg::FileSocket(p) = arg.inner() {
                     match p {
                         FileSocket::Rx(s) => read.push(s.as_str()),
                         FileSocket::Tx(s) => write.push(s.as_str()),
@@ -217,32 +1081,47 @@ impl Specification {
         (read, write)
     }
 
-    pub fn validate(&self) -> Result<()> {
+    /**
+     * Check the specification is runnable, accumulating every error found. A
+     * runnable specification returns the warnings for anything questionable.
+     */
+    pub fn validate(&self) -> Result<Vec<Warning>> {
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+
         // validate pipes match
         let (read, write) = self.pipes();
         let mut read_set = HashSet::with_capacity(read.len());
 
         for pipe in read {
             if !read_set.insert(pipe) {
-                return Err(Error::BadPipe(pipe.to_string()));
+                errors.push(Error::BadPipe(pipe.to_string()));
             }
         }
 
         let mut write_set = HashSet::with_capacity(write.len());
         for pipe in write {
             if !write_set.insert(pipe) {
-                return Err(Error::BadPipe(pipe.to_string()));
+                errors.push(Error::BadPipe(pipe.to_string()));
             }
         }
 
         for pipe in read_set {
             if !write_set.remove(pipe) {
-                return Err(Error::BadPipe(pipe.to_string()));
+                errors.push(Error::BadPipe(pipe.to_string()));
             }
         }
 
-        if let Some(pipe) = write_set.into_iter().next() {
-            return Err(Error::BadPipe(pipe.to_string()));
+        for pipe in write_set {
+            errors.push(Error::BadPipe(pipe.to_string()));
+        }
+
+        // validate pipe options are for pipes that exist
+        let (read, _) = self.pipes();
+        for pipe in self.pipe_options.keys() {
+            if !read.contains(&pipe.as_str()) {
+                errors.push(Error::BadPipe(pipe.to_string()));
+            }
         }
 
         // validate sockets match
@@ -251,7 +1130,7 @@ impl Specification {
 
         for socket in read {
             if !read_set.insert(socket) {
-                return Err(Error::BadFileSocket(socket.to_string()));
+                errors.push(Error::BadFileSocket(socket.to_string()));
             }
         }
 
@@ -260,27 +1139,586 @@ impl Specification {
             write_set.insert(socket);
         }
 
+        // a socket with an abstract address can be sent to from outside the shim alone
         for socket in &read_set {
-            if !write_set.contains(socket) {
-                return Err(Error::BadFileSocket(socket.to_string()));
+            let external = self
+                .socket_options
+                .get(*socket)
+                .map(|o| o.abstract_address.is_some())
+                .unwrap_or(false);
+
+            if !write_set.contains(socket) && !external {
+                errors.push(Error::BadFileSocket(socket.to_string()));
+            }
+        }
+
+        // validate abstract addresses are for sockets triggering an entrypoint
+        for (socket, options) in &self.socket_options {
+            let triggered = self.entrypoints.values().any(|e| {
+                e.trigger
+                    .sources()
+                    .into_iter()
+                    .any(|t| matches!(t, Trigger::FileSocket(s) if s == socket))
+            });
+
+            if let Some(address) = &options.abstract_address {
+                // sun_path holds 108 bytes, of which the first is the leading nul
+                if !triggered || address.is_empty() || address.len() > 107 || address.contains('\0')
+                {
+                    errors.push(Error::BadAbstractAddress(socket.to_string()));
+                }
             }
         }
 
-        if let Some(socket) = (&write_set - &read_set).into_iter().next() {
-            return Err(Error::BadFileSocket(socket.to_string()));
+        for socket in &write_set - &read_set {
+            errors.push(Error::BadFileSocket(socket.to_string()));
+        }
+
+        // validate trigger lists only hold triggers that run in a trigger void
+        for (name, entrypoint) in &self.entrypoints {
+            if let Trigger::Any(triggers) = &entrypoint.trigger {
+                let listens = triggers.iter().all(|t| {
+                    matches!(
+                        t,
+                        Trigger::Pipe(_) | Trigger::FileSocket(_) | Trigger::Interval { .. }
+                    )
+                });
+
+                if triggers.is_empty() || !listens {
+                    errors.push(Error::BadTriggerList(name.to_string()));
+                }
+            }
         }
 
-        // validate trigger arguments make sense
+        // validate trigger arguments make sense, for every trigger of a list
         for entrypoint in self.entrypoints.values() {
             if entrypoint.args.contains(&Arg::Trigger) {
-                match entrypoint.trigger {
-                    Trigger::Pipe(_) => {}
-                    Trigger::FileSocket(_) => {}
-                    _ => return Err(Error::BadTriggerArgument),
+                let data = entrypoint
+                    .trigger
+                    .sources()
+                    .into_iter()
+                    .all(|t| matches!(t, Trigger::Pipe(_) | Trigger::FileSocket(_)));
+
+                if !data {
+                    errors.push(Error::BadTriggerArgument);
+                }
+            }
+        }
+
+        // validate readiness is only signalled by entrypoints spawned at startup
+        for entrypoint in self.entrypoints.values() {
+            let ready = entrypoint.args.iter().any(|arg| arg.inner() == &Arg::Ready);
+            if ready && !matches!(entrypoint.trigger, Trigger::Startup) {
+                errors.push(Error::BadReadyArgument);
+            }
+        }
+
+        // validate dependencies become ready once, before their dependents are spawned
+        for (name, entrypoint) in &self.entrypoints {
+            let signals_ready = |dependency: &String| match self.entrypoints.get(dependency) {
+                Some(d) => {
+                    d.oneshot
+                        || (matches!(d.trigger, Trigger::Startup)
+                            && d.args.iter().any(|arg| arg.inner() == &Arg::Ready))
+                }
+                None => false,
+            };
+
+            let depends = !entrypoint.depends_on.is_empty();
+            if (depends && (entrypoint.oneshot || matches!(entrypoint.trigger, Trigger::Signal(_))))
+                || !entrypoint.depends_on.iter().all(signals_ready)
+            {
+                errors.push(Error::BadDependency(name.to_string()));
+            }
+        }
+
+        if let Err(e) = self.dependency_order() {
+            errors.push(e);
+        }
+
+        // validate intervals are non-zero
+        for (name, entrypoint) in &self.entrypoints {
+            for trigger in entrypoint.trigger.sources() {
+                if let Trigger::Interval { period_ms: 0, .. } = trigger {
+                    errors.push(Error::BadInterval(name.to_string()));
+                }
+            }
+        }
+
+        // validate instances are started once, as one instance cannot be spawned again alone
+        for (name, entrypoint) in &self.entrypoints {
+            let multiple = entrypoint.instances > 1
+                && (!matches!(entrypoint.trigger, Trigger::Startup)
+                    || entrypoint.oneshot
+                    || entrypoint.restart != RestartPolicy::Never);
+
+            if entrypoint.instances == 0 || multiple {
+                errors.push(Error::BadInstances(name.to_string()));
+            }
+        }
+
+        // validate pids are requested by one void at a time, as a second would find it taken
+        for (name, entrypoint) in &self.entrypoints {
+            if let Some(pid) = entrypoint.pid {
+                let single = entrypoint.instances == 1
+                    && matches!(entrypoint.trigger, Trigger::Startup | Trigger::Signal(_));
+
+                if pid <= 0 || !single {
+                    errors.push(Error::BadPid(name.to_string()));
+                }
+            }
+        }
+
+        // validate resource limits, as the leaf cgroup is named after the entrypoint
+        for (name, entrypoint) in &self.entrypoints {
+            let limited = entrypoint.memory_max.is_some() || entrypoint.cpu_max.is_some();
+            let memory = entrypoint.memory_max != Some(0);
+            let cpu = entrypoint
+                .cpu_max
+                .map(|cpus| cpus.is_finite() && cpus >= MIN_CPU_MAX)
+                .unwrap_or(true);
+
+            if !memory || !cpu || (limited && name.contains('/')) {
+                errors.push(Error::BadLimits(name.to_string()));
+            }
+        }
+
+        // validate fd limits, as the kernel passes at most SCM_MAX_FD in one message
+        for (name, entrypoint) in &self.entrypoints {
+            if !(1..=SCM_MAX_FD).contains(&entrypoint.max_file_descriptors) {
+                errors.push(Error::BadMaxFileDescriptors(name.to_string()));
+            }
+        }
+
+        // validate timeouts, which only the shim's wait loop enforces
+        for (name, entrypoint) in &self.entrypoints {
+            if let Some(timeout) = entrypoint.timeout_ms {
+                if timeout == 0 || !matches!(entrypoint.trigger, Trigger::Startup) {
+                    errors.push(Error::BadTimeout(name.to_string()));
+                }
+            }
+        }
+
+        // validate root limits are non-zero, as tmpfs treats zero as unlimited
+        for (name, entrypoint) in &self.entrypoints {
+            let root = &entrypoint.root;
+            if root.size == Some(0) || root.inodes == Some(0) {
+                errors.push(Error::BadRootLimits(name.to_string()));
+            }
+        }
+
+        // validate working directories are absolute, as they are entered after pivot_root
+        for entrypoint in self.entrypoints.values() {
+            if let Some(workdir) = &entrypoint.workdir {
+                if !workdir.is_absolute() {
+                    errors.push(Error::BadWorkdir(workdir.clone()));
+                }
+            }
+        }
+
+        // validate only network namespaces are named, names are only set in a voided uts
+        // namespace, and ids are only mapped in a voided user namespace
+        for (name, entrypoint) in &self.entrypoints {
+            let sets_name = entrypoint
+                .environment
+                .iter()
+                .any(|env| matches!(env, Environment::Hostname(_) | Environment::DomainName(_)));
+            let maps_ids = entrypoint
+                .environment
+                .iter()
+                .any(|env| matches!(env, Environment::UidMap { .. } | Environment::GidMap { .. }));
+
+            let namespaces = &entrypoint.namespaces;
+            let named = [
+                &namespaces.pid,
+                &namespaces.ipc,
+                &namespaces.uts,
+                &namespaces.cgroup,
+                &namespaces.user,
+            ]
+            .into_iter()
+            .any(|ns| matches!(ns, Namespace::Named(_)));
+
+            // joining a named network namespace also joins the user namespace owning it
+            let shared_user = namespaces.user == Namespace::Host;
+            let joins_network = matches!(namespaces.network, Namespace::Named(_));
+
+            if named
+                || (sets_name && namespaces.uts == Namespace::Host)
+                || (shared_user && (maps_ids || joins_network))
+            {
+                errors.push(Error::BadNamespaces(name.to_string()));
+            }
+        }
+
+        // validate each entrypoint declares at most one network, in an otherwise voided namespace
+        for (name, entrypoint) in &self.entrypoints {
+            let networks: Vec<&Network> = entrypoint
+                .environment
+                .iter()
+                .filter_map(|env| match env {
+                    Environment::Network(network) => Some(network),
+                    _ => None,
+                })
+                .collect();
+
+            let bad = match networks.as_slice() {
+                [] => false,
+                [network] => {
+                    let private = matches!(network, Network::PrivateV4(_) | Network::PrivateV6(_));
+                    entrypoint.namespaces.network != Namespace::Void
+                        || (private
+                            && (!entrypoint.namespaces.loopback
+                                || network.private_host(1).is_none()))
+                }
+                _ => true,
+            };
+            if bad {
+                errors.push(Error::BadNetwork(name.to_string()));
+            }
+        }
+
+        // validate links join two different named network namespaces with a usable subnet
+        let named_networks: HashSet<&str> = self
+            .entrypoints
+            .values()
+            .filter_map(|entrypoint| match &entrypoint.namespaces.network {
+                Namespace::Named(ns) => Some(ns.as_str()),
+                _ => None,
+            })
+            .collect();
+        for (index, link) in self.links.iter().enumerate() {
+            let (first, second) = &link.networks;
+            if first == second
+                || !named_networks.contains(first.as_str())
+                || !named_networks.contains(second.as_str())
+                || link.addresses().is_none()
+            {
+                errors.push(Error::BadLink(format!("veth{}", index)));
+            }
+        }
+
+        // validate host and domain name templates only use known placeholders
+        let templates = self.hostname.iter().chain(&self.domain_name).chain(
+            self.entrypoints
+                .values()
+                .flat_map(|entrypoint| &entrypoint.environment)
+                .filter_map(|env| match env {
+                    Environment::Hostname(name) | Environment::DomainName(name) => Some(name),
+                    _ => None,
+                }),
+        );
+        for template in templates {
+            let stripped = template.replace("{entrypoint}", "").replace("{pid}", "");
+            if stripped.contains(['{', '}']) {
+                errors.push(Error::BadNameTemplate(template.clone()));
+            }
+        }
+
+        // validate signals exist, are not used by the shim and can trigger repeatedly
+        for (name, entrypoint) in &self.entrypoints {
+            if let Trigger::Signal(sig) = entrypoint.trigger {
+                let consumes = entrypoint.args.iter().any(|arg| {
+                    matches!(
+                        arg.inner(),
+                        Arg::Pipe(_) | Arg::FileSocket(FileSocket::Rx(_))
+                    )
+                });
+
+                let reserved = match Signal::try_from(sig) {
+                    Ok(sig) => RESERVED_SIGNALS.contains(&sig),
+                    Err(_) => true,
+                };
+
+                if consumes || reserved {
+                    errors.push(Error::BadSignal(name.to_string()));
+                }
+            }
+        }
+
+        // validate rpc rules are well formed
+        for entrypoint in self.entrypoints.values() {
+            for arg in &entrypoint.args {
+                if let Arg::Rpc(specs) = arg.inner() {
+                    for spec in specs {
+                        if let Err(e) = spec.validate() {
+                            errors.push(e);
+                        }
+                    }
+                }
+            }
+        }
+
+        // validate literals can be passed in argv, which is nul terminated
+        for entrypoint in self.entrypoints.values() {
+            for arg in &entrypoint.args {
+                if let Arg::Literal(s) = arg {
+                    if s.contains('\0') {
+                        errors.push(Error::BadLiteral(s.clone()));
+                    }
+                }
+            }
+        }
+
+        // validate files are only created if writable
+        for entrypoint in self.entrypoints.values() {
+            for arg in &entrypoint.args {
+                if let Arg::File(file) = arg.inner() {
+                    if file.create && !file.writable {
+                        errors.push(Error::BadFile(file.path.clone()));
+                    }
+                }
+            }
+        }
+
+        // validate restarted entrypoints do not use up pipes or sockets, and only
+        // they limit restarts, never with a zero backoff
+        for (name, entrypoint) in &self.entrypoints {
+            let limited =
+                entrypoint.max_restarts.is_some() || entrypoint.restart_backoff_ms.is_some();
+
+            if entrypoint.restart == RestartPolicy::Never {
+                if limited {
+                    errors.push(Error::BadRestartPolicy(name.to_string()));
+                }
+                continue;
+            }
+
+            let consumes = entrypoint.args.iter().any(|arg| {
+                matches!(
+                    arg.inner(),
+                    Arg::Pipe(_) | Arg::FileSocket(FileSocket::Rx(_))
+                )
+            });
+
+            if consumes
+                || !matches!(entrypoint.trigger, Trigger::Startup)
+                || entrypoint.restart_backoff_ms == Some(0)
+            {
+                errors.push(Error::BadRestartPolicy(name.to_string()));
+            }
+        }
+
+        // validate oneshot entrypoints are started once and cannot wait on later entrypoints
+        for (name, entrypoint) in &self.entrypoints {
+            if !entrypoint.oneshot {
+                continue;
+            }
+
+            let consumes = entrypoint.args.iter().any(|arg| {
+                matches!(
+                    arg.inner(),
+                    Arg::Pipe(Pipe::Rx(_)) | Arg::FileSocket(FileSocket::Rx(_))
+                )
+            });
+
+            if consumes
+                || !matches!(entrypoint.trigger, Trigger::Startup)
+                || entrypoint.restart != RestartPolicy::Never
+            {
+                errors.push(Error::BadOneshot(name.to_string()));
+            }
+        }
+
+        // validate fixed fds wrap an fd, avoid stdio and are unique per entrypoint
+        for entrypoint in self.entrypoints.values() {
+            let mut fixed_fds = HashSet::new();
+
+            for arg in &entrypoint.args {
+                if let Arg::FixedFd { fd, arg } = arg {
+                    if *fd < 3 || !arg.produces_fd() || !fixed_fds.insert(*fd) {
+                        errors.push(Error::BadFixedFd(*fd));
+                    }
+                }
+            }
+
+            // validate inherited fds avoid stdio and are not replaced by a fixed fd
+            for arg in &entrypoint.args {
+                if let Arg::InheritFd(fd) = arg {
+                    if *fd < 3 || fixed_fds.contains(fd) {
+                        errors.push(Error::BadInheritFd(*fd));
+                    }
+                }
+            }
+        }
+
+        // warn about triggers whose data is read and then thrown away
+        for (name, entrypoint) in &self.entrypoints {
+            let takes_data = entrypoint
+                .trigger
+                .sources()
+                .into_iter()
+                .any(|t| matches!(t, Trigger::Pipe(_) | Trigger::FileSocket(_)));
+
+            if takes_data && !entrypoint.args.contains(&Arg::Trigger) {
+                warnings.push(Warning::UnusedTrigger(name.to_string()));
+            }
+        }
+
+        // warn about mounting the root or a whole top level directory
+        for entrypoint in self.entrypoints.values() {
+            for env in &entrypoint.environment {
+                if let Environment::Filesystem { host_path, .. } = env {
+                    if host_path.components().count() <= 2 {
+                        warnings.push(Warning::BroadMount(host_path.clone()));
+                    }
+                }
+            }
+        }
+
+        match errors.len() {
+            0 => Ok(warnings),
+            1 => Err(errors.remove(0)),
+            _ => Err(Error::BadSpecification(errors)),
+        }
+    }
+
+    /**
+     * Check the specification against the binary it will launch, warning
+     * about entrypoints that cannot load a dynamically linked binary.
+     */
+    pub fn validate_binary(&self, binary: &Path) -> Result<Vec<Warning>> {
+        let interpreter = match Self::interpreter(binary)? {
+            Some(interpreter) => interpreter,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut warnings = Vec::new();
+        for (name, entrypoint) in &self.entrypoints {
+            let mounted = entrypoint.environment.iter().any(|env| match env {
+                Environment::Filesystem {
+                    environment_path, ..
+                } => environment_path == &interpreter,
+                _ => false,
+            });
+
+            if !mounted {
+                warnings.push(Warning::MissingInterpreter {
+                    entrypoint: name.to_string(),
+                    interpreter: interpreter.clone(),
+                });
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /**
+     * Check every TrailingAt that is not optional has an argument, given the
+     * number of arguments following the binary, before anything is spawned.
+     */
+    pub fn validate_trailing(&self, trailing: usize) -> Result<()> {
+        for entrypoint in self.entrypoints.values() {
+            for arg in &entrypoint.args {
+                if let Arg::TrailingAt {
+                    index,
+                    optional: false,
+                } = arg
+                {
+                    if *index >= trailing {
+                        return Err(Error::MissingTrailingArg(*index));
+                    }
                 }
             }
         }
 
         Ok(())
     }
+
+    /**
+     * Check every InheritFd is open in the shim, before the shim opens any fds
+     * of its own that could take a closed fd's number and be passed instead.
+     */
+    pub fn validate_inherited_fds(&self) -> Result<()> {
+        for entrypoint in self.entrypoints.values() {
+            for arg in &entrypoint.args {
+                if let Arg::InheritFd(fd) = arg.inner() {
+                    fcntl(*fd, FcntlArg::F_GETFD).map_err(|_| Error::BadInheritFd(*fd))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * The program interpreter of a dynamically linked ELF binary, or None if
+     * the binary is statically linked or not an ELF at all.
+     */
+    fn interpreter(binary: &Path) -> Result<Option<PathBuf>> {
+        let data = read_binary(binary)?;
+        let elf = match object::File::parse(data.as_slice()) {
+            Ok(elf) => elf,
+            Err(_) => return Ok(None),
+        };
+
+        let interp = match elf.section_by_name(".interp") {
+            Some(section) => section.data()?,
+            None => return Ok(None),
+        };
+
+        let interp = interp.split(|b| *b == 0).next().unwrap_or_default();
+        Ok(Some(PathBuf::from(OsStr::from_bytes(interp))))
+    }
+}
+
+fn substitute_args(value: &mut serde_json::Value, argv: &[&str]) -> Result<()> {
+    match value {
+        serde_json::Value::String(s) => *s = substitute_str(s, argv)?,
+        serde_json::Value::Array(values) => {
+            for v in values {
+                substitute_args(v, argv)?;
+            }
+        }
+        serde_json::Value::Object(values) => {
+            for v in values.values_mut() {
+                substitute_args(v, argv)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn substitute_str(s: &str, argv: &[&str]) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let placeholder = rest.find('}').map(|end| &rest[..=end]);
+        let index = placeholder.and_then(|p| p[2..p.len() - 1].parse::<usize>().ok());
+
+        match (placeholder, index) {
+            (Some(placeholder), Some(index)) => {
+                let arg = argv
+                    .get(index)
+                    .ok_or_else(|| Error::BadSubstitution(placeholder.to_string()))?;
+
+                out.push_str(arg);
+                rest = &rest[placeholder.len()..];
+            }
+            _ => {
+                // not a placeholder, keep it as written
+                out.push_str("${");
+                rest = &rest[2..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/**
+ * Read the binary to launch, naming it in the error if it does not exist.
+ */
+fn read_binary(binary: &Path) -> Result<Vec<u8>> {
+    fs::read(binary).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => Error::BinaryNotFound(binary.to_path_buf()),
+        _ => e.into(),
+    })
 }
@@ -1,25 +1,177 @@
 use log::{debug, error, info, trace};
 
-use crate::clone::{clone3, CloneArgs, CloneFlags};
+use crate::clone::{clone, CloneArgs, CloneFlags};
 use crate::{Error, Result};
 
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt;
 use std::fs::{self, File};
-use std::io::Write;
-use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
 
-use nix::fcntl::{FcntlArg, FdFlag};
+use nix::errno::Errno;
+use nix::fcntl::{FcntlArg, FdFlag, OFlag};
 use nix::mount::{mount, umount2, MntFlags, MsFlags};
-use nix::sys::signal::{signal, SigHandler, Signal};
-use nix::unistd::{close, dup2, getgid, getuid, pivot_root, sethostname, Gid, Pid, Uid};
-
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sched::{setns, unshare};
+use nix::sys::signal::{signal, SigHandler, SigSet, Signal};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{
+    close, dup2, fork, getgid, getuid, pipe2, pivot_root, sethostname, ForkResult, Gid, Pid, Uid,
+};
+
+use caps::{CapSet, Capability};
 use close_fds::CloseFdsBuilder;
+use seccompiler::{BpfProgram, TargetArch};
+
+/// Environment variable naming the directory new roots are created in,
+/// before falling back to the system's temporary directory
+pub const TMPDIR_ENV_VAR: &str = "VOID_TMPDIR";
+
+/// Entries of /proc hidden from a masked void
+const PROC_MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/proc/sysrq-trigger",
+    "/proc/timer_list",
+];
+
+/// Pid of the child of a void's init, for its signal handler to forward to
+static INIT_CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Entries of /proc made read-only in a masked void
+const PROC_READONLY_PATHS: &[&str] = &["/proc/bus", "/proc/fs", "/proc/irq", "/proc/sys"];
 
 pub struct VoidHandle {
     pid: Pid,
+    pidfd: File,
+
+    /// Read ends of the pipes capturing stdout and stderr, until each closes
+    stdout: Option<File>,
+    stderr: Option<File>,
+    output: Option<CapturedOutput>,
+}
+
+/// What a void wrote to stdout and stderr while its output was captured
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CapturedOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl VoidHandle {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// A pidfd for the void, which always refers to it even after its pid is reused
+    pub fn pidfd(&self) -> &File {
+        &self.pidfd
+    }
+
+    /**
+     * Send a signal to the void through its pidfd. Unlike kill(2) this can
+     * never reach another process that has since been given the void's pid.
+     */
+    pub fn signal(&self, sig: Signal) -> Result<()> {
+        // SAFETY: the pidfd is valid for the lifetime of the handle and no siginfo is passed
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.pidfd.as_raw_fd(),
+                sig as libc::c_int,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+
+        Errno::result(result).map(drop).map_err(|e| Error::Nix {
+            msg: "pidfd_send_signal",
+            src: e,
+        })
+    }
+
+    /**
+     * Wait up to the timeout, or forever if none is given, for the void to
+     * exit. Returns whether it has. The void is not reaped, so its exit
+     * status is still available to waitid(2).
+     */
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool> {
+        let timeout = match timeout {
+            Some(t) => t.as_millis().try_into().unwrap_or(libc::c_int::MAX),
+            None => -1,
+        };
+
+        // a pidfd becomes readable once its process exits
+        let mut fds = [PollFd::new(self.pidfd.as_raw_fd(), PollFlags::POLLIN)];
+        loop {
+            match poll(&mut fds, timeout) {
+                Ok(ready) => return Ok(ready > 0),
+                Err(Errno::EINTR) => {}
+                Err(e) => {
+                    return Err(Error::Nix {
+                        msg: "poll",
+                        src: e,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Read ends of the pipes still capturing the void's output, to poll on
+    pub fn output_fds(&self) -> impl Iterator<Item = RawFd> + '_ {
+        self.stdout
+            .iter()
+            .chain(self.stderr.iter())
+            .map(|pipe| pipe.as_raw_fd())
+    }
+
+    /**
+     * Read whatever the void has written to its captured stdout and stderr
+     * without blocking, closing each pipe once every writer has. Reading must
+     * keep up with the void, as it blocks once a pipe fills.
+     */
+    pub fn read_output(&mut self) -> Result<()> {
+        if let Some(output) = &mut self.output {
+            read_available(&mut self.stdout, &mut output.stdout)?;
+            read_available(&mut self.stderr, &mut output.stderr)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Read the rest of the captured output and take everything read so far,
+     * or None if the void's output isn't captured. Output written after the
+     * void exits by processes outside its pid namespace may be missed.
+     */
+    pub fn take_output(&mut self) -> Result<Option<CapturedOutput>> {
+        self.read_output()?;
+        Ok(self.output.take())
+    }
+}
+
+/// Append what can be read from a non-blocking pipe to buf, closing it at EOF
+fn read_available(pipe: &mut Option<File>, buf: &mut Vec<u8>) -> Result<()> {
+    if let Some(file) = pipe {
+        match file.read_to_end(buf) {
+            Ok(_) => *pipe = None,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
 }
 
 impl fmt::Display for VoidHandle {
@@ -28,14 +180,117 @@ impl fmt::Display for VoidHandle {
     }
 }
 
+impl fmt::Debug for VoidBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fds: Vec<&RawFd> = self.fds.iter().collect();
+        fds.sort();
+
+        f.debug_struct("VoidBuilder")
+            .field("clone_flags", &self.clone_flags())
+            .field("hostname", &self.hostname)
+            .field("domain_name", &self.domain_name)
+            .field("mounts", &self.mounts)
+            .field("mount_plan", &self.mount_plan.is_some())
+            .field("mount_flags", &self.mount_flags)
+            .field("fds", &fds)
+            .field("redirects", &self.redirects)
+            .field("capture_output", &self.capture_output)
+            .field("uid_maps", &self.uid_maps)
+            .field("gid_maps", &self.gid_maps)
+            .field("remount_proc", &self.remount_proc)
+            .field("mask_proc", &self.mask_proc)
+            .field("tty", &self.tty)
+            .field("workdir", &self.workdir)
+            .field("root_size", &self.root_size)
+            .field("root_inodes", &self.root_inodes)
+            .field("loopback_addresses", &self.loopback_addresses)
+            .field("tid", &self.tid)
+            .field("init", &self.init)
+            .field("cgroup", &self.cgroup)
+            .field("seccomp", &self.seccomp)
+            .field("capabilities", &self.capabilities)
+            .finish()
+    }
+}
+
+/// A contiguous range of ids mapped into a user namespace
+#[derive(Debug)]
+struct IdMap {
+    inside: u32,
+    outside: u32,
+    count: u32,
+}
+
+/// A bind mount with its source resolved
+#[derive(Debug)]
+struct PlannedMount {
+    /// Source in the parent's mount namespace, with a symlink followed
+    src: PathBuf,
+    dst: PathBuf,
+    dir: bool,
+}
+
+/// The bind mounts of a void with their sources resolved, which voids with
+/// the same mounts can share rather than each resolving them again
+#[derive(Debug)]
+pub struct MountPlan {
+    /// The mounts the plan was made from, as (source, destination)
+    mounts: HashMap<PathBuf, PathBuf>,
+    /// Mounts in order of destination, so parents are mounted before children
+    planned: Vec<PlannedMount>,
+}
+
+impl MountPlan {
+    /// Whether the plan was made from the same mounts as builder
+    pub fn matches(&self, builder: &VoidBuilder) -> bool {
+        self.mounts == builder.mounts
+    }
+}
+
 pub struct VoidBuilder {
     hostname: Option<String>,
     domain_name: Option<String>,
 
     mounts: HashMap<PathBuf, PathBuf>,
+    /// Resolved mounts to use rather than resolving them in the void
+    mount_plan: Option<Rc<MountPlan>>,
+    /// Flags to remount bind mounts with, by destination
+    mount_flags: HashMap<PathBuf, MsFlags>,
     fds: HashSet<RawFd>,
+    /// Files opened with ambient authority to replace standard fds, by fd
+    redirects: HashMap<RawFd, File>,
+    /// Connect stdout and stderr to pipes read through the handle
+    capture_output: bool,
+
+    uid_maps: Vec<IdMap>,
+    gid_maps: Vec<IdMap>,
 
     remount_proc: bool,
+    mask_proc: bool,
+    tty: bool,
+
+    workdir: Option<PathBuf>,
+    root_size: Option<u64>,
+    root_inodes: Option<u64>,
+
+    /// Namespaces shared with the parent rather than voided
+    shared: CloneFlags,
+    /// pidfd of a void whose user and network namespaces to join
+    join_network: Option<RawFd>,
+    loopback: bool,
+    /// Addresses the loopback interface also holds, with their prefixes
+    loopback_addresses: Vec<(IpAddr, u8)>,
+    /// Offsets of the monotonic and boottime clocks in milliseconds, for a
+    /// new time namespace
+    time_offsets: Option<(i64, i64)>,
+    /// Pid requested in the parent's pid namespace
+    tid: Option<Pid>,
+    init: bool,
+    /// Directory fd of a cgroup v2 to clone the void into
+    cgroup: Option<RawFd>,
+
+    seccomp: Option<Vec<String>>,
+    capabilities: Option<HashSet<Capability>>,
 }
 
 impl VoidBuilder {
@@ -44,8 +299,29 @@ impl VoidBuilder {
             hostname: None,
             domain_name: None,
             mounts: HashMap::new(),
+            mount_plan: None,
+            mount_flags: HashMap::new(),
             fds: HashSet::new(),
+            redirects: HashMap::new(),
+            capture_output: false,
+            uid_maps: Vec::new(),
+            gid_maps: Vec::new(),
             remount_proc: false,
+            mask_proc: false,
+            tty: false,
+            workdir: None,
+            root_size: None,
+            root_inodes: None,
+            shared: CloneFlags::empty(),
+            join_network: None,
+            loopback: false,
+            loopback_addresses: Vec::new(),
+            time_offsets: None,
+            tid: None,
+            init: false,
+            cgroup: None,
+            seccomp: None,
+            capabilities: None,
         }
     }
 
@@ -59,8 +335,54 @@ impl VoidBuilder {
         self
     }
 
+    /// The bind mounts of the void as (source, destination), by destination
+    pub fn mounts(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        let mut mounts: Vec<(&Path, &Path)> = self
+            .mounts
+            .iter()
+            .map(|(src, dst)| (src.as_path(), dst.as_path()))
+            .collect();
+        mounts.sort_by_key(|(_, dst)| *dst);
+        mounts.into_iter()
+    }
+
+    /**
+     * Resolve the sources of the bind mounts as the void would, following a
+     * symlink at each, so voids with the same mounts can share the result.
+     * A source that changes between file and directory after planning fails
+     * to mount.
+     */
+    pub fn plan_mounts(&self) -> Result<MountPlan> {
+        Ok(MountPlan {
+            mounts: self.mounts.clone(),
+            planned: self.resolve_mounts(Path::new("/"))?,
+        })
+    }
+
+    /// Use mounts resolved by plan_mounts, which must match this builder's
+    pub fn mount_plan(&mut self, plan: Rc<MountPlan>) -> &mut Self {
+        self.mount_plan = Some(plan);
+        self
+    }
+
     pub fn mount<T1: AsRef<Path>, T2: AsRef<Path>>(&mut self, src: T1, dst: T2) -> &mut Self {
+        self.mount_with_flags(src, dst, MsFlags::empty())
+    }
+
+    /// Bind mount then remount with flags such as MS_NOEXEC, MS_NOSUID and MS_NODEV,
+    /// which apply to the top mount but not any mounts beneath it
+    pub fn mount_with_flags<T1: AsRef<Path>, T2: AsRef<Path>>(
+        &mut self,
+        src: T1,
+        dst: T2,
+        flags: MsFlags,
+    ) -> &mut Self {
         self.mounts.insert(src.as_ref().into(), dst.as_ref().into());
+        if flags.is_empty() {
+            self.mount_flags.remove(dst.as_ref());
+        } else {
+            self.mount_flags.insert(dst.as_ref().into(), flags);
+        }
         self
     }
 
@@ -74,47 +396,320 @@ impl VoidBuilder {
         self
     }
 
+    /// Hide sensitive entries of the remounted /proc and make the tunables read-only
+    pub fn mask_proc(&mut self) -> &mut Self {
+        self.mask_proc = true;
+        self
+    }
+
+    /// Replace a standard fd of the void with a file, such as stdout with a log file
+    pub fn redirect_fd(&mut self, fd: RawFd, file: File) -> &mut Self {
+        self.keep_fd(&file);
+        self.redirects.insert(fd, file);
+        self
+    }
+
+    /// Connect stdout and stderr to pipes read through the handle, in place of any redirect
+    pub fn capture_output(&mut self) -> &mut Self {
+        self.capture_output = true;
+        self
+    }
+
+    /// Keep the parent's stdout and stderr, in place of any redirect
+    pub fn inherit_output(&mut self) -> &mut Self {
+        self.remove_output_redirects();
+        self.keep_fd(&1).keep_fd(&2)
+    }
+
+    fn remove_output_redirects(&mut self) {
+        for stdfd in [1, 2] {
+            if let Some(file) = self.redirects.remove(&stdfd) {
+                self.fds.remove(&file.as_raw_fd());
+            }
+        }
+    }
+
+    /// Mount the controlling terminal and use it for stdin, stdout and stderr
+    pub fn attach_tty(&mut self) -> &mut Self {
+        self.tty = true;
+        self.mount("/dev/tty", "/dev/tty")
+    }
+
+    /// Stay in the parent's network namespace, with access to its interfaces
+    pub fn share_network(&mut self) -> &mut Self {
+        self.shared |= CloneFlags::CLONE_NEWNET;
+        self
+    }
+
+    /// Join the network namespace of the void with this pidfd, which must stay open
+    /// until spawning. The void's user namespace is joined too, including its id maps.
+    pub fn join_network(&mut self, pidfd: RawFd) -> &mut Self {
+        self.join_network = Some(pidfd);
+        self
+    }
+
+    /// Bring up the loopback interface of a new network namespace
+    pub fn loopback(&mut self) -> &mut Self {
+        self.loopback = true;
+        self
+    }
+
+    /// Create a new time namespace with the monotonic and boottime clocks offset by these milliseconds
+    pub fn time_offsets(&mut self, monotonic_ms: i64, boottime_ms: i64) -> &mut Self {
+        self.time_offsets = Some((monotonic_ms, boottime_ms));
+        self
+    }
+
+    /// Also hold this address on the loopback interface, bringing it up
+    pub fn loopback_address(&mut self, address: IpAddr, prefix: u8) -> &mut Self {
+        self.loopback = true;
+        self.loopback_addresses.push((address, prefix));
+        self
+    }
+
+    /// Stay in the parent's pid namespace, so /proc is bind mounted rather than remounted
+    pub fn share_pid(&mut self) -> &mut Self {
+        self.shared |= CloneFlags::CLONE_NEWPID;
+        self
+    }
+
+    /// Stay in the parent's ipc namespace
+    pub fn share_ipc(&mut self) -> &mut Self {
+        self.shared |= CloneFlags::CLONE_NEWIPC;
+        self
+    }
+
+    /// Stay in the parent's uts namespace, keeping its host and domain names
+    pub fn share_uts(&mut self) -> &mut Self {
+        self.shared |= CloneFlags::CLONE_NEWUTS;
+        self
+    }
+
+    /// Stay in the parent's cgroup namespace
+    pub fn share_cgroup(&mut self) -> &mut Self {
+        self.shared |= CloneFlags::CLONE_NEWCGROUP;
+        self
+    }
+
+    /// Stay in the parent's user namespace, skipping id maps, which needs the parent to be root
+    pub fn share_user(&mut self) -> &mut Self {
+        self.shared |= CloneFlags::CLONE_NEWUSER;
+        self
+    }
+
+    /// Request this pid in the parent's pid namespace, which needs CAP_SYS_ADMIN there
+    pub fn set_tid(&mut self, pid: Pid) -> &mut Self {
+        self.tid = Some(pid);
+        self
+    }
+
+    /// Run a minimal init as pid 1 that reaps orphans, with the child function as its child
+    pub fn init(&mut self) -> &mut Self {
+        self.init = true;
+        self
+    }
+
+    /// Clone directly into a cgroup v2 directory, which must stay open until spawned
+    pub fn cgroup_fd(&mut self, dir: &File) -> &mut Self {
+        self.cgroup = Some(dir.as_raw_fd());
+        self
+    }
+
+    /// Limit the size of the root tmpfs in bytes, instead of half of RAM
+    pub fn root_size(&mut self, bytes: u64) -> &mut Self {
+        self.root_size = Some(bytes);
+        self
+    }
+
+    /// Limit the number of inodes in the root tmpfs
+    pub fn root_inodes(&mut self, count: u64) -> &mut Self {
+        self.root_inodes = Some(count);
+        self
+    }
+
+    /// Change to this directory, relative to the new root, once mounts are set up
+    pub fn workdir<T: Into<PathBuf>>(&mut self, path: T) -> &mut Self {
+        self.workdir = Some(path.into());
+        self
+    }
+
+    /// Kill the void on any syscall not in the allowlist once it is prepared
+    pub fn seccomp<T: Into<String>>(&mut self, syscalls: impl IntoIterator<Item = T>) -> &mut Self {
+        self.seccomp = Some(syscalls.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Drop all but these capabilities from the bounding set once the void is prepared
+    pub fn keep_capabilities(
+        &mut self,
+        capabilities: impl IntoIterator<Item = Capability>,
+    ) -> &mut Self {
+        self.capabilities = Some(capabilities.into_iter().collect());
+        self
+    }
+
+    /// Replace the default mapping of root to the parent uid with ranges
+    pub fn uid_map(&mut self, inside: u32, outside: u32, count: u32) -> &mut Self {
+        self.uid_maps.push(IdMap {
+            inside,
+            outside,
+            count,
+        });
+        self
+    }
+
+    /// Replace the default mapping of root to the parent gid with ranges
+    pub fn gid_map(&mut self, inside: u32, outside: u32, count: u32) -> &mut Self {
+        self.gid_maps.push(IdMap {
+            inside,
+            outside,
+            count,
+        });
+        self
+    }
+
+    fn clone_flags(&self) -> CloneFlags {
+        let flags = CloneFlags::CLONE_NEWCGROUP
+            | CloneFlags::CLONE_NEWIPC
+            | CloneFlags::CLONE_NEWNET
+            | CloneFlags::CLONE_NEWNS
+            | CloneFlags::CLONE_NEWPID
+            | CloneFlags::CLONE_NEWUSER
+            | CloneFlags::CLONE_NEWUTS;
+
+        let joined = if self.join_network.is_some() {
+            CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNET
+        } else {
+            CloneFlags::empty()
+        };
+
+        flags - self.shared - joined
+    }
+
     pub fn spawn(&mut self, child_fn: impl FnOnce() -> i32) -> Result<VoidHandle> {
-        let mut args = CloneArgs::new(
-            CloneFlags::CLONE_NEWCGROUP
-                | CloneFlags::CLONE_NEWIPC
-                | CloneFlags::CLONE_NEWNET
-                | CloneFlags::CLONE_NEWNS
-                | CloneFlags::CLONE_NEWPID
-                | CloneFlags::CLONE_NEWUSER
-                | CloneFlags::CLONE_NEWUTS,
-        );
+        let mut args = CloneArgs::new(self.clone_flags());
         args.exit_signal = Some(Signal::SIGCHLD);
 
+        // set_tid starts from the innermost namespace, where a new one numbers the void 1
+        let new_pid = self.clone_flags().contains(CloneFlags::CLONE_NEWPID);
+        let tids: Vec<Pid> = match self.tid {
+            Some(tid) if new_pid => vec![Pid::from_raw(1), tid],
+            Some(tid) => vec![tid],
+            None => vec![],
+        };
+        if !tids.is_empty() {
+            args.set_tid = Some(&tids);
+        }
+        args.cgroup = self.cgroup;
+
         let parent_uid = getuid();
         let parent_gid = getgid();
 
-        let child = clone3(args).map_err(|e| Error::Nix {
-            msg: "clone3",
-            src: e,
-        })?;
+        // without a new user namespace, creating the others needs real root
+        if self.shared.contains(CloneFlags::CLONE_NEWUSER) && !parent_uid.is_root() {
+            return Err(Error::SharedUserNamespace);
+        }
 
-        if child == Pid::from_raw(0) {
-            // ignore SIGHUP
-            // safety: safe as ignores the return result of the previous handler
-            unsafe { signal(Signal::SIGHUP, SigHandler::SigIgn) }.map_err(|e| Error::Nix {
-                msg: "signal",
+        // compile with ambient authority so a bad filter fails before cloning
+        let seccomp_filter = self
+            .seccomp
+            .as_deref()
+            .map(compile_seccomp_filter)
+            .transpose()?;
+
+        // id ranges can only be mapped with capabilities in the parent namespace,
+        // so the parent writes them while the child waits on this pipe
+        let new_user = self.clone_flags().contains(CloneFlags::CLONE_NEWUSER);
+        let maps_pipe = if !new_user || (self.uid_maps.is_empty() && self.gid_maps.is_empty()) {
+            None
+        } else {
+            let (read, write) = pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::Nix {
+                msg: "pipe2",
                 src: e,
             })?;
 
-            let result = {
+            // SAFETY: valid new fds as pipe2(2) returned successfully
+            Some(unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) })
+        };
+
+        // the pipes' write ends replace stdout and stderr in the void alone, so
+        // the builder is left unchanged however the spawn returns
+        let capture = if self.capture_output {
+            Some((output_pipe()?, output_pipe()?))
+        } else {
+            None
+        };
+
+        let mut pidfd = None;
+        let child = match self.join_network {
+            Some(owner) => {
+                let child = Self::clone_joined(owner, args)?;
+
+                // the void is an unreaped child of this process, so its pid can't have been reused
+                if child != Pid::from_raw(0) {
+                    pidfd = Some(pidfd_open(child)?);
+                }
+                child
+            }
+            None => {
+                args.pidfd = Some(&mut pidfd);
+                clone(args).map_err(|e| match (self.tid, e) {
+                    (Some(pid), Errno::EEXIST | Errno::EINVAL | Errno::EPERM) => {
+                        Error::UnavailablePid { pid, src: e }
+                    }
+                    _ => clone_error(e),
+                })?
+            }
+        };
+
+        if child == Pid::from_raw(0) {
+            let maps_ready = maps_pipe.map(|(read, _write)| read);
+
+            // the void's copy of the builder, in place of any redirect
+            if let Some(((_, stdout), (_, stderr))) = capture {
+                self.remove_output_redirects();
+                self.redirect_fd(1, stdout).redirect_fd(2, stderr);
+            }
+
+            // a closure so errors exit the void rather than returning into the shim
+            let result = (|| {
+                // ignore SIGHUP
+                // safety: safe as ignores the return result of the previous handler
+                unsafe { signal(Signal::SIGHUP, SigHandler::SigIgn) }.map_err(|e| Error::Nix {
+                    msg: "signal",
+                    src: e,
+                })?;
+
+                // unblock the signals the shim waits on, as a blocked mask survives exec
+                SigSet::empty().thread_set_mask().map_err(|e| Error::Nix {
+                    msg: "pthread_sigmask",
+                    src: e,
+                })?;
+
+                // the spawner's procfs is still mounted, so this is the pid it sees
+                let pid = fs::read_link("/proc/self")?;
+                let pid = pid.to_string_lossy();
+
                 debug!("voiding user namespace...");
-                self.void_user_namespace(parent_uid, parent_gid)?; // first to regain full capabilities
+                self.void_user_namespace(parent_uid, parent_gid, maps_ready)?; // first to regain full capabilities
+                debug!("voiding time namespace..."); // needs the spawner's procfs
+                self.void_time_namespace()?;
 
                 debug!("voiding mount namespace...");
                 self.void_mount_namespace()?;
                 debug!("voiding file descriptors..."); // occur after mount to unmount /dev/null
                 self.void_file_descriptors()?;
 
+                if let Some(workdir) = &self.workdir {
+                    debug!("changing working directory to {:?}...", workdir);
+                    std::env::set_current_dir(workdir)
+                        .map_err(|_| Error::BadWorkdir(workdir.clone()))?;
+                }
+
                 debug!("voiding ipc namespace...");
                 self.void_ipc_namespace()?;
                 debug!("voiding uts namespace...");
-                self.void_uts_namespace()?;
+                self.void_uts_namespace(&pid)?;
                 debug!("voiding network namespace...");
                 self.void_network_namespace()?;
                 debug!("voiding pid namespace...");
@@ -122,8 +717,24 @@ impl VoidBuilder {
                 debug!("voiding cgroup namespace...");
                 self.void_cgroup_namespace()?;
 
+                if let Some(keep) = &self.capabilities {
+                    debug!("dropping capabilities...");
+                    self.drop_capabilities(keep)?;
+                }
+
+                // before seccomp, as the filter may not allow the init to fork
+                if self.init && !self.shared.contains(CloneFlags::CLONE_NEWPID) {
+                    debug!("forking init...");
+                    Self::fork_init()?;
+                }
+
+                if let Some(filter) = &seccomp_filter {
+                    debug!("applying seccomp filter...");
+                    seccompiler::apply_filter(filter)?;
+                }
+
                 Ok::<(), Error>(())
-            };
+            })();
 
             if let Err(e) = result {
                 error!("error preparing void: {}", e);
@@ -135,7 +746,195 @@ impl VoidBuilder {
         }
 
         debug!("cloned child: {}", child);
-        Ok(VoidHandle { pid: child })
+
+        // close the write ends, so the read ends see EOF once the void's are closed
+        let (stdout, stderr) = match capture {
+            Some(((stdout, stdout_write), (stderr, stderr_write))) => {
+                drop((stdout_write, stderr_write));
+                (Some(stdout), Some(stderr))
+            }
+            None => (None, None),
+        };
+
+        if let Some((_read, mut write)) = maps_pipe {
+            self.write_id_maps(child, parent_uid, parent_gid)?;
+            write.write_all(&[0])?;
+        }
+
+        let output = stdout.as_ref().map(|_| CapturedOutput::default());
+
+        Ok(VoidHandle {
+            pid: child,
+            pidfd: pidfd.expect("clone fills the requested pidfd"),
+            stdout,
+            stderr,
+            output,
+        })
+    }
+
+    /**
+     * Clone a void into the user and network namespaces of another. An
+     * unprivileged process can only join them from the parent user namespace,
+     * where it owns them, so an intermediate process joins and clones the void
+     * as a sibling. The void remains a child of this process and its pid is
+     * passed back over a pipe.
+     *
+     * Returns 0 in the void, as with clone.
+     */
+    fn clone_joined(owner: RawFd, mut args: CloneArgs) -> Result<Pid> {
+        let (read, write) = pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::Nix {
+            msg: "pipe2",
+            src: e,
+        })?;
+
+        // SAFETY: valid new fds as pipe2(2) returned successfully
+        let (mut read, mut write) = unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) };
+
+        // SAFETY: the shim is single threaded
+        match unsafe { fork() }.map_err(|e| Error::Nix {
+            msg: "fork",
+            src: e,
+        })? {
+            ForkResult::Parent { child } => {
+                drop(write);
+
+                let mut buf = [0_u8; 4];
+                let received = read.read_exact(&mut buf);
+
+                waitpid(child, None).map_err(|e| Error::Nix {
+                    msg: "waitpid",
+                    src: e,
+                })?;
+
+                if received.is_err() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to join network namespace",
+                    )
+                    .into());
+                }
+
+                Ok(Pid::from_raw(i32::from_ne_bytes(buf)))
+            }
+            ForkResult::Child => {
+                drop(read);
+
+                let result = setns(owner, CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNET)
+                    .map_err(|e| Error::Nix {
+                        msg: "setns",
+                        src: e,
+                    })
+                    .and_then(|()| {
+                        // a sibling inherits the SIGCHLD exit signal of this fork
+                        args.flags |= CloneFlags::CLONE_PARENT;
+                        args.exit_signal = None;
+                        clone(args).map_err(clone_error)
+                    });
+
+                match result {
+                    Ok(child) if child == Pid::from_raw(0) => Ok(child),
+                    Ok(child) => {
+                        let code = match write.write_all(&child.as_raw().to_ne_bytes()) {
+                            Ok(()) => exitcode::OK,
+                            Err(_) => exitcode::IOERR,
+                        };
+                        std::process::exit(code)
+                    }
+                    Err(e) => {
+                        error!("error joining network namespace: {}", e);
+                        std::process::exit(exitcode::OSERR)
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+     * Fork so this process stays as the init of the void, reaping every
+     * orphan reparented to it and forwarding SIGTERM and SIGINT to its child.
+     * The init exits with the child's status once the child exits, which
+     * kills anything left in the pid namespace. Returns only in the child.
+     */
+    fn fork_init() -> Result<()> {
+        // SAFETY: the void is single threaded
+        let child = match unsafe { fork() }.map_err(|e| Error::Nix {
+            msg: "fork",
+            src: e,
+        })? {
+            ForkResult::Child => return Ok(()),
+            ForkResult::Parent { child } => child,
+        };
+
+        INIT_CHILD.store(child.as_raw(), Ordering::SeqCst);
+        for sig in [Signal::SIGTERM, Signal::SIGINT] {
+            // SAFETY: the handler only calls kill(2), which is async-signal-safe
+            unsafe { signal(sig, SigHandler::Handler(forward_signal)) }.map_err(|e| {
+                Error::Nix {
+                    msg: "signal",
+                    src: e,
+                }
+            })?;
+        }
+
+        // the child holds the void's fds, so the init keeps none open
+        // SAFETY: nothing else in this process uses the closed fds
+        unsafe {
+            CloseFdsBuilder::new().closefrom(3);
+        }
+
+        loop {
+            match waitpid(None, None) {
+                Ok(WaitStatus::Exited(pid, code)) if pid == child => std::process::exit(code),
+                Ok(WaitStatus::Signaled(pid, sig, _)) if pid == child => {
+                    std::process::exit(128 + sig as i32)
+                }
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(e) => {
+                    error!("init failed to wait: {}", e);
+                    std::process::exit(exitcode::OSERR)
+                }
+            }
+        }
+    }
+
+    /**
+     * Voiding a time namespace sets the offsets of its clocks, which can only
+     * be written before any process enters it. So rather than cloning into a
+     * new one, the void unshares one for its children, writes the offsets and
+     * only then joins it. A void without offsets shares the parent's.
+     */
+    fn void_time_namespace(&self) -> Result<()> {
+        let (monotonic_ms, boottime_ms) = match self.time_offsets {
+            Some(offsets) => offsets,
+            None => return Ok(()),
+        };
+
+        // SAFETY: no pointers are passed
+        Errno::result(unsafe { libc::unshare(libc::CLONE_NEWTIME) }).map_err(|e| Error::Nix {
+            msg: "unshare",
+            src: e,
+        })?;
+
+        // offsets are whole seconds and non-negative nanoseconds, even when negative
+        let offset = |clock: libc::clockid_t, ms: i64| {
+            format!(
+                "{} {} {}\n",
+                clock,
+                ms.div_euclid(1000),
+                ms.rem_euclid(1000) * 1_000_000
+            )
+        };
+        let offsets = offset(libc::CLOCK_MONOTONIC, monotonic_ms)
+            + &offset(libc::CLOCK_BOOTTIME, boottime_ms);
+
+        debug!("offsetting clocks: {}", offsets.trim_end());
+        fs::write("/proc/self/timens_offsets", offsets)?;
+
+        let time = File::open("/proc/self/ns/time_for_children")?;
+        setns(time.as_raw_fd(), CloneFlags::empty()).map_err(|e| Error::Nix {
+            msg: "setns",
+            src: e,
+        })
     }
 
     /**
@@ -150,15 +949,23 @@ impl VoidBuilder {
     /**
      * Voiding a uts namespace requires setting the host and domain names to
      * something specific. A newly created uts namespace has copies of the
-     * parent values for each of these.
+     * parent values for each of these. A shared uts namespace is left as is.
+     * Any `{pid}` in the names is replaced with the void's pid as seen by its
+     * spawner, which is only known once the void exists.
      */
-    fn void_uts_namespace(&self) -> Result<()> {
-        sethostname(self.hostname.as_deref().unwrap_or("void")).map_err(|e| Error::Nix {
+    fn void_uts_namespace(&self, pid: &str) -> Result<()> {
+        if self.shared.contains(CloneFlags::CLONE_NEWUTS) {
+            return Ok(());
+        }
+
+        let hostname = self.hostname.as_deref().unwrap_or("void");
+        sethostname(hostname.replace("{pid}", pid)).map_err(|e| Error::Nix {
             msg: "sethostname",
             src: e,
         })?;
 
-        setdomainname(self.domain_name.as_deref().unwrap_or("(none)")).map_err(|e| Error::Nix {
+        let domain_name = self.domain_name.as_deref().unwrap_or("(none)");
+        setdomainname(domain_name.replace("{pid}", pid)).map_err(|e| Error::Nix {
             msg: "setdomainname",
             src: e,
         })?;
@@ -167,10 +974,36 @@ impl VoidBuilder {
     }
 
     /**
-     * Voiding a network namespace requires no work. A newly created network
-     * namespace contains only a loopback adapter, so is already a void.
+     * Voiding a network namespace requires little work. A newly created network
+     * namespace contains only a loopback adapter, so is already a void. The
+     * adapter starts down, so is brought up if requested to make localhost
+     * usable within the void.
      */
     fn void_network_namespace(&self) -> Result<()> {
+        if self.loopback && self.clone_flags().contains(CloneFlags::CLONE_NEWNET) {
+            trace!("bringing up the loopback interface");
+            interface_up("lo").map_err(|e| Error::Nix {
+                msg: "ioctl",
+                src: e,
+            })?;
+
+            // an IPv4 address on an alias label is added alongside 127.0.0.1
+            // rather than replacing it
+            for (index, (address, prefix)) in self.loopback_addresses.iter().enumerate() {
+                trace!("adding {}/{} to the loopback interface", address, prefix);
+                match address {
+                    IpAddr::V4(address) => {
+                        set_ipv4_address(&format!("lo:{}", index), *address, *prefix)
+                    }
+                    IpAddr::V6(address) => add_ipv6_address("lo", *address, *prefix),
+                }
+                .map_err(|e| Error::Nix {
+                    msg: "ioctl",
+                    src: e,
+                })?;
+            }
+        }
+
         Ok(())
     }
 
@@ -182,6 +1015,44 @@ impl VoidBuilder {
         Ok(())
     }
 
+    /**
+     * Resolve each bind mount's source beneath root, where the parent's root
+     * is found, along with the /dev/null every void is given. A symlink at
+     * the source is followed once, as a bind mount would otherwise fail.
+     */
+    fn resolve_mounts(&self, root: &Path) -> Result<Vec<PlannedMount>> {
+        let standard_dev_null = if self.mounts.contains_key(&PathBuf::from("/dev/null")) {
+            None
+        } else {
+            Some((PathBuf::from("/dev/null"), PathBuf::from("/dev/null")))
+        };
+
+        let mut planned = Vec::with_capacity(self.mounts.len() + 1);
+        for (src, dst) in self
+            .mounts
+            .iter()
+            .chain(standard_dev_null.as_ref().map(|(x, y)| (x, y)))
+        {
+            let mut src = src.clone();
+            let mut src_data =
+                fs::symlink_metadata(root.join(src.strip_prefix("/").unwrap_or(&src)))?;
+
+            if src_data.is_symlink() {
+                src = fs::read_link(root.join(src.strip_prefix("/").unwrap_or(&src)))?;
+                src_data = fs::metadata(root.join(src.strip_prefix("/").unwrap_or(&src)))?;
+            }
+
+            planned.push(PlannedMount {
+                src,
+                dst: dst.clone(),
+                dir: src_data.is_dir(),
+            });
+        }
+
+        planned.sort_by(|a, b| a.dst.cmp(&b.dst));
+        Ok(planned)
+    }
+
     /**
      * Voiding a mount namespace replaces the current root with a new `tmpfs`.
      * This requires pivoting the old root and setting it to private before
@@ -190,12 +1061,15 @@ impl VoidBuilder {
      * unavailable after unmounting the old root.
      */
     fn void_mount_namespace(&self) -> Result<()> {
+        // recursively, as a mount namespace in the parent's user namespace still
+        // shares its mounts' peer groups, and detaching the old root would
+        // otherwise unmount them in the parent too
         trace!("changing the propagation type of the old root to private");
         mount(
             Option::<&str>::None,
             "/",
             Option::<&str>::None,
-            MsFlags::MS_PRIVATE,
+            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
             Option::<&str>::None,
         )
         .map_err(|e| Error::Nix {
@@ -204,26 +1078,42 @@ impl VoidBuilder {
         })?;
 
         trace!("creating tmpdir for new root");
-        let tmp_base = {
-            let env_dir = env::temp_dir();
-            if env_dir.exists() {
-                env_dir
-            } else {
-                debug!("env_dir does not exist, assuming `/` as the base");
+        let tmp_base = env::var_os(TMPDIR_ENV_VAR)
+            .map(PathBuf::from)
+            .into_iter()
+            .chain(std::iter::once(env::temp_dir()))
+            .find(|dir| dir.is_dir())
+            .unwrap_or_else(|| {
+                debug!("no temporary directory exists, assuming `/` as the base");
                 "/".into()
-            }
-        };
-
-        // consume so it does not attempt to delete a folder which no longer exists
-        let new_root = tempfile::tempdir_in(tmp_base)?.into_path();
+            });
+
+        // removed on drop if the root cannot be pivoted into
+        let tmp_dir = tempfile::tempdir_in(tmp_base)?;
+        let new_root = tmp_dir.path().to_owned();
+
+        let options: Vec<String> = self
+            .root_size
+            .map(|size| format!("size={}", size))
+            .into_iter()
+            .chain(
+                self.root_inodes
+                    .map(|inodes| format!("nr_inodes={}", inodes)),
+            )
+            .collect();
+        let options = Some(options.join(",")).filter(|o| !o.is_empty());
 
-        trace!("mounting a new root tmpfs at `{:?}`", &new_root);
+        trace!(
+            "mounting a new root tmpfs at `{:?}` with {:?}",
+            &new_root,
+            options
+        );
         mount(
             Some("tmpfs"),
             &new_root,
             Some("tmpfs"),
             MsFlags::empty(),
-            Option::<&str>::None,
+            options.as_deref(),
         )
         .map_err(|e| Error::Nix {
             msg: "mount",
@@ -240,6 +1130,9 @@ impl VoidBuilder {
             src: e,
         })?;
 
+        // consume so it does not attempt to delete a folder which is now beneath the old root
+        let tmp_dir = tmp_dir.into_path();
+
         let new_root = PathBuf::from("/");
         let old_root = PathBuf::from("/old_root/");
 
@@ -248,33 +1141,23 @@ impl VoidBuilder {
 
         trace!("creating bind mounts before unmounting");
 
-        let standard_dev_null = if self.mounts.contains_key(&PathBuf::from("/dev/null")) {
-            None
-        } else {
-            Some((PathBuf::from("/dev/null"), PathBuf::from("/dev/null")))
+        let resolved;
+        let planned = match &self.mount_plan {
+            Some(plan) => &plan.planned,
+            None => {
+                resolved = self.resolve_mounts(&old_root)?;
+                &resolved
+            }
         };
 
-        for (src, dst) in self
-            .mounts
-            .iter()
-            .chain(standard_dev_null.as_ref().map(|(x, y)| (x, y)))
-        {
-            let mut src = old_root.join(src.strip_prefix("/").unwrap_or(src));
-            let dst = new_root.join(dst.strip_prefix("/").unwrap_or(dst));
+        for bind in planned {
+            let src = old_root.join(bind.src.strip_prefix("/").unwrap_or(&bind.src));
+            let dst = new_root.join(bind.dst.strip_prefix("/").unwrap_or(&bind.dst));
 
             debug!("mounting `{:?}` as `{:?}`", src, dst);
 
             // create the target
-            let mut src_data = fs::symlink_metadata(&src)?;
-
-            if src_data.is_symlink() {
-                let link = fs::read_link(src)?;
-
-                src = old_root.join(link.strip_prefix("/").unwrap_or(&link));
-                src_data = fs::metadata(&src)?;
-            }
-
-            if src_data.is_dir() {
+            if bind.dir {
                 fs::create_dir_all(&dst)?;
             } else {
                 if let Some(parent) = dst.parent() {
@@ -297,15 +1180,19 @@ impl VoidBuilder {
             })?;
         }
 
-        // remount proc
-        if self.remount_proc {
-            debug!("remounting /proc`");
+        // remount bind mounts with flags, which a bind mount ignores
+        for (dst, flags) in &self.mount_flags {
+            let dst = new_root.join(dst.strip_prefix("/").unwrap_or(dst));
+            debug!("remounting `{:?}` with {:?}", dst, flags);
+
+            // flags locked by a less privileged user namespace must be kept
+            let locked = locked_mount_flags(&dst)?;
 
             mount(
-                Some("proc"),
-                "/proc",
-                Some("proc"),
-                MsFlags::empty(),
+                Option::<&str>::None,
+                &dst,
+                Option::<&str>::None,
+                MsFlags::MS_BIND | MsFlags::MS_REMOUNT | *flags | locked,
                 Option::<&str>::None,
             )
             .map_err(|e| Error::Nix {
@@ -314,6 +1201,39 @@ impl VoidBuilder {
             })?;
         }
 
+        // remount proc
+        if self.remount_proc {
+            if self.shared.contains(CloneFlags::CLONE_NEWPID) {
+                // a new proc can only be mounted by the user namespace owning the pid namespace
+                debug!("keeping the bind mounted /proc of the shared pid namespace");
+            } else {
+                debug!("remounting /proc`");
+
+                mount(
+                    Some("proc"),
+                    "/proc",
+                    Some("proc"),
+                    MsFlags::empty(),
+                    Option::<&str>::None,
+                )
+                .map_err(|e| Error::Nix {
+                    msg: "mount",
+                    src: e,
+                })?;
+            }
+
+            if self.mask_proc {
+                Self::mask_proc_entries()?;
+            }
+        }
+
+        // the new root's directory is no longer a mount point once pivoted,
+        // so remove it from the launching namespace while it is reachable
+        let tmp_dir = old_root.join(tmp_dir.strip_prefix("/").unwrap_or(&tmp_dir));
+        if let Err(e) = fs::remove_dir(&tmp_dir) {
+            debug!("failed to remove new root directory {:?}: {}", tmp_dir, e);
+        }
+
         // unmount the old root
         umount2(&old_root, MntFlags::MNT_DETACH).map_err(|e| Error::Nix {
             msg: "umount2",
@@ -326,13 +1246,96 @@ impl VoidBuilder {
         Ok(())
     }
 
+    /**
+     * Mask /proc the way container runtimes do: files that leak host information
+     * or control the kernel are covered with /dev/null, directories with an empty
+     * read-only tmpfs, and kernel tunables are made read-only. Entries missing on
+     * this kernel are skipped.
+     */
+    fn mask_proc_entries() -> Result<()> {
+        debug!("masking /proc");
+
+        for path in PROC_MASKED_PATHS {
+            let path = Path::new(path);
+            let (src, fstype, flags) = match fs::metadata(path) {
+                Ok(m) if m.is_dir() => ("tmpfs", Some("tmpfs"), MsFlags::MS_RDONLY),
+                Ok(_) => ("/dev/null", None, MsFlags::MS_BIND),
+                Err(_) => continue,
+            };
+
+            mount(Some(src), path, fstype, flags, Option::<&str>::None).map_err(|e| {
+                Error::Nix {
+                    msg: "mount",
+                    src: e,
+                }
+            })?;
+        }
+
+        for path in PROC_READONLY_PATHS {
+            let path = Path::new(path);
+            if !path.exists() {
+                continue;
+            }
+
+            for flags in [
+                MsFlags::MS_BIND | MsFlags::MS_REC,
+                MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+            ] {
+                mount(
+                    Some(path),
+                    path,
+                    Option::<&str>::None,
+                    flags,
+                    Option::<&str>::None,
+                )
+                .map_err(|e| Error::Nix {
+                    msg: "mount",
+                    src: e,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
     /**
      * Voiding the user namespace requires writing to two mapping files, and disabling
      * setgid(2). The contents of the mapping files map back to the parent_uid and
      * parent_gid, which must be passed in as they are lost when the new namespace is
-     * created.
+     * created. If ranges of ids are mapped, the parent writes the mapping files instead
+     * and signals maps_ready once they are complete.
      */
-    fn void_user_namespace(&self, parent_uid: Uid, parent_gid: Gid) -> Result<()> {
+    fn void_user_namespace(
+        &self,
+        parent_uid: Uid,
+        parent_gid: Gid,
+        maps_ready: Option<File>,
+    ) -> Result<()> {
+        if self.shared.contains(CloneFlags::CLONE_NEWUSER) {
+            debug!("sharing the user namespace of the parent as root");
+            return Ok(());
+        }
+
+        if !self.clone_flags().contains(CloneFlags::CLONE_NEWUSER) {
+            debug!("joined the user namespace of another void");
+            return Ok(());
+        }
+
+        if let Some(mut maps_ready) = maps_ready {
+            debug!("waiting for the parent to write id maps");
+
+            let mut buf = [0_u8; 1];
+            if maps_ready.read(&mut buf)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "parent failed to write id maps",
+                )
+                .into());
+            }
+
+            return Ok(());
+        }
+
         debug!("mapping root uid to {} in the parent", parent_uid);
         let mut uid_map = fs::OpenOptions::new()
             .read(false)
@@ -372,12 +1375,68 @@ impl VoidBuilder {
         Ok(())
     }
 
+    /**
+     * Mapping ranges of ids requires CAP_SETUID/CAP_SETGID in the parent user namespace,
+     * so must be written from the parent. setgroups(2) must still be denied before writing
+     * the gid map when the parent is unprivileged.
+     */
+    fn write_id_maps(&self, child: Pid, parent_uid: Uid, parent_gid: Gid) -> Result<()> {
+        let uid_map = Self::format_id_map(&self.uid_maps, parent_uid.as_raw());
+        debug!("writing uid map for {}: {:?}", child, uid_map);
+        fs::write(format!("/proc/{}/uid_map", child), uid_map)?;
+
+        if !parent_uid.is_root() {
+            debug!("writing deny to setgroups for {}", child);
+            fs::write(format!("/proc/{}/setgroups", child), "deny\n")?;
+        }
+
+        let gid_map = Self::format_id_map(&self.gid_maps, parent_gid.as_raw());
+        debug!("writing gid map for {}: {:?}", child, gid_map);
+        fs::write(format!("/proc/{}/gid_map", child), gid_map)?;
+
+        Ok(())
+    }
+
+    fn format_id_map(maps: &[IdMap], parent_id: u32) -> String {
+        if maps.is_empty() {
+            format!("0 {} 1\n", parent_id)
+        } else {
+            maps.iter()
+                .map(|m| format!("{} {} {}\n", m.inside, m.outside, m.count))
+                .collect()
+        }
+    }
+
     /**
      * Voiding cgroups involves placing the process into a leaf before creating a
      * cgroup namespace. This ensures the view of the process does not exceed itself.
      */
     fn void_cgroup_namespace(&self) -> Result<()> {
-        // TODO: void cgroup namespace
+        // TODO: void cgroup namespace of voids without a leaf
+        if self.cgroup.is_none() || self.shared.contains(CloneFlags::CLONE_NEWCGROUP) {
+            return Ok(());
+        }
+
+        // the namespace from clone is rooted at the parent's cgroup, so
+        // unshare again now the void is in its leaf
+        unshare(CloneFlags::CLONE_NEWCGROUP).map_err(|e| Error::Nix {
+            msg: "unshare",
+            src: e,
+        })
+    }
+
+    /**
+     * Dropping capabilities from the bounding set leaves the current process able to
+     * finish preparing, but limits the capabilities gained when executing the entrypoint.
+     */
+    fn drop_capabilities(&self, keep: &HashSet<Capability>) -> Result<()> {
+        for cap in caps::read(None, CapSet::Bounding)? {
+            if !keep.contains(&cap) {
+                trace!("dropping capability {}", cap);
+                caps::drop(None, CapSet::Bounding, cap)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -396,7 +1455,21 @@ impl VoidBuilder {
         }
 
         // overwrite stdin/stdout/stderr without closing
-        {
+        if self.tty {
+            trace!("attaching stdfds to the controlling terminal");
+
+            let tty = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/tty")?;
+
+            for stdfd in &[0, 1, 2] {
+                dup2(tty.as_raw_fd(), *stdfd).map_err(|e| Error::Nix {
+                    msg: "dup2",
+                    src: e,
+                })?;
+            }
+        } else {
             let mut nullfd: Option<File> = None;
             for stdfd in &[0, 1, 2] {
                 if !keep.contains(stdfd) {
@@ -442,10 +1515,73 @@ impl VoidBuilder {
             })?;
         }
 
+        // after the loop above, as the redirected files are closed once duplicated
+        for (stdfd, file) in &self.redirects {
+            trace!("redirecting stdfd {} to fd {}", stdfd, file.as_raw_fd());
+
+            if file.as_raw_fd() != *stdfd {
+                dup2(file.as_raw_fd(), *stdfd).map_err(|e| Error::Nix {
+                    msg: "dup2",
+                    src: e,
+                })?;
+                close(file.as_raw_fd()).map_err(|e| Error::Nix {
+                    msg: "close",
+                    src: e,
+                })?;
+            }
+        }
+
         Ok(())
     }
 }
 
+/**
+ * Create a pipe for capturing a void's output, returning its read end, which
+ * is non-blocking so the shim can drain it alongside waiting on signals, and
+ * its write end.
+ */
+fn output_pipe() -> Result<(File, File)> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC).map_err(|e| Error::Nix {
+        msg: "pipe2",
+        src: e,
+    })?;
+
+    // SAFETY: valid new fds as pipe2(2) returned successfully
+    let (read, write) = unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) };
+
+    // only the read end, as the void's writes should block on a full pipe
+    nix::fcntl::fcntl(read.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).map_err(|e| {
+        Error::Nix {
+            msg: "fcntl",
+            src: e,
+        }
+    })?;
+
+    Ok((read, write))
+}
+
+/**
+ * Compile an allowlist of syscall names into a filter which kills the process
+ * on any other syscall.
+ */
+fn compile_seccomp_filter(syscalls: &[String]) -> Result<BpfProgram> {
+    let filter = serde_json::json!({
+        "void": {
+            "mismatch_action": "kill_process",
+            "match_action": "allow",
+            "filter": syscalls
+                .iter()
+                .map(|s| serde_json::json!({ "syscall": s }))
+                .collect::<Vec<_>>(),
+        }
+    });
+
+    let arch = TargetArch::try_from(env::consts::ARCH).map_err(seccompiler::Error::Backend)?;
+    let mut filters = seccompiler::compile_from_json(filter.to_string().as_bytes(), arch)?;
+
+    Ok(filters.remove("void").unwrap_or_default())
+}
+
 pub fn setdomainname<S: AsRef<std::ffi::OsStr>>(name: S) -> nix::Result<()> {
     use std::os::unix::ffi::OsStrExt;
 
@@ -455,3 +1591,188 @@ pub fn setdomainname<S: AsRef<std::ffi::OsStr>>(name: S) -> nix::Result<()> {
     let res = unsafe { libc::setdomainname(ptr, len) };
     nix::Error::result(res).map(drop)
 }
+
+/**
+ * Open a pidfd for a process, which must not yet have been reaped for the
+ * pidfd to be certain to refer to it.
+ */
+fn pidfd_open(pid: Pid) -> Result<File> {
+    // SAFETY: no pointers are passed
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    let fd = Errno::result(fd).map_err(|e| Error::Nix {
+        msg: "pidfd_open",
+        src: e,
+    })?;
+
+    // SAFETY: valid new fd as pidfd_open(2) returned successfully
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+/**
+ * The error for a failed clone. ENOSYS means clone3 is missing, as the kernel
+ * predates 5.3 or a seccomp filter such as a container runtime's denies it,
+ * and the fallback cannot spawn this void. EPERM means creating the
+ * namespaces was denied, for example by a filter or `user.max_user_namespaces`.
+ */
+fn clone_error(e: Errno) -> Error {
+    match e {
+        Errno::ENOSYS | Errno::EPERM => Error::CloneUnavailable(e),
+        _ => Error::Nix {
+            msg: "clone",
+            src: e,
+        },
+    }
+}
+
+/**
+ * The flags of an existing mount that a bind remount must repeat, as an
+ * unprivileged remount cannot clear them.
+ */
+fn locked_mount_flags(path: &Path) -> Result<MsFlags> {
+    let stat = statvfs(path).map_err(|e| Error::Nix {
+        msg: "statvfs",
+        src: e,
+    })?;
+
+    let mut flags = MsFlags::empty();
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_RDONLY, MsFlags::MS_RDONLY),
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        flags.set(ms_flag, stat.flags().contains(fs_flag));
+    }
+
+    // no atime flag means strictatime, but a remount defaults to relatime
+    if !flags.intersects(MsFlags::MS_NOATIME | MsFlags::MS_RELATIME) {
+        flags |= MsFlags::MS_STRICTATIME;
+    }
+
+    Ok(flags)
+}
+
+/// The flags member of `struct ifreq`, padded to the size of the full union
+#[repr(C)]
+struct IfFlagsReq {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    _pad: [u8; 22],
+}
+
+/**
+ * Bring up a network interface of the calling thread's network namespace.
+ */
+pub(crate) fn interface_up(name: &str) -> nix::Result<()> {
+    let socket = nix::sys::socket::socket(
+        nix::sys::socket::AddressFamily::Inet,
+        nix::sys::socket::SockType::Datagram,
+        nix::sys::socket::SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    // SAFETY: valid new fd as socket(2) returned successfully
+    let socket = unsafe { File::from_raw_fd(socket) };
+
+    let mut req = IfFlagsReq {
+        name: [0; libc::IFNAMSIZ],
+        flags: 0,
+        _pad: [0; 22],
+    };
+    for (dst, src) in req.name.iter_mut().zip(name.as_bytes()) {
+        *dst = *src as libc::c_char;
+    }
+
+    let res = unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFFLAGS, &mut req) };
+    nix::Error::result(res)?;
+
+    req.flags |= (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+
+    let res = unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCSIFFLAGS, &req) };
+    nix::Error::result(res).map(drop)
+}
+
+/// The address member of `struct ifreq`, padded to the size of the full union
+#[repr(C)]
+struct IfAddrReq {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    addr: libc::sockaddr_in,
+    _pad: [u8; 8],
+}
+
+/**
+ * Set the IPv4 address and netmask of a network interface of the calling
+ * thread's network namespace.
+ */
+pub(crate) fn set_ipv4_address(name: &str, address: Ipv4Addr, prefix: u8) -> nix::Result<()> {
+    let socket = nix::sys::socket::socket(
+        nix::sys::socket::AddressFamily::Inet,
+        nix::sys::socket::SockType::Datagram,
+        nix::sys::socket::SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    // SAFETY: valid new fd as socket(2) returned successfully
+    let socket = unsafe { File::from_raw_fd(socket) };
+
+    let mask = Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0));
+    for (request, addr) in [(libc::SIOCSIFADDR, address), (libc::SIOCSIFNETMASK, mask)] {
+        // SAFETY: an all zero ifreq is valid
+        let mut req: IfAddrReq = unsafe { mem::zeroed() };
+        for (dst, src) in req.name.iter_mut().zip(name.as_bytes()) {
+            *dst = *src as libc::c_char;
+        }
+        req.addr.sin_family = libc::AF_INET as libc::sa_family_t;
+        req.addr.sin_addr.s_addr = u32::from(addr).to_be();
+
+        let res = unsafe { libc::ioctl(socket.as_raw_fd(), request, &req) };
+        nix::Error::result(res)?;
+    }
+
+    Ok(())
+}
+
+/// `struct in6_ifreq`, adding an IPv6 address to an interface
+#[repr(C)]
+struct In6IfReq {
+    addr: libc::in6_addr,
+    prefix: u32,
+    index: libc::c_int,
+}
+
+/**
+ * Add an IPv6 address to a network interface of the calling thread's network
+ * namespace.
+ */
+fn add_ipv6_address(name: &str, address: Ipv6Addr, prefix: u8) -> nix::Result<()> {
+    let socket = nix::sys::socket::socket(
+        nix::sys::socket::AddressFamily::Inet6,
+        nix::sys::socket::SockType::Datagram,
+        nix::sys::socket::SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    // SAFETY: valid new fd as socket(2) returned successfully
+    let socket = unsafe { File::from_raw_fd(socket) };
+
+    let req = In6IfReq {
+        addr: libc::in6_addr {
+            s6_addr: address.octets(),
+        },
+        prefix: prefix as u32,
+        index: nix::net::if_::if_nametoindex(name)? as libc::c_int,
+    };
+
+    let res = unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCSIFADDR, &req) };
+    nix::Error::result(res).map(drop)
+}
+
+/**
+ * Signal handler of a void's init, passing the signal on to its child.
+ */
+extern "C" fn forward_signal(sig: libc::c_int) {
+    // SAFETY: kill(2) is async-signal-safe
+    unsafe {
+        libc::kill(INIT_CHILD.load(Ordering::SeqCst), sig);
+    }
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::example;
+
+use std::process::Command;
+
+#[test]
+fn aborted_void_fails_shim() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--stdout")
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/abort/spec.json"
+        ))
+        .arg(example("abort"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "aborting\n");
+
+    // killed by a signal, so exits with 128 plus the signal number
+    let code = output.status.code().unwrap();
+    assert!(code > 128, "shim exited with {}", code);
+}
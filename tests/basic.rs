@@ -0,0 +1,48 @@
+mod common;
+
+use common::example;
+
+use void_orchestrator::{Orchestrator, Specification};
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use nix::sys::signal::{SigSet, Signal};
+
+fn main() {
+    let spec = Specification::from_file(
+        Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/basic/spec.json"
+        )),
+        &[],
+    )
+    .unwrap();
+
+    let stdout = std::env::temp_dir().join(format!("void-basic-{}.out", std::process::id()));
+    let _ = fs::remove_file(&stdout);
+
+    // a child of the embedding program, which the shim must leave to it
+    let mut unrelated = Command::new("true").spawn().unwrap();
+
+    let binary = example("basic");
+    let result = Orchestrator::new(spec, &binary)
+        .stdout_file(&stdout)
+        .run()
+        .unwrap();
+
+    let output = fs::read_to_string(&stdout).unwrap();
+    fs::remove_file(&stdout).unwrap();
+
+    assert_eq!(result.exit_code, exitcode::OK, "{}", output);
+
+    // both voids append to the same file, in no particular order
+    let mut lines: Vec<&str> = output.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(lines, ["hello from main1!", "hello from main2!"]);
+
+    assert!(unrelated.wait().unwrap().success());
+    let mask = SigSet::thread_get_mask().unwrap();
+    assert!(!mask.contains(Signal::SIGCHLD));
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::example;
+
+use std::process::Command;
+
+const CAP_NET_BIND_SERVICE: u32 = 10;
+
+#[test]
+fn bounding_set_holds_only_kept_capabilities() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/capabilities/spec.json"
+        ))
+        .arg(example("capabilities"))
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let bounding = u64::from_str_radix(stdout.trim(), 16).unwrap();
+    assert_eq!(bounding, 1 << CAP_NET_BIND_SERVICE, "CapBnd: {}", stdout);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::example;
+
+use void_orchestrator::{Orchestrator, RunResult, Specification};
+
+use std::path::Path;
+
+use nix::unistd::Pid;
+
+fn main() {
+    captures_both_streams();
+    captures_triggered_voids();
+}
+
+fn run(name: &str) -> RunResult {
+    let spec = Specification::from_file(
+        &Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("examples")
+            .join(name)
+            .join("spec.json"),
+        &[],
+    )
+    .unwrap();
+
+    let binary = example(name);
+    let result = Orchestrator::new(spec, &binary)
+        .capture_output()
+        .run()
+        .unwrap();
+
+    assert_eq!(result.exit_code, exitcode::OK);
+    result
+}
+
+/// The pid of the only void spawned for an entrypoint
+fn spawned(result: &RunResult, entrypoint: &str) -> Pid {
+    let mut pids = result
+        .spawned
+        .iter()
+        .filter(|(_, name)| name == entrypoint)
+        .map(|(pid, _)| *pid);
+
+    let pid = pids.next().unwrap();
+    assert_eq!(pids.next(), None);
+    pid
+}
+
+fn captures_both_streams() {
+    let result = run("output");
+
+    // captured even though the entrypoint doesn't forward either stream
+    let output = &result.output[&spawned(&result, "output")];
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "to stdout\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "to stderr\n");
+}
+
+fn captures_triggered_voids() {
+    let result = run("pipes");
+
+    let sender = &result.output[&spawned(&result, "pipe_sender")];
+    assert_eq!(
+        String::from_utf8_lossy(&sender.stdout),
+        "hello from pipe_sender!\n"
+    );
+
+    // each receiver writes to the output of the trigger's void
+    let receiver = &result.output[&spawned(&result, "pipe_receiver")];
+    let stdout = String::from_utf8_lossy(&receiver.stdout);
+    assert_eq!(stdout.matches("received data: ").count(), 3, "{}", stdout);
+    assert!(stdout.contains("received data: some data\n"), "{}", stdout);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::example;
+
+use std::collections::BTreeMap;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output};
+
+use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+
+/// Run the isolation example, with clone3 denied as a container runtime would if deny is set
+fn run_isolation(deny_clone3: bool) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"));
+    command
+        .arg("--stdout")
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/isolation/spec.json"
+        ))
+        .arg(example("isolation"));
+
+    if deny_clone3 {
+        let filter: BpfProgram = SeccompFilter::new(
+            BTreeMap::from([(libc::SYS_clone3, vec![])]),
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::ENOSYS as u32),
+            std::env::consts::ARCH.try_into().unwrap(),
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+        // SAFETY: only installs the already compiled filter between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                seccompiler::apply_filter(&filter)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            });
+        }
+    }
+
+    command.output().unwrap()
+}
+
+#[test]
+fn clone_fallback_spawns_an_equivalent_void() {
+    let clone3 = run_isolation(false);
+    let fallback = run_isolation(true);
+
+    for output in [&clone3, &fallback] {
+        assert!(
+            output.status.success(),
+            "shim failed: {}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let stdout = String::from_utf8_lossy(&clone3.stdout);
+    assert!(
+        stdout.starts_with("pid 1 uid 0 gid 0 hostname void "),
+        "{}",
+        stdout
+    );
+    assert_eq!(stdout, String::from_utf8_lossy(&fallback.stdout));
+
+    let stderr = String::from_utf8_lossy(&fallback.stderr);
+    assert!(stderr.contains("falling back to clone"), "{}", stderr);
+}
@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+/// The path of a built example, which sits beside the test binaries
+pub fn example(name: &str) -> PathBuf {
+    let mut dir = std::env::current_exe().unwrap();
+    dir.pop();
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+
+    dir.join("examples").join(name)
+}
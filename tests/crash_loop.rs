@@ -0,0 +1,35 @@
+mod common;
+
+use common::example;
+
+use std::process::Command;
+
+#[test]
+fn crash_loop_gives_up_after_max_restarts() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--stdout")
+        .arg("--max-restarts")
+        .arg("4")
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/crash_loop/spec.json"
+        ))
+        .arg(example("crash_loop"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let count = |name| {
+        stdout
+            .lines()
+            .filter(|l| *l == format!("{} crashing", name))
+            .count()
+    };
+
+    // the first run plus each restart, with the specification overriding the flag
+    assert_eq!(count("limited"), 3, "{}", stdout);
+    assert_eq!(count("unlimited"), 5, "{}", stdout);
+
+    assert_eq!(output.status.code(), Some(3));
+}
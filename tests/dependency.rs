@@ -0,0 +1,41 @@
+mod common;
+
+use common::example;
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn failed_dependency_shuts_down_spawned_voids() {
+    let mut shim = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/dependency/spec.json"
+        ))
+        .arg(example("dependency"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // every void holds the shim's stdout, so it only reads to EOF once all have exited
+    let mut stdout = shim.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).unwrap();
+        tx.send(output).unwrap();
+    });
+
+    assert!(!shim.wait().unwrap().success());
+
+    // the cache only exits by itself after a minute
+    let output = rx
+        .recv_timeout(Duration::from_secs(30))
+        .expect("a void outlived the shim");
+    assert_eq!(output, "database exiting before it is ready\n");
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use common::example;
+
+use void_orchestrator::{Orchestrator, Specification};
+
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let spec = Specification::from_file(
+        Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/fib/spec.json"
+        )),
+        &[],
+    )
+    .unwrap();
+
+    let stdout = std::env::temp_dir().join(format!("void-fib-{}.out", std::process::id()));
+    let _ = fs::remove_file(&stdout);
+
+    let binary = example("fib");
+    let result = Orchestrator::new(spec, &binary)
+        .stdout_file(&stdout)
+        .run()
+        .unwrap();
+
+    let output = fs::read_to_string(&stdout).unwrap();
+    fs::remove_file(&stdout).unwrap();
+
+    assert_eq!(result.exit_code, exitcode::OK, "{}", output);
+    assert_eq!(output, "fib(1) = 1\nfib(7) = 13\nfib(19) = 4181\n");
+}
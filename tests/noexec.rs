@@ -0,0 +1,29 @@
+mod common;
+
+use common::example;
+
+use std::process::Command;
+
+#[test]
+fn noexec_mount_refuses_exec() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--stdout")
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/noexec/spec.json"
+        ))
+        .arg(example("noexec"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert_eq!(stdout, format!("exec errno {}\n", libc::EACCES));
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::example;
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+/// Ignored by default, as it needs root on a host using cgroup v2. Run it with
+/// `cargo test --test oom -- --ignored`.
+#[test]
+#[ignore]
+fn void_exceeding_memory_max_is_killed() {
+    // the shim needs a cgroup to itself, so gets a new child of the root
+    let cgroup =
+        Path::new("/sys/fs/cgroup").join(format!("void-orchestrator-oom-{}", std::process::id()));
+    fs::create_dir(&cgroup).expect("creating a cgroup needs root and cgroup v2");
+    let procs = OpenOptions::new()
+        .write(true)
+        .open(cgroup.join("cgroup.procs"))
+        .unwrap();
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"));
+    command
+        .arg("--stdout")
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/oom/spec.json"
+        ))
+        .arg(example("oom"));
+
+    // SAFETY: write(2) is async-signal-safe, so may run between fork and exec
+    let fd = procs.as_raw_fd();
+    unsafe {
+        command.pre_exec(move || {
+            // "0" moves the writing process, which becomes the shim
+            if libc::write(fd, b"0".as_ptr().cast(), 1) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let output = command.output().unwrap();
+    drop(procs);
+
+    // the shim removes the cgroups it created before exiting
+    let leftover: Vec<_> = fs::read_dir(&cgroup)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().unwrap().is_dir())
+        .map(|e| e.file_name())
+        .collect();
+    fs::remove_dir(&cgroup).unwrap();
+    assert!(leftover.is_empty(), "left behind {:?}", leftover);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout,
+        format!("allocating {} bytes\n", 256 << 20),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // killed by the OOM killer with SIGKILL, leaving the shim running
+    assert_eq!(output.status.code(), Some(128 + libc::SIGKILL));
+}
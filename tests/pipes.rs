@@ -0,0 +1,46 @@
+mod common;
+
+use common::example;
+
+use std::process::Command;
+
+#[test]
+fn pipe_trigger_delivers_data() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--stdout")
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/pipes/spec.json"
+        ))
+        .arg(example("pipes"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert!(stdout.contains("hello from pipe_sender!\n"), "{}", stdout);
+
+    // each message is one packet on the O_DIRECT pipe, so spawns one receiver
+    assert_eq!(stdout.matches("received data: ").count(), 3, "{}", stdout);
+    assert!(stdout.contains("received data: some data\n"), "{}", stdout);
+    assert!(
+        stdout.contains("received data: some more data\n"),
+        "{}",
+        stdout
+    );
+
+    // a 4KB message must arrive whole rather than truncated to a smaller buffer
+    let large = "0123456789abcdef".repeat(256);
+    assert!(
+        stdout.contains(&format!("received data: {}\n", large)),
+        "{}",
+        stdout
+    );
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::example;
+
+use std::fs;
+use std::net::TcpListener;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output};
+
+const CAP_NET_BIND_SERVICE: libc::c_ulong = 10;
+
+fn run_shim(drop_capability: bool) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"));
+    command
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/privileged_port/spec.json"
+        ))
+        .arg(example("privileged_port"));
+
+    if drop_capability {
+        // SAFETY: prctl is async-signal-safe. Dropping the capability from the
+        // bounding set removes it from the shim when executed, even as root.
+        // Without CAP_SETPCAP the test runner never had it to drop.
+        unsafe {
+            command.pre_exec(|| {
+                libc::prctl(libc::PR_CAPBSET_DROP, CAP_NET_BIND_SERVICE, 0, 0, 0);
+                Ok(())
+            });
+        }
+    }
+
+    command.output().unwrap()
+}
+
+/// Ignored by default, as binding port 80 needs CAP_NET_BIND_SERVICE. Run it
+/// as root with `cargo test --test privileged_port -- --ignored`.
+#[test]
+#[ignore]
+fn binds_privileged_port_with_capability() {
+    // bind first to check the test runner has the capability and the port is free
+    if let Err(e) = TcpListener::bind("127.0.0.1:80") {
+        panic!("port 80 cannot be bound by the test runner: {}", e);
+    }
+
+    let output = run_shim(false);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert_eq!(stdout, "listening on 127.0.0.1:80\n");
+}
+
+/// Ignored by default, as container runtimes commonly make every port
+/// unprivileged. Run it with `cargo test --test privileged_port -- --ignored`
+/// on a host where `ip_unprivileged_port_start` is above 80.
+#[test]
+#[ignore]
+fn privileged_port_fails_cleanly_without_capability() {
+    let start = fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+        .ok()
+        .and_then(|s| s.trim().parse::<u16>().ok())
+        .unwrap_or(1024);
+    assert!(start > 80, "port 80 is unprivileged on this host");
+
+    let output = run_shim(true);
+
+    // the shim fails before spawning the void, so nothing is printed
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(
+        stderr.contains("binding 127.0.0.1:80 needs CAP_NET_BIND_SERVICE"),
+        "unexpected error: {}",
+        stderr
+    );
+}
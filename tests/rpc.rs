@@ -0,0 +1,253 @@
+mod common;
+
+use common::example;
+
+use std::net::TcpListener;
+use std::os::unix::fs::symlink;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn denied_rpc_returns_errno() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--stdout")
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/rpc/spec.json"
+        ))
+        .arg(example("rpc"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // RpcResultTag::OperationNotPermitted with EPERM
+    let expected = format!("rpc result: tag 2 errno {}\n", libc::EPERM);
+    assert_eq!(stdout, expected);
+}
+
+/// Run the rpc_connect example with a specification permitting a single
+/// OpenTcpSocket, returning its output
+fn connect(
+    family: Option<&str>,
+    port: Option<u16>,
+    host: Option<&str>,
+    request: &[&str],
+) -> String {
+    let rpc = serde_json::json!({
+        "OpenTcpSocket": {
+            "family": family,
+            "port": port,
+            "host": host,
+        }
+    });
+    run_connect(rpc, request)
+}
+
+/// Run the rpc_connect example with a specification permitting only the
+/// given rpc, returning its output
+fn run_connect(rpc: serde_json::Value, request: &[&str]) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = dir.path().join("spec.json");
+
+    let mut json: serde_json::Value = serde_json::from_str(include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/examples/rpc_connect/spec.json"
+    )))
+    .unwrap();
+    json["entrypoints"]["connect"]["args"][1]["Rpc"][0] = rpc;
+    std::fs::write(&spec, json.to_string()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--stdout")
+        .arg("--specification")
+        .arg(&spec)
+        .arg(example("rpc_connect"))
+        .args(request)
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn open_tcp_socket_matches_each_field() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let port_arg = port.to_string();
+
+    let success = "rpc result: tag 0 errno 0\n";
+    let denied = format!("rpc result: tag 2 errno {}\n", libc::EPERM);
+
+    // every combination of fields, where None permits anything
+    for family in [None, Some("Inet")] {
+        for permitted_port in [None, Some(port)] {
+            for host in [None, Some("127.0.0.1")] {
+                let out = connect(
+                    family,
+                    permitted_port,
+                    host,
+                    &["inet", "127.0.0.1", &port_arg],
+                );
+                assert_eq!(out, success, "{:?} {:?} {:?}", family, permitted_port, host);
+            }
+        }
+    }
+
+    // a mismatch in any specified field is denied
+    let other_port = (port + 1).to_string();
+    let cases = [
+        (Some("Inet6"), None, None, ["inet", "127.0.0.1", &port_arg]),
+        (None, Some(port), None, ["inet", "127.0.0.1", &other_port]),
+        (
+            None,
+            None,
+            Some("127.0.0.2"),
+            ["inet", "127.0.0.1", &port_arg],
+        ),
+    ];
+    for (family, permitted_port, host, request) in cases {
+        let out = connect(family, permitted_port, host, &request);
+        assert_eq!(out, denied, "{:?} {:?} {:?}", family, permitted_port, host);
+    }
+}
+
+#[test]
+fn open_tcp_socket_accepts_bracketed_ipv6() {
+    let listener = TcpListener::bind("[::1]:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let port_arg = port.to_string();
+
+    let success = "rpc result: tag 0 errno 0\n";
+    let denied = format!("rpc result: tag 2 errno {}\n", libc::EPERM);
+
+    // addresses compare by value, with or without brackets on either side
+    for permitted in ["::1", "[::1]"] {
+        for requested in ["::1", "[::1]"] {
+            let out = connect(
+                Some("Inet6"),
+                Some(port),
+                Some(permitted),
+                &["inet6", requested, &port_arg],
+            );
+            assert_eq!(out, success, "{} {}", permitted, requested);
+        }
+    }
+
+    let out = connect(
+        Some("Inet6"),
+        Some(port),
+        Some("[::2]"),
+        &["inet6", "[::1]", &port_arg],
+    );
+    assert_eq!(out, denied);
+}
+
+#[test]
+fn open_tcp_socket_rejects_malformed_hosts() {
+    let invalid = format!("rpc result: tag 1 errno {}\n", libc::EINVAL);
+
+    // the port is a field of its own, and an address must match the family
+    for request in [
+        ["inet", "127.0.0.1:80", "80"],
+        ["inet", "::1", "80"],
+        ["inet6", "127.0.0.1", "80"],
+        ["inet6", "[127.0.0.1]", "80"],
+        ["inet", "127.0.0.1", "0"],
+    ] {
+        assert_eq!(
+            connect(None, None, None, &request),
+            invalid,
+            "{:?}",
+            request
+        );
+    }
+}
+
+#[test]
+fn open_unix_socket_matches_the_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("socket");
+    let _listener = UnixListener::bind(&path).unwrap();
+    let path = path.to_str().unwrap();
+
+    let success = "rpc result: tag 0 errno 0\n";
+    let denied = format!("rpc result: tag 2 errno {}\n", libc::EPERM);
+
+    for permitted in [None, Some(path)] {
+        let rpc = serde_json::json!({ "OpenUnixSocket": { "path": permitted } });
+        assert_eq!(
+            run_connect(rpc, &["unix", path]),
+            success,
+            "{:?}",
+            permitted
+        );
+    }
+
+    let other = format!("{}2", path);
+    let rpc = serde_json::json!({ "OpenUnixSocket": { "path": other } });
+    assert_eq!(run_connect(rpc, &["unix", path]), denied);
+}
+
+#[test]
+fn open_unix_socket_rejects_overlong_paths() {
+    // longer than the 108 bytes of sun_path
+    let path = format!("/{}", "a".repeat(200));
+
+    let rpc = serde_json::json!({ "OpenUnixSocket": { "path": null } });
+    let expected = format!("rpc result: tag 3 errno {}\n", libc::ENAMETOOLONG);
+    assert_eq!(run_connect(rpc, &["unix", &path]), expected);
+}
+
+#[test]
+fn open_file_stays_beneath_the_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = dir.path().join("data");
+    let sibling = dir.path().join("data2");
+    std::fs::create_dir(&data).unwrap();
+    std::fs::create_dir(&sibling).unwrap();
+    std::fs::write(data.join("file"), "").unwrap();
+    std::fs::write(sibling.join("file"), "").unwrap();
+    symlink(sibling.join("file"), data.join("link")).unwrap();
+
+    let rpc = serde_json::json!({
+        "OpenFile": {
+            "path_prefix": data,
+            "write": false,
+        }
+    });
+    let open =
+        |mode: &str, path: &Path| run_connect(rpc.clone(), &["file", mode, path.to_str().unwrap()]);
+
+    assert_eq!(
+        open("read", &data.join("file")),
+        "rpc result: tag 0 errno 0\n"
+    );
+
+    // the prefix matches whole components, and writing must be permitted
+    let denied = format!("rpc result: tag 2 errno {}\n", libc::EPERM);
+    assert_eq!(open("read", &sibling.join("file")), denied);
+    assert_eq!(open("write", &data.join("file")), denied);
+
+    let invalid = format!("rpc result: tag 1 errno {}\n", libc::EINVAL);
+    assert_eq!(open("read", &data.join("../data2/file")), invalid);
+
+    // symlinks are never followed, even beneath the prefix
+    let refused = format!("rpc result: tag 3 errno {}\n", libc::ELOOP);
+    assert_eq!(open("read", &data.join("link")), refused);
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::example;
+
+use std::process::Command;
+
+#[test]
+fn forbidden_syscall_kills_void() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/seccomp/spec.json"
+        ))
+        .arg(example("seccomp"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stdout, "calling getppid\n", "{}", stderr);
+
+    // killed by SIGSYS, so exits with 128 plus its number
+    assert_eq!(output.status.code(), Some(128 + libc::SIGSYS), "{}", stderr);
+    assert!(
+        stderr.contains("was terminated with signal SIGSYS"),
+        "{}",
+        stderr
+    );
+}
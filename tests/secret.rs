@@ -0,0 +1,30 @@
+mod common;
+
+use common::example;
+
+use std::process::Command;
+
+#[test]
+fn secret_is_passed_as_sealed_memfd() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg("--stdout")
+        .arg("--specification")
+        .arg("examples/secret/spec.json")
+        .arg(example("secret"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert_eq!(
+        stdout,
+        format!("read hunter2\nwrite failed with errno {}\n", libc::EPERM)
+    );
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::example;
+
+use std::io::{BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn file_socket_handlers_run_concurrently() {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"));
+    command
+        .arg("--stdout")
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/sockets/spec.json"
+        ))
+        .arg(example("sockets"))
+        .stdout(Stdio::piped());
+
+    // SAFETY: setpgid(2) is async-signal-safe, so may run between fork and exec
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut shim = command.spawn().unwrap();
+
+    let stdout = shim.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            tx.send(line.unwrap()).unwrap();
+        }
+    });
+
+    // each handler blocks until the other has run, so run one at a time neither finishes
+    let handled: Vec<String> = (0..2)
+        .map_while(|_| rx.recv_timeout(Duration::from_secs(10)).ok())
+        .collect();
+
+    // the trigger never sees the socket close, so kill the shim and every void
+    // in its process group, which takes each pid namespace down with its init
+    unsafe { libc::kill(-(shim.id() as i32), libc::SIGKILL) };
+    shim.wait().unwrap();
+
+    assert_eq!(handled.len(), 2, "handled: {:?}", handled);
+    for line in handled {
+        assert!(line.starts_with("handled connection"), "{}", line);
+    }
+}
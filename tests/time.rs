@@ -0,0 +1,54 @@
+mod common;
+
+use common::example;
+
+use std::path::Path;
+use std::process::Command;
+
+use nix::time::{clock_gettime, ClockId};
+
+const DAY: i64 = 24 * 60 * 60;
+
+#[test]
+fn time_namespace_offsets_clocks() {
+    assert!(
+        Path::new("/proc/self/ns/time").exists(),
+        "time namespaces need Linux 5.6 or later"
+    );
+
+    let before = clock_gettime(ClockId::CLOCK_MONOTONIC).unwrap().tv_sec();
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/time/spec.json"
+        ))
+        .arg(example("time"))
+        .output()
+        .unwrap();
+    let after = clock_gettime(ClockId::CLOCK_MONOTONIC).unwrap().tv_sec();
+
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let clocks: Vec<i64> = stdout
+        .split_whitespace()
+        .map(|c| c.parse().unwrap())
+        .collect();
+
+    // offset by one day and two days, from a clock read between before and after
+    let monotonic = clocks[0] - DAY;
+    assert!(
+        (before..=after).contains(&monotonic),
+        "{} not offset from {}..={}",
+        clocks[0],
+        before,
+        after
+    );
+    assert!(clocks[1] - clocks[0] >= DAY, "{}", stdout);
+}
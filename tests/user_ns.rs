@@ -0,0 +1,41 @@
+mod common;
+
+use common::example;
+
+use std::process::Command;
+
+/// Ignored by default, as sharing the user namespace needs the shim to run as
+/// root. Run it as root with `cargo test --test user_ns -- --ignored`.
+#[test]
+#[ignore]
+fn voided_and_shared_user_namespaces_both_run() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/user_ns/spec.json"
+        ))
+        .arg(example("user_ns"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        stderr
+    );
+
+    // the voids run concurrently, so their lines may interleave in either order
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(
+        lines,
+        ["shared maps 0 0 4294967295", "voided maps 0 0 1"],
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use common::example;
+
+use std::process::Command;
+
+/// Ignored by default, as creating a link needs privileges in the shim's own
+/// namespaces. Run it as root with `cargo test --test veth -- --ignored`.
+#[test]
+#[ignore]
+fn veth_joins_named_network_namespaces() {
+    let output = Command::new(env!("CARGO_BIN_EXE_void-orchestrator"))
+        .arg("--specification")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/veth/spec.json"
+        ))
+        .arg(example("veth"))
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(
+        output.status.success(),
+        "shim failed: {}\n{}",
+        output.status,
+        stderr
+    );
+    assert_eq!(stdout, "received hello from 10.0.0.1\n");
+}